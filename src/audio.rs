@@ -0,0 +1,509 @@
+use crate::settings;
+use crate::music;
+use crate::events::GameEvent;
+
+use anyhow::{Context, Result};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/*
+Sound effects are synthesised when the game starts, from the same square, triangle and noise
+channels an old console has, so there are no sample files to ship. They're mixed in mono and
+copied to every output channel. Music comes from a sequencer the mixer runs alongside them.
+
+The output is the default audio device when built with the audio feature and one can be opened.
+Otherwise it's the null backend, which mixes into a buffer as the game ticks so voices still
+play out and finish, and which render() can be read back from without any hardware.
+*/
+
+pub const SAMPLE_RATE: u32 = 44_100;
+//Oldest voices are cut off past this
+pub const MAX_VOICES: usize = 16;
+//One 16 ms game tick of audio
+const FRAMES_PER_TICK: usize = (SAMPLE_RATE as usize * 16) / 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Sound {
+    Move,
+    Rotate,
+    Lock,
+    HardDrop,
+    //Lines cleared at once, 1 to 4
+    LineClear(u8),
+    TSpin,
+    //Clears in a row past the first, 1 to COMBO_TIERS
+    Combo(u8),
+    Hold,
+    LevelUp,
+    GameOver,
+}
+
+pub const COMBO_TIERS: u8 = 4;
+
+impl Sound {
+
+    pub fn all() -> Vec<Sound> {
+        let mut sounds = vec![Sound::Move, Sound::Rotate, Sound::Lock, Sound::HardDrop, Sound::TSpin, Sound::Hold, Sound::LevelUp, Sound::GameOver];
+        sounds.extend((1..=4).map(Sound::LineClear));
+        sounds.extend((1..=COMBO_TIERS).map(Sound::Combo));
+        sounds
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Wave {
+    Square,
+    Triangle,
+    Noise,
+}
+
+//One chip channel. Noise is the 15 bit shift register the NES uses, clocked at the frequency asked for.
+#[derive(Debug, Clone)]
+pub struct Oscillator {
+    pub wave: Wave,
+    phase: f32,
+    lfsr: u16,
+}
+
+impl Oscillator {
+
+    pub fn new(wave: Wave) -> Oscillator {
+        Oscillator { wave, phase: 0.0, lfsr: 1 }
+    }
+
+    //The next sample, -1 to 1
+    pub fn next(&mut self, freq: f32) -> f32 {
+        let sample = match self.wave {
+            Wave::Square => if self.phase < 0.5 { 1.0 } else { -1.0 },
+            Wave::Triangle => 4.0 * (self.phase - 0.5).abs() - 1.0,
+            Wave::Noise => if self.lfsr & 1 == 0 { 1.0 } else { -1.0 },
+        };
+        self.phase += freq / SAMPLE_RATE as f32;
+        while self.phase >= 1.0 {
+            self.phase -= 1.0;
+            let bit = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+            self.lfsr = (self.lfsr >> 1) | (bit << 14);
+        }
+        sample
+    }
+}
+
+//A tone gliding from one frequency to another, fading out over its length
+pub fn sweep(wave: Wave, from: f32, to: f32, seconds: f32, volume: f32) -> Vec<f32> {
+    let length = (seconds * SAMPLE_RATE as f32) as usize;
+    let mut osc = Oscillator::new(wave);
+    (0..length).map(|i| {
+        let t = i as f32 / length as f32;
+        //A few ms of attack so it doesn't click
+        let attack = (i as f32 / 100.0).min(1.0);
+        osc.next(from + (to - from) * t) * volume * attack * (1.0 - t)
+    }).collect()
+}
+
+//Notes one after another
+pub fn notes(wave: Wave, freqs: &[f32], seconds_each: f32, volume: f32) -> Vec<f32> {
+    freqs.iter().flat_map(|f| sweep(wave, *f, *f, seconds_each, volume)).collect()
+}
+
+//Plays the sounds over each other from the start
+pub fn layer(sounds: &[Vec<f32>]) -> Vec<f32> {
+    let length = sounds.iter().map(|s| s.len()).max().unwrap_or(0);
+    (0..length).map(|i| sounds.iter().map(|s| s.get(i).copied().unwrap_or(0.0)).sum()).collect()
+}
+
+//Equal tempered, semitones up from A 440
+pub fn note(semitones: i32) -> f32 {
+    440.0 * 2f32.powf(semitones as f32 / 12.0)
+}
+
+pub fn synthesise(sound: Sound) -> Vec<f32> {
+    match sound {
+        Sound::Move => sweep(Wave::Square, 880.0, 880.0, 0.025, 0.08),
+        Sound::Rotate => sweep(Wave::Triangle, 660.0, 990.0, 0.04, 0.25),
+        Sound::Lock => layer(&[sweep(Wave::Noise, 4000.0, 2000.0, 0.04, 0.12), sweep(Wave::Square, 220.0, 180.0, 0.04, 0.1)]),
+        Sound::HardDrop => layer(&[sweep(Wave::Noise, 6000.0, 800.0, 0.09, 0.2), sweep(Wave::Square, 180.0, 60.0, 0.09, 0.15)]),
+        //More lines, more notes, a tetris goes up the octave
+        Sound::LineClear(lines) => {
+            let steps: &[i32] = match lines {
+                1 => &[3, 7],
+                2 => &[3, 7, 10],
+                3 => &[3, 7, 10, 15],
+                _ => &[3, 7, 10, 15, 19, 22, 27],
+            };
+            let freqs: Vec<f32> = steps.iter().map(|s| note(*s)).collect();
+            notes(Wave::Square, &freqs, 0.05, 0.15)
+        },
+        Sound::TSpin => layer(&[sweep(Wave::Triangle, 300.0, 1200.0, 0.15, 0.3), sweep(Wave::Noise, 8000.0, 8000.0, 0.1, 0.05)]),
+        //Each tier a whole tone higher
+        Sound::Combo(tier) => notes(Wave::Square, &[note(2 * tier as i32), note(2 * tier as i32 + 7)], 0.05, 0.12),
+        Sound::Hold => sweep(Wave::Triangle, 440.0, 330.0, 0.06, 0.25),
+        Sound::LevelUp => notes(Wave::Square, &[note(3), note(7), note(10), note(15)], 0.08, 0.15),
+        Sound::GameOver => layer(&[sweep(Wave::Square, 440.0, 110.0, 0.8, 0.15), sweep(Wave::Triangle, 220.0, 55.0, 0.8, 0.25)]),
+    }
+}
+
+pub struct Voice {
+    samples: Arc<Vec<f32>>,
+    //Fractional so sounds made at SAMPLE_RATE play at the right pitch on any device rate
+    pos: f32,
+    gain: f32,
+}
+
+pub struct Mixer {
+    voices: Vec<Voice>,
+    //Output frames a second, and input samples per output frame
+    rate: u32,
+    step: f32,
+    pub music: Option<music::Sequencer>,
+    pub master_volume: f32,
+    pub music_volume: f32,
+}
+
+impl Mixer {
+
+    pub fn new(output_rate: u32) -> Mixer {
+        Mixer { voices: Vec::new(), rate: output_rate, step: SAMPLE_RATE as f32 / output_rate as f32, music: None, master_volume: 1.0, music_volume: 1.0 }
+    }
+
+    pub fn play(&mut self, samples: Arc<Vec<f32>>, gain: f32) {
+        if self.voices.len() >= MAX_VOICES {
+            self.voices.remove(0);
+        }
+        self.voices.push(Voice { samples, pos: 0.0, gain });
+    }
+
+    pub fn voices(&self) -> usize {
+        self.voices.len()
+    }
+
+    //Fills out with the next frames, each sample copied to every channel
+    pub fn mix(&mut self, out: &mut [f32], channels: usize) {
+        let channels = channels.max(1);
+        for frame in out.chunks_mut(channels) {
+            let mut sample = 0.0;
+            for voice in self.voices.iter_mut() {
+                if let Some(s) = voice.samples.get(voice.pos as usize) {
+                    sample += s * voice.gain;
+                }
+                voice.pos += self.step;
+            }
+            if let Some(music) = self.music.as_mut() {
+                sample += music.next_sample() * self.music_volume;
+            }
+            let sample = (sample * self.master_volume).clamp(-1.0, 1.0);
+            for s in frame.iter_mut() {
+                *s = sample;
+            }
+        }
+        self.voices.retain(|v| (v.pos as usize) < v.samples.len());
+    }
+}
+
+pub enum Output {
+    //What was mixed most recently, mono at SAMPLE_RATE
+    Null(Vec<f32>),
+    #[cfg(feature = "audio")]
+    Device(cpal::Stream),
+}
+
+pub struct Audio {
+    mixer: Arc<Mutex<Mixer>>,
+    bank: HashMap<Sound, Arc<Vec<f32>>>,
+    pub effects_volume: f32,
+    pub output: Output,
+}
+
+impl Audio {
+
+    //The default device if there is one, otherwise the null backend
+    pub fn new(settings: &settings::Settings) -> Audio {
+        #[cfg(feature = "audio")]
+        {
+            match device::open() {
+                Ok((mixer, stream)) => {
+                    let mut audio = Audio::with_output(mixer, Output::Device(stream));
+                    audio.set_volume(settings);
+                    return audio;
+                },
+                Err(e) => println!("No sound: {:#}", e),
+            }
+        }
+        let mut audio = Audio::null();
+        audio.set_volume(settings);
+        audio
+    }
+
+    pub fn null() -> Audio {
+        Audio::with_output(Arc::new(Mutex::new(Mixer::new(SAMPLE_RATE))), Output::Null(Vec::new()))
+    }
+
+    fn with_output(mixer: Arc<Mutex<Mixer>>, output: Output) -> Audio {
+        let bank = Sound::all().into_iter().map(|s| (s, Arc::new(synthesise(s)))).collect();
+        Audio { mixer, bank, effects_volume: 1.0, output }
+    }
+
+    pub fn set_volume(&mut self, settings: &settings::Settings) {
+        self.effects_volume = settings.effects_volume;
+        if let Ok(mut mixer) = self.mixer.lock() {
+            mixer.master_volume = settings.master_volume;
+            mixer.music_volume = settings.music_volume;
+        }
+    }
+
+    pub fn play(&mut self, sound: Sound) {
+        let samples = match self.bank.get(&sound) {
+            Some(s) => s.clone(),
+            None => return,
+        };
+        if let Ok(mut mixer) = self.mixer.lock() {
+            mixer.play(samples, self.effects_volume);
+        }
+    }
+
+    //Starts the cued track if it isn't already going and keeps it following the game, None stops it
+    pub fn cue_music(&mut self, cue: Option<music::Cue>) {
+        let mut mixer = match self.mixer.lock() {
+            Ok(m) => m,
+            Err(_) => return,
+        };
+        let cue = match cue {
+            Some(c) => c,
+            None => {
+                mixer.music = None;
+                return;
+            },
+        };
+        if mixer.music.as_ref().is_none_or(|m| m.track.name != cue.track) {
+            let rate = mixer.rate;
+            mixer.music = music::Track::builtin(cue.track).map(|t| music::Sequencer::new(t, rate));
+        }
+        if let Some(m) = mixer.music.as_mut() {
+            m.paused = cue.paused;
+            m.set_level(cue.level);
+            m.set_stack_height(cue.stack_height);
+        }
+    }
+
+    //The sound effects for what just happened in the game
+    pub fn on_event(&mut self, event: &GameEvent) {
+        match event {
+            GameEvent::Moved { dx, .. } if *dx != 0 => self.play(Sound::Move),
+            GameEvent::Rotated { .. } => self.play(Sound::Rotate),
+            GameEvent::Held { .. } => self.play(Sound::Hold),
+            GameEvent::Locked { hard_drop, spin, .. } => {
+                self.play(if *hard_drop { Sound::HardDrop } else { Sound::Lock });
+                if *spin {
+                    self.play(Sound::TSpin);
+                }
+            },
+            GameEvent::LinesCleared { rows, combo, .. } => {
+                self.play(Sound::LineClear(rows.len().min(4) as u8));
+                if *combo > 0 {
+                    self.play(Sound::Combo((*combo).min(COMBO_TIERS as u32) as u8));
+                }
+            },
+            GameEvent::LevelUp { .. } => self.play(Sound::LevelUp),
+            GameEvent::TopOut => self.play(Sound::GameOver),
+            _ => (),
+        }
+    }
+
+    pub fn playing(&self) -> usize {
+        self.mixer.lock().map(|m| m.voices()).unwrap_or(0)
+    }
+
+    //A device pulls samples on its own, the null backend is moved on a game tick at a time
+    pub fn tick(&mut self) {
+        if matches!(self.output, Output::Null(_)) {
+            self.render(FRAMES_PER_TICK);
+        }
+    }
+
+    //Mixes the next frames into the null backend's buffer and hands them back, nothing on a device
+    pub fn render(&mut self, frames: usize) -> &[f32] {
+        match &mut self.output {
+            Output::Null(buffer) => {
+                buffer.clear();
+                buffer.resize(frames, 0.0);
+                if let Ok(mut mixer) = self.mixer.lock() {
+                    mixer.mix(buffer, 1);
+                }
+                buffer
+            },
+            #[cfg(feature = "audio")]
+            Output::Device(_) => &[],
+        }
+    }
+}
+
+#[cfg(feature = "audio")]
+mod device {
+
+    use super::Mixer;
+
+    use anyhow::{Context, Result};
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+    use std::sync::{Arc, Mutex};
+
+    pub fn open() -> Result<(Arc<Mutex<Mixer>>, cpal::Stream)> {
+
+        let host = cpal::default_host();
+        let device = host.default_output_device().context("No audio output device")?;
+        let supported = device.default_output_config()?;
+        let format = supported.sample_format();
+        let config: cpal::StreamConfig = supported.into();
+
+        let mixer = Arc::new(Mutex::new(Mixer::new(config.sample_rate.0)));
+        let stream = match format {
+            cpal::SampleFormat::F32 => stream::<f32>(&device, &config, mixer.clone())?,
+            cpal::SampleFormat::I16 => stream::<i16>(&device, &config, mixer.clone())?,
+            cpal::SampleFormat::U16 => stream::<u16>(&device, &config, mixer.clone())?,
+        };
+        stream.play()?;
+        Ok((mixer, stream))
+    }
+
+    fn stream<T: cpal::Sample>(device: &cpal::Device, config: &cpal::StreamConfig, mixer: Arc<Mutex<Mixer>>) -> Result<cpal::Stream> {
+
+        let channels = config.channels as usize;
+        let mut mixed: Vec<f32> = Vec::new();
+        let stream = device.build_output_stream(
+            config,
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                mixed.clear();
+                mixed.resize(data.len(), 0.0);
+                if let Ok(mut m) = mixer.lock() {
+                    m.mix(&mut mixed, channels);
+                }
+                for (out, sample) in data.iter_mut().zip(mixed.iter()) {
+                    *out = cpal::Sample::from::<f32>(sample);
+                }
+            },
+            |e| println!("Audio stream error: {}", e),
+        )?;
+        Ok(stream)
+    }
+}
+
+//16 bit mono PCM
+pub fn write_wav(path: &str, samples: &[f32], sample_rate: u32) -> Result<()> {
+
+    let data_size = samples.len() as u32 * 2;
+    let mut bytes = Vec::with_capacity(44 + data_size as usize);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_size).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    //PCM, one channel
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&sample_rate.to_le_bytes());
+    bytes.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    bytes.extend_from_slice(&2u16.to_le_bytes());
+    bytes.extend_from_slice(&16u16.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_size.to_le_bytes());
+    for s in samples {
+        bytes.extend_from_slice(&((s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes());
+    }
+
+    std::fs::write(path, bytes).with_context(|| format!("Couldn't write {}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0f32, |m, s| m.max(s.abs()))
+    }
+
+    #[test]
+    fn every_sound_is_made() {
+        for sound in Sound::all() {
+            let samples = synthesise(sound);
+            assert!(!samples.is_empty(), "{:?}", sound);
+            assert!(peak(&samples) > 0.0 && peak(&samples) <= 1.0, "{:?}", sound);
+        }
+    }
+
+    #[test]
+    fn notes_are_equal_tempered() {
+        assert_eq!(note(0), 440.0);
+        assert!((note(12) - 880.0).abs() < 0.01);
+        assert!((note(-12) - 220.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn null_backend_plays_sounds_out() {
+        let mut audio = Audio::null();
+        assert_eq!(audio.playing(), 0);
+        assert!(peak(audio.render(FRAMES_PER_TICK)) == 0.0);
+
+        audio.play(Sound::Move);
+        assert_eq!(audio.playing(), 1);
+        assert!(peak(audio.render(FRAMES_PER_TICK)) > 0.0);
+
+        //A move is 25ms, two ticks see it through
+        audio.tick();
+        audio.tick();
+        assert_eq!(audio.playing(), 0);
+    }
+
+    #[test]
+    fn events_pick_the_sounds() {
+        let mut audio = Audio::null();
+        audio.on_event(&GameEvent::Moved { dx: 0, dy: -1 });
+        assert_eq!(audio.playing(), 0);
+        audio.on_event(&GameEvent::Locked { kind: 5, cells: Vec::new(), hard_drop: true, spin: true });
+        assert_eq!(audio.playing(), 2);
+        audio.on_event(&GameEvent::LinesCleared { rows: vec![1, 2], spin: false, combo: 3 });
+        assert_eq!(audio.playing(), 4);
+        audio.on_event(&GameEvent::GarbageSent { lines: 1 });
+        assert_eq!(audio.playing(), 4);
+    }
+
+    #[test]
+    fn oldest_voices_are_cut_off() {
+        let mut audio = Audio::null();
+        for _ in 0..MAX_VOICES + 5 {
+            audio.play(Sound::GameOver);
+        }
+        assert_eq!(audio.playing(), MAX_VOICES);
+    }
+
+    #[test]
+    fn volume_scales_the_mix() {
+        let mut audio = Audio::null();
+        let mut settings = settings::Settings::default_settings();
+        settings.master_volume = 0.0;
+        audio.set_volume(&settings);
+        audio.play(Sound::HardDrop);
+        assert_eq!(peak(audio.render(FRAMES_PER_TICK)), 0.0);
+    }
+
+    #[test]
+    fn mixer_resamples_to_the_output_rate() {
+        let samples = Arc::new(vec![0.5; SAMPLE_RATE as usize / 10]);
+        let mut mixer = Mixer::new(SAMPLE_RATE / 2);
+        mixer.play(samples, 1.0);
+        let mut out = vec![0.0; SAMPLE_RATE as usize / 20 * 2];
+        mixer.mix(&mut out, 2);
+        assert!(out.iter().all(|s| *s == 0.5));
+        assert_eq!(mixer.voices(), 0);
+    }
+
+    #[test]
+    fn wav_header() {
+        let path = std::env::temp_dir().join(format!("tetris-audio-{}.wav", std::process::id())).to_string_lossy().to_string();
+        write_wav(&path, &[0.0, 1.0, -1.0], SAMPLE_RATE).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(bytes.len(), 44 + 6);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(u32::from_le_bytes([bytes[24], bytes[25], bytes[26], bytes[27]]), SAMPLE_RATE);
+        assert_eq!(i16::from_le_bytes([bytes[46], bytes[47]]), i16::MAX);
+    }
+}
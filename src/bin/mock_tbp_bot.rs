@@ -0,0 +1,219 @@
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+
+/*
+Minimal Tetris Bot Protocol bot for testing the frontend with --tbp mock_tbp_bot.
+It keeps its own copy of the board and always suggests the lowest hard drop it can find.
+*/
+
+const WIDTH: i32 = 10;
+const HEIGHT: i32 = 40;
+const PIECES: [&str; 7] = ["I", "J", "L", "O", "S", "T", "Z"];
+const ORIENTATIONS: [&str; 4] = ["north", "east", "south", "west"];
+const NORTH_CELLS: [[(i32, i32); 4]; 7] = [
+    [(-1, 0), (0, 0), (1, 0), (2, 0)],
+    [(-1, 0), (0, 0), (1, 0), (-1, 1)],
+    [(-1, 0), (0, 0), (1, 0), (1, 1)],
+    [(0, 0), (1, 0), (0, 1), (1, 1)],
+    [(-1, 0), (0, 0), (0, 1), (1, 1)],
+    [(-1, 0), (0, 0), (1, 0), (0, 1)],
+    [(-1, 1), (0, 1), (0, 0), (1, 0)],
+];
+
+struct MockBot {
+    board: Vec<[bool; WIDTH as usize]>,
+    queue: VecDeque<String>,
+    hold: Option<String>,
+}
+
+fn cells(piece: &str, orientation: usize, x: i32, y: i32) -> Vec<(i32, i32)> {
+    let kind = PIECES.iter().position(|p| *p == piece).unwrap_or(0);
+    NORTH_CELLS[kind].iter().map(|(dx, dy)| {
+        let (dx, dy) = match orientation {
+            0 => (*dx, *dy),
+            1 => (*dy, -*dx),
+            2 => (-*dx, -*dy),
+            _ => (-*dy, *dx),
+        };
+        (x + dx, y + dy)
+    }).collect()
+}
+
+impl MockBot {
+
+    fn fits(&self, cells: &[(i32, i32)]) -> bool {
+        cells.iter().all(|(x, y)| *x >= 0 && *x < WIDTH && *y >= 0 && *y < HEIGHT && !self.board[*y as usize][*x as usize])
+    }
+
+    //Every hard drop from near the top, lowest first
+    fn suggest(&self) -> Vec<Value> {
+
+        let mut pieces: Vec<String> = self.queue.iter().take(1).cloned().collect();
+        match &self.hold {
+            Some(h) => pieces.push(h.clone()),
+            None => pieces.extend(self.queue.iter().skip(1).take(1).cloned()),
+        }
+
+        let mut drops = Vec::new();
+        for piece in pieces.iter() {
+            for orientation in 0..4 {
+                for x in -2..(WIDTH + 2) {
+                    let mut y = 20;
+                    if !self.fits(&cells(piece, orientation, x, y)) {
+                        continue;
+                    }
+                    while self.fits(&cells(piece, orientation, x, y - 1)) {
+                        y -= 1;
+                    }
+                    let top = cells(piece, orientation, x, y).iter().map(|c| c.1).max().unwrap_or(0);
+                    drops.push((top, x, piece.clone(), orientation, y));
+                }
+            }
+        }
+        drops.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+
+        drops.iter().take(10).map(|(_, x, piece, orientation, y)| json!({
+            "location": { "type": piece, "orientation": ORIENTATIONS[*orientation], "x": x, "y": y },
+            "spin": "none",
+        })).collect()
+    }
+
+    fn play(&mut self, mv: &Value) {
+
+        let location = &mv["location"];
+        let piece = location["type"].as_str().unwrap_or("I").to_string();
+        let orientation = ORIENTATIONS.iter().position(|o| Some(*o) == location["orientation"].as_str()).unwrap_or(0);
+        let x = location["x"].as_i64().unwrap_or(0) as i32;
+        let y = location["y"].as_i64().unwrap_or(0) as i32;
+
+        if self.queue.front() != Some(&piece) {
+            if self.hold.is_none() {
+                self.hold = self.queue.pop_front();
+            }
+            else {
+                let held = self.queue.pop_front();
+                self.queue.push_front(self.hold.take().unwrap_or_default());
+                self.hold = held;
+            }
+        }
+        self.queue.pop_front();
+
+        for (cx, cy) in cells(&piece, orientation, x, y) {
+            if cx >= 0 && cx < WIDTH && cy >= 0 && cy < HEIGHT {
+                self.board[cy as usize][cx as usize] = true;
+            }
+        }
+        self.board.retain(|row| !row.iter().all(|c| *c));
+        while self.board.len() < HEIGHT as usize {
+            self.board.push([false; WIDTH as usize]);
+        }
+    }
+}
+
+fn send(message: Value) {
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    let _ = writeln!(out, "{}", message);
+    let _ = out.flush();
+}
+
+fn main() {
+
+    let mut bot = MockBot { board: vec![[false; WIDTH as usize]; HEIGHT as usize], queue: VecDeque::new(), hold: None };
+
+    send(json!({ "type": "info", "name": "Mock", "version": "0.1", "author": "tetris", "features": [] }));
+
+    let stdin = std::io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(_) => break,
+        };
+        let message: Value = match serde_json::from_str(&line) {
+            Ok(m) => m,
+            Err(_) => continue,
+        };
+        match message["type"].as_str() {
+            Some("rules") => send(json!({ "type": "ready" })),
+            Some("start") => {
+                bot.hold = message["hold"].as_str().map(|s| s.to_string());
+                bot.queue = message["queue"].as_array().map(|q| q.iter().filter_map(|p| p.as_str().map(|s| s.to_string())).collect()).unwrap_or_default();
+                for (y, row) in message["board"].as_array().cloned().unwrap_or_default().iter().enumerate().take(HEIGHT as usize) {
+                    for (x, cell) in row.as_array().cloned().unwrap_or_default().iter().enumerate().take(WIDTH as usize) {
+                        bot.board[y][x] = !cell.is_null();
+                    }
+                }
+            },
+            Some("new_piece") => {
+                if let Some(piece) = message["piece"].as_str() {
+                    bot.queue.push_back(piece.to_string());
+                }
+            },
+            Some("suggest") => send(json!({ "type": "suggestion", "moves": bot.suggest() })),
+            Some("play") => bot.play(&message["move"]),
+            Some("quit") => break,
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bot(queue: &[&str], hold: Option<&str>) -> MockBot {
+        MockBot { board: vec![[false; WIDTH as usize]; HEIGHT as usize], queue: queue.iter().map(|p| p.to_string()).collect(), hold: hold.map(|h| h.to_string()) }
+    }
+
+    fn location(mv: &Value) -> (String, String, i64, i64) {
+        let l = &mv["location"];
+        (l["type"].as_str().unwrap().to_string(), l["orientation"].as_str().unwrap().to_string(), l["x"].as_i64().unwrap(), l["y"].as_i64().unwrap())
+    }
+
+    #[test]
+    fn orientations_turn_clockwise() {
+        assert_eq!(cells("T", 0, 4, 5), vec![(3, 5), (4, 5), (5, 5), (4, 6)]);
+        let mut east = cells("T", 1, 4, 5);
+        east.sort();
+        assert_eq!(east, vec![(4, 4), (4, 5), (4, 6), (5, 5)]);
+    }
+
+    #[test]
+    fn suggests_the_lowest_drop_first() {
+        let b = bot(&["I", "O"], None);
+        let moves = b.suggest();
+        assert_eq!(moves.len(), 10);
+        let (piece, orientation, x, y) = location(&moves[0]);
+        assert_eq!((orientation.as_str(), y), ("north", 0));
+        assert!(piece == "I" || piece == "O");
+        assert!(x >= 0 && x < WIDTH as i64);
+        //Only the current piece and whichever holding would bring in
+        assert!(moves.iter().all(|m| ["I", "O"].contains(&location(m).0.as_str())));
+    }
+
+    #[test]
+    fn plays_from_hold() {
+        let mut b = bot(&["T", "S", "Z"], None);
+        b.play(&json!({ "location": { "type": "S", "orientation": "north", "x": 1, "y": 0 }, "spin": "none" }));
+        assert_eq!(b.hold.as_deref(), Some("T"));
+        assert_eq!(b.queue.iter().cloned().collect::<Vec<String>>(), vec!["Z"]);
+        assert!(b.board[0][0] && b.board[0][1] && b.board[1][1] && b.board[1][2]);
+
+        b.play(&json!({ "location": { "type": "T", "orientation": "north", "x": 5, "y": 0 }, "spin": "none" }));
+        assert_eq!(b.hold.as_deref(), Some("Z"));
+        assert!(b.queue.is_empty());
+    }
+
+    #[test]
+    fn clears_full_rows() {
+        let mut b = bot(&["I"], None);
+        for x in 0..6 {
+            b.board[0][x] = true;
+        }
+        b.board[1][0] = true;
+        b.play(&json!({ "location": { "type": "I", "orientation": "north", "x": 7, "y": 0 }, "spin": "none" }));
+        assert!(b.board[0][0] && !b.board[0][1]);
+        assert_eq!(b.board.len(), HEIGHT as usize);
+    }
+}
@@ -0,0 +1,115 @@
+use tetris::{audio, music};
+
+use anyhow::{bail, Result};
+
+/*
+Renders the built in music to a WAV file with no window or audio device, to listen to or check.
+
+render_music --out <file.wav> [--track drift|climb] [--seconds 60] [--level 1] [--height 0]
+             [--ramp]
+
+--height is how many rows the stack reaches, 14 or more brings in the danger layers.
+--ramp ignores both and plays a whole game's worth over the length, the level climbing
+from 1 to 10 and the stack rising into danger then falling back.
+*/
+
+fn arg_value(args: &[String], name: &str) -> Option<String> {
+    args.iter().position(|a| a == name).and_then(|i| args.get(i + 1).cloned())
+}
+
+//The next frames of the track and how many bars of them had the danger layers in
+fn render(sequencer: &mut music::Sequencer, frames: usize, ramp: bool) -> (Vec<f32>, u32) {
+
+    //Changes go in a game tick apart, the way the game sends them
+    let tick = audio::SAMPLE_RATE as usize * 16 / 1000;
+    let mut samples = Vec::with_capacity(frames);
+    let mut danger_bars = 0;
+    while samples.len() < frames {
+        if ramp {
+            let t = samples.len() as f32 / frames as f32;
+            sequencer.set_level(1 + (t * 10.0) as u32);
+            //Up to the top over the first two thirds, cleared down in the last
+            let rise = if t < 0.66 { t / 0.66 } else { (1.0 - t) / 0.34 };
+            sequencer.set_stack_height((rise * 18.0) as usize);
+        }
+        let step = sequencer.step();
+        samples.extend(sequencer.render(tick.min(frames - samples.len())));
+        if sequencer.step() != step && sequencer.step() % music::STEPS_PER_BAR == 0 && sequencer.danger_playing() {
+            danger_bars += 1;
+        }
+    }
+    (samples, danger_bars)
+}
+
+fn run() -> Result<()> {
+
+    let args: Vec<String> = std::env::args().collect();
+    let out = match arg_value(&args, "--out") {
+        Some(o) => o,
+        None => bail!("Nowhere to write, give --out <file.wav>"),
+    };
+    let name = arg_value(&args, "--track").unwrap_or_else(|| music::ENDLESS_TRACK.to_string());
+    let track = match music::Track::builtin(&name) {
+        Some(t) => t,
+        None => bail!("Unknown track {}, try {}", name, music::TRACKS.join(" or ")),
+    };
+    let seconds: f32 = arg_value(&args, "--seconds").map(|s| s.parse()).transpose()?.unwrap_or(60.0);
+    let level: u32 = arg_value(&args, "--level").map(|l| l.parse()).transpose()?.unwrap_or(1);
+    let height: usize = arg_value(&args, "--height").map(|h| h.parse()).transpose()?.unwrap_or(0);
+    let ramp = args.iter().any(|a| a == "--ramp");
+
+    let mut sequencer = music::Sequencer::new(track, audio::SAMPLE_RATE);
+    sequencer.set_level(level);
+    sequencer.set_stack_height(height);
+
+    let frames = (seconds * audio::SAMPLE_RATE as f32) as usize;
+    let (samples, danger_bars) = render(&mut sequencer, frames, ramp);
+
+    audio::write_wav(&out, &samples, audio::SAMPLE_RATE)?;
+    let peak = samples.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+    println!("Wrote {:.1}s of {} to {}, peak {:.2}, {} bars in danger", seconds, name, out, peak, danger_bars);
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{:#}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sequencer(height: usize) -> music::Sequencer {
+        let mut sequencer = music::Sequencer::new(music::Track::builtin(music::ENDLESS_TRACK).unwrap(), audio::SAMPLE_RATE);
+        sequencer.set_stack_height(height);
+        sequencer
+    }
+
+    #[test]
+    fn renders_the_length_asked_for() {
+        let frames = audio::SAMPLE_RATE as usize * 2 + 7;
+        let (samples, danger_bars) = render(&mut sequencer(0), frames, false);
+        assert_eq!(samples.len(), frames);
+        assert_eq!(danger_bars, 0);
+        assert!(samples.iter().any(|s| *s != 0.0));
+        assert!(samples.iter().all(|s| s.abs() <= 1.0));
+    }
+
+    #[test]
+    fn high_stack_plays_danger() {
+        let (_, danger_bars) = render(&mut sequencer(music::DANGER_HEIGHT), audio::SAMPLE_RATE as usize * 10, false);
+        assert!(danger_bars > 0);
+    }
+
+    #[test]
+    fn ramp_rises_into_danger_and_back() {
+        let mut sequencer = sequencer(0);
+        let (_, danger_bars) = render(&mut sequencer, audio::SAMPLE_RATE as usize * 60, true);
+        assert!(danger_bars > 0);
+        assert!(!sequencer.danger);
+        assert_eq!(sequencer.tempo, music::tempo_for_level(10));
+    }
+}
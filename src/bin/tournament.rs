@@ -0,0 +1,292 @@
+use tetris::{bot, key_bindings, stats, versus};
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::{Duration, Instant};
+
+/*
+Plays seeded bot against bot matches with no window and reports how each side did.
+
+tournament --a <config> --b <config> [--matches 100] [--seed 1] [--max-ticks 18750]
+           [--timeout <seconds per match>] [--format json|csv]
+
+A config is comma separated key=value pairs starting from the medium CPU, for example
+difficulty=hard,weights=./bot_weights.cfg,depth=1,pps=3,mistakes=0.05
+
+A match both bots survive to max ticks is a draw, counted apart from the draws where both top
+out on the same tick. Running past the timeout on the clock is a timeout, however far it got.
+*/
+
+#[derive(Debug, Clone)]
+struct BotConfig {
+    spec: String,
+    weights: bot::BotWeights,
+    pps: f32,
+    depth: u32,
+    mistake_rate: f32,
+}
+
+impl BotConfig {
+
+    fn parse(spec: &str) -> Result<BotConfig> {
+
+        let base = bot::Difficulty::Medium.bot();
+        let mut config = BotConfig {
+            spec: spec.to_string(),
+            weights: base.weights,
+            pps: base.pps,
+            depth: base.depth,
+            mistake_rate: base.mistake_rate,
+        };
+
+        for part in spec.split(',').filter(|p| !p.is_empty()) {
+            let mut kv = part.splitn(2, '=');
+            let key = kv.next().unwrap_or("");
+            let value = kv.next().with_context(|| format!("Expected key=value in {}", part))?;
+            match key {
+                "difficulty" => {
+                    let preset = match bot::Difficulty::from_name(value) {
+                        Some(d) => d.bot(),
+                        None => bail!("Unknown difficulty {}", value),
+                    };
+                    config.pps = preset.pps;
+                    config.depth = preset.depth;
+                    config.mistake_rate = preset.mistake_rate;
+                },
+                "weights" => config.weights = bot::BotWeights::load(value),
+                "pps" => config.pps = value.parse()?,
+                "depth" => config.depth = value.parse()?,
+                "mistakes" => config.mistake_rate = value.parse()?,
+                _ => bail!("Unknown bot setting {}", key),
+            }
+        }
+
+        Ok(config)
+    }
+
+    fn bot(&self, seed: u64) -> bot::Bot {
+        let mut bot = bot::Bot::new(self.weights.clone(), self.pps);
+        bot.depth = self.depth;
+        bot.mistake_rate = self.mistake_rate;
+        bot.reseed(seed);
+        bot
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Outcome {
+    Winner(usize),
+    //Both topped out together
+    Draw,
+    //Both still going at max ticks
+    TickLimit,
+    Timeout,
+    Crash,
+}
+
+struct MatchResult {
+    outcome: Outcome,
+    stats: [stats::Stats; 2],
+}
+
+fn play_match(configs: &[BotConfig; 2], seed: u64, max_ticks: u32, timeout: Duration) -> MatchResult {
+
+    let mut stats = [stats::Stats::new(), stats::Stats::new()];
+    let mut ticks = 0;
+    let mut timed_out = false;
+
+    let played = panic::catch_unwind(AssertUnwindSafe(|| {
+        let bindings = [key_bindings::KeyBindings::default_bindings(), key_bindings::KeyBindings::player_two_bindings()];
+        let mut v = versus::Versus::new_seeded(bindings, seed);
+        v.announce_winner = false;
+        for i in 0..2 {
+            v.players[i].bot = Some(configs[i].bot(seed.wrapping_add(i as u64)));
+        }
+
+        let started = Instant::now();
+        while v.outcome.is_none() && ticks < max_ticks {
+            if started.elapsed() >= timeout {
+                timed_out = true;
+                break;
+            }
+            v.process_inputs([0, 0]);
+            v.update();
+            for i in 0..2 {
+                stats[i] = v.players[i].stats.clone();
+            }
+            ticks += 1;
+        }
+        v.outcome
+    }));
+
+    let outcome = match played {
+        Ok(Some(versus::Outcome::Winner(winner))) => Outcome::Winner(winner),
+        Ok(Some(versus::Outcome::Draw)) => Outcome::Draw,
+        Ok(None) if timed_out => Outcome::Timeout,
+        Ok(None) => Outcome::TickLimit,
+        Err(_) => Outcome::Crash,
+    };
+    MatchResult { outcome, stats }
+}
+
+#[derive(Serialize)]
+struct BotReport {
+    config: String,
+    wins: u32,
+    win_rate: f32,
+    average_apm: f32,
+    average_pps: f32,
+}
+
+#[derive(Serialize)]
+struct Report {
+    matches: u32,
+    seed: u64,
+    bots: [BotReport; 2],
+    draws: u32,
+    tick_limit_draws: u32,
+    timeouts: u32,
+    crashes: u32,
+}
+
+fn tournament(configs: &[BotConfig; 2], matches: u32, seed: u64, max_ticks: u32, timeout: Duration) -> Report {
+
+    let mut wins = [0, 0];
+    let mut apm = [0.0, 0.0];
+    let mut pps = [0.0, 0.0];
+    let mut timed = 0;
+    let mut draws = 0;
+    let mut tick_limit_draws = 0;
+    let mut timeouts = 0;
+    let mut crashes = 0;
+
+    for m in 0..matches {
+        let result = play_match(configs, seed.wrapping_add(m as u64), max_ticks, timeout);
+        match result.outcome {
+            Outcome::Winner(w) => wins[w] += 1,
+            Outcome::Draw => draws += 1,
+            Outcome::TickLimit => tick_limit_draws += 1,
+            Outcome::Timeout => timeouts += 1,
+            Outcome::Crash => {
+                crashes += 1;
+                continue;
+            },
+        }
+        if result.stats[0].ticks > 0 {
+            for i in 0..2 {
+                apm[i] += result.stats[i].apm();
+                pps[i] += result.stats[i].pps();
+            }
+            timed += 1;
+        }
+        eprintln!("Match {} of {} done", m + 1, matches);
+    }
+
+    let bot_report = |i: usize| BotReport {
+        config: configs[i].spec.clone(),
+        wins: wins[i],
+        win_rate: if matches > 0 { wins[i] as f32 / matches as f32 } else { 0.0 },
+        average_apm: if timed > 0 { apm[i] / timed as f32 } else { 0.0 },
+        average_pps: if timed > 0 { pps[i] / timed as f32 } else { 0.0 },
+    };
+    Report { matches, seed, bots: [bot_report(0), bot_report(1)], draws, tick_limit_draws, timeouts, crashes }
+}
+
+//A header and a row for each bot, the match totals repeated on both
+fn csv(report: &Report) -> String {
+    let mut lines = vec!["bot,config,matches,wins,win_rate,average_apm,average_pps,draws,tick_limit_draws,timeouts,crashes".to_string()];
+    for (i, b) in report.bots.iter().enumerate() {
+        lines.push(format!(
+            "{},\"{}\",{},{},{:.3},{:.2},{:.2},{},{},{},{}",
+            if i == 0 { "a" } else { "b" }, b.config, report.matches, b.wins, b.win_rate, b.average_apm, b.average_pps,
+            report.draws, report.tick_limit_draws, report.timeouts, report.crashes,
+        ));
+    }
+    lines.join("\n")
+}
+
+fn arg_value(args: &[String], name: &str) -> Option<String> {
+    args.iter().position(|a| a == name).and_then(|i| args.get(i + 1).cloned())
+}
+
+fn run() -> Result<()> {
+
+    let args: Vec<String> = std::env::args().collect();
+    let configs = [
+        BotConfig::parse(&arg_value(&args, "--a").unwrap_or_default())?,
+        BotConfig::parse(&arg_value(&args, "--b").unwrap_or_default())?,
+    ];
+    let matches: u32 = arg_value(&args, "--matches").map(|m| m.parse()).transpose()?.unwrap_or(100);
+    let seed: u64 = arg_value(&args, "--seed").map(|s| s.parse()).transpose()?.unwrap_or(1);
+    //Five minutes of game time
+    let max_ticks: u32 = arg_value(&args, "--max-ticks").map(|t| t.parse()).transpose()?.unwrap_or(18750);
+    let timeout = Duration::from_secs_f32(arg_value(&args, "--timeout").map(|t| t.parse()).transpose()?.unwrap_or(60.0));
+    let format = arg_value(&args, "--format").unwrap_or_else(|| "json".to_string());
+    if format != "json" && format != "csv" {
+        bail!("Unknown format {}, try json or csv", format);
+    }
+
+    //Panics are counted as crashes, the default hook would print a backtrace over the report
+    panic::set_hook(Box::new(|info| eprintln!("Match crashed: {}", info)));
+
+    let report = tournament(&configs, matches, seed, max_ticks, timeout);
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    }
+    else {
+        println!("{}", csv(&report));
+    }
+
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{:#}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn configs() -> [BotConfig; 2] {
+        [BotConfig::parse("depth=0,pps=10").unwrap(), BotConfig::parse("difficulty=easy").unwrap()]
+    }
+
+    #[test]
+    fn tick_limits_and_timeouts_are_told_apart() {
+        let result = play_match(&configs(), 5, 60, Duration::from_secs(60));
+        assert_eq!(result.outcome, Outcome::TickLimit);
+        assert_eq!(result.stats[0].ticks, 60);
+        let result = play_match(&configs(), 5, 60, Duration::from_secs(0));
+        assert_eq!(result.outcome, Outcome::Timeout);
+    }
+
+    #[test]
+    fn reports_have_a_fixed_shape() {
+        let report = tournament(&configs(), 3, 11, 400, Duration::from_secs(60));
+        let json = serde_json::to_value(&report).unwrap();
+        let keys: Vec<&String> = json.as_object().unwrap().keys().collect();
+        assert_eq!(keys, vec!["bots", "crashes", "draws", "matches", "seed", "tick_limit_draws", "timeouts"]);
+        let bot_keys: Vec<&String> = json["bots"][1].as_object().unwrap().keys().collect();
+        assert_eq!(bot_keys, vec!["average_apm", "average_pps", "config", "win_rate", "wins"]);
+        assert_eq!(json["bots"][1]["config"], "difficulty=easy");
+        assert_eq!(report.bots[0].wins + report.bots[1].wins + report.draws + report.tick_limit_draws + report.timeouts + report.crashes, 3);
+        assert_eq!(report.timeouts, 0);
+
+        //The same seed plays the same matches
+        let again = tournament(&configs(), 3, 11, 400, Duration::from_secs(60));
+        assert_eq!(serde_json::to_value(&again).unwrap(), json);
+
+        let csv = csv(&report);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "bot,config,matches,wins,win_rate,average_apm,average_pps,draws,tick_limit_draws,timeouts,crashes");
+        assert!(lines[1].starts_with("a,\"depth=0,pps=10\",3,"));
+        assert!(lines[2].starts_with("b,\"difficulty=easy\",3,"));
+        assert!(lines[2].ends_with(&format!(",{},{},0,0", report.draws, report.tick_limit_draws)));
+    }
+}
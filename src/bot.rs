@@ -0,0 +1,604 @@
+use crate::game;
+use crate::tetromino;
+use crate::game_options;
+use crate::tetris_board;
+use crate::key_bindings;
+use crate::movegen;
+
+use std::fs;
+use std::collections::VecDeque;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+pub const WEIGHTS_PATH: &str = "./bot_weights.cfg";
+//How many placements are followed at each step of a lookahead
+const SEARCH_WIDTH: usize = 5;
+const TOPPED_OUT_SCORE: f32 = -1000.0;
+
+//Playfield without the walls, the bot doesn't care about them
+pub const FIELD_WIDTH: usize = game_options::BOARD_WIDTH - 2;
+pub const FIELD_HEIGHT: usize = game_options::BOARD_HEIGHT - 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BotWeights {
+    pub aggregate_height: f32,
+    pub holes: f32,
+    pub bumpiness: f32,
+    pub wells: f32,
+    pub row_transitions: f32,
+    pub column_transitions: f32,
+    pub lines_cleared: f32,
+    pub tspin_setups: f32,
+}
+
+impl BotWeights {
+
+    pub fn default_weights() -> BotWeights {
+        BotWeights {
+            aggregate_height: -0.51,
+            holes: -0.36,
+            bumpiness: -0.18,
+            wells: -0.05,
+            row_transitions: -0.2,
+            column_transitions: -0.3,
+            lines_cleared: 0.76,
+            tspin_setups: 0.3,
+        }
+    }
+
+    fn weight_mut(&mut self, name: &str) -> Option<&mut f32> {
+        match name {
+            "aggregate_height" => Some(&mut self.aggregate_height),
+            "holes" => Some(&mut self.holes),
+            "bumpiness" => Some(&mut self.bumpiness),
+            "wells" => Some(&mut self.wells),
+            "row_transitions" => Some(&mut self.row_transitions),
+            "column_transitions" => Some(&mut self.column_transitions),
+            "lines_cleared" => Some(&mut self.lines_cleared),
+            "tspin_setups" => Some(&mut self.tspin_setups),
+            _ => None,
+        }
+    }
+
+    //Same name=value format as the bindings file, missing weights keep their defaults
+    pub fn load(path: &str) -> BotWeights {
+
+        let mut weights = BotWeights::default_weights();
+
+        let contents = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(_) => return weights,
+        };
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(2, '=');
+            let name = parts.next().unwrap_or("").trim();
+            let value = parts.next().and_then(|v| v.trim().parse::<f32>().ok());
+            match (weights.weight_mut(name), value) {
+                (Some(w), Some(v)) => *w = v,
+                _ => println!("Bad line in bot weights file: {}", line),
+            }
+        }
+
+        weights
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Features {
+    pub aggregate_height: f32,
+    pub holes: f32,
+    pub bumpiness: f32,
+    pub wells: f32,
+    pub row_transitions: f32,
+    pub column_transitions: f32,
+    pub lines_cleared: f32,
+    pub tspin_setups: f32,
+}
+
+impl Features {
+    pub fn score(&self, w: &BotWeights) -> f32 {
+        self.aggregate_height * w.aggregate_height
+            + self.holes * w.holes
+            + self.bumpiness * w.bumpiness
+            + self.wells * w.wells
+            + self.row_transitions * w.row_transitions
+            + self.column_transitions * w.column_transitions
+            + self.lines_cleared * w.lines_cleared
+            + self.tspin_setups * w.tspin_setups
+    }
+}
+
+//Occupancy of the playfield, row 0 at the bottom
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub cells: [[bool; FIELD_WIDTH]; FIELD_HEIGHT],
+}
+
+impl Field {
+
+    pub fn from_board(board: &tetris_board::TetrisBoard) -> Field {
+        let mut cells = [[false; FIELD_WIDTH]; FIELD_HEIGHT];
+        for (y, row) in cells.iter_mut().enumerate() {
+            for (x, cell) in row.iter_mut().enumerate() {
+                *cell = board.cell(x + 1, y + 1) > 0;
+            }
+        }
+        Field { cells }
+    }
+
+    //Outside the field counts as filled, like the walls
+    pub fn filled(&self, x: i32, y: i32) -> bool {
+        if x < 0 || y < 0 || x >= FIELD_WIDTH as i32 {
+            return true;
+        }
+        if y >= FIELD_HEIGHT as i32 {
+            return false;
+        }
+        self.cells[y as usize][x as usize]
+    }
+
+    //Writes the piece in at its current position, returns false if any of it is off the field
+    pub fn place(&mut self, piece: &tetromino::Tetromino) -> bool {
+        let rc = piece.rotation_constant as usize;
+        for i in 0..piece.piece_data.len() {
+            if piece.piece_data[i] == 0 {
+                continue;
+            }
+            let x = piece.x as i32 - (i % rc) as i32 - 1;
+            let y = piece.y as i32 - (i / rc) as i32 - 1;
+            if x < 0 || y < 0 || x >= FIELD_WIDTH as i32 || y >= FIELD_HEIGHT as i32 {
+                return false;
+            }
+            self.cells[y as usize][x as usize] = true;
+        }
+        true
+    }
+
+    pub fn clear_lines(&mut self) -> usize {
+        let mut cleared = 0;
+        let mut y = 0;
+        while y < FIELD_HEIGHT {
+            if self.cells[y].iter().all(|c| *c) {
+                for above in y..(FIELD_HEIGHT - 1) {
+                    self.cells[above] = self.cells[above + 1];
+                }
+                self.cells[FIELD_HEIGHT - 1] = [false; FIELD_WIDTH];
+                cleared += 1;
+            }
+            else {
+                y += 1;
+            }
+        }
+        cleared
+    }
+
+    pub fn column_heights(&self) -> [usize; FIELD_WIDTH] {
+        let mut heights = [0; FIELD_WIDTH];
+        for (x, height) in heights.iter_mut().enumerate() {
+            for y in (0..FIELD_HEIGHT).rev() {
+                if self.cells[y][x] {
+                    *height = y + 1;
+                    break;
+                }
+            }
+        }
+        heights
+    }
+
+    pub fn features(&self, lines_cleared: usize) -> Features {
+
+        let heights = self.column_heights();
+        let max_height = heights.iter().copied().max().unwrap_or(0);
+        let mut f = Features { lines_cleared: lines_cleared as f32, ..Features::default() };
+
+        for x in 0..FIELD_WIDTH {
+            f.aggregate_height += heights[x] as f32;
+            for y in 0..heights[x] {
+                if !self.cells[y][x] {
+                    f.holes += 1.0;
+                }
+            }
+            if x + 1 < FIELD_WIDTH {
+                f.bumpiness += (heights[x] as i32 - heights[x + 1] as i32).abs() as f32;
+            }
+
+            //Walls count as full height so edge wells are found too
+            let left = if x == 0 { FIELD_HEIGHT } else { heights[x - 1] };
+            let right = if x + 1 == FIELD_WIDTH { FIELD_HEIGHT } else { heights[x + 1] };
+            let depth = left.min(right).saturating_sub(heights[x]);
+            f.wells += (depth * (depth + 1) / 2) as f32;
+
+            let mut last = true;
+            for y in 0..FIELD_HEIGHT {
+                if self.cells[y][x] != last {
+                    f.column_transitions += 1.0;
+                }
+                last = self.cells[y][x];
+            }
+        }
+
+        for y in 0..max_height {
+            let mut last = true;
+            for x in 0..FIELD_WIDTH {
+                if self.cells[y][x] != last {
+                    f.row_transitions += 1.0;
+                }
+                last = self.cells[y][x];
+            }
+            if !last {
+                f.row_transitions += 1.0;
+            }
+        }
+
+        //A T slot is an empty centre with empty arms, a floor under it and a roof over one side
+        for y in 0..(max_height as i32) {
+            for x in 0..(FIELD_WIDTH as i32) {
+                if self.filled(x, y) || self.filled(x - 1, y) || self.filled(x + 1, y) {
+                    continue;
+                }
+                let floor = self.filled(x, y - 1) && self.filled(x - 1, y - 1) && self.filled(x + 1, y - 1);
+                let roof = self.filled(x - 1, y + 1) || self.filled(x + 1, y + 1);
+                if floor && roof && !self.filled(x, y + 1) {
+                    f.tspin_setups += 1.0;
+                }
+            }
+        }
+
+        f
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Placement {
+    pub piece: tetromino::Tetromino,
+    pub path: Vec<key_bindings::Action>,
+    pub score: f32,
+}
+
+pub fn evaluate(field: &Field, piece: &tetromino::Tetromino, weights: &BotWeights) -> Option<f32> {
+    let mut after = field.clone();
+    if !after.place(piece) {
+        return None;
+    }
+    let lines = after.clear_lines();
+    Some(after.features(lines).score(weights))
+}
+
+//The board once a piece has locked and any lines it made are gone
+pub fn board_after(board: &tetris_board::TetrisBoard, piece: &tetromino::Tetromino) -> (tetris_board::TetrisBoard, usize) {
+    let mut after = board.clone();
+    after.add_piece_to_board(piece);
+    after.check_line(piece.rotation_constant, piece.y);
+    let lines = after.clear_lines();
+    after.did_zero = false;
+    (after, lines)
+}
+
+//Best score reachable by placing the queued pieces in turn, only following the most promising
+//placements at each step so deeper searches stay quick enough to run between frames
+fn lookahead(board: &tetris_board::TetrisBoard, lines: usize, queue: &[u8], depth: u32, weights: &BotWeights) -> f32 {
+
+    if depth == 0 || queue.is_empty() {
+        return Field::from_board(board).features(lines).score(weights);
+    }
+
+    let piece = tetromino::Tetromino::new_piece(queue[0]);
+    let placements = movegen::reachable_placements(&piece, board);
+    let on_field = placements.iter().filter(|m| m.piece.cells().iter().all(|(_, y)| *y as usize <= FIELD_HEIGHT));
+    let mut candidates: Vec<(f32, tetris_board::TetrisBoard, usize)> = on_field.map(|m| {
+        let (after, cleared) = board_after(board, &m.piece);
+        let score = Field::from_board(&after).features(lines + cleared).score(weights);
+        (score, after, lines + cleared)
+    }).collect();
+    candidates.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    candidates.iter().take(SEARCH_WIDTH).map(|(_, after, total)| lookahead(after, *total, &queue[1..], depth - 1, weights)).fold(TOPPED_OUT_SCORE, f32::max)
+}
+
+//Every placement of the current piece and of the hold piece if holding is allowed, best first.
+//Depth is how many of the next pieces are looked ahead at.
+pub fn search(game: &game::Game, weights: &BotWeights, depth: u32) -> Vec<Placement> {
+
+    let board = game.board();
+    let field = Field::from_board(board);
+    let next = game.next_pieces();
+
+    let mut placements = Vec::new();
+    for m in movegen::placements_with_hold(game) {
+        if let Some(score) = evaluate(&field, &m.piece, weights) {
+            placements.push(Placement { piece: m.piece, path: m.path, score });
+        }
+    }
+    placements.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+
+    if depth > 0 {
+        for p in placements.iter_mut().take(SEARCH_WIDTH) {
+            let hold = p.path.first() == Some(&key_bindings::Action::Hold);
+            let queue = if hold && game.hold_piece().is_none() { &next[1..] } else { &next[..] };
+            let (after, lines) = board_after(board, &p.piece);
+            p.score = lookahead(&after, lines, queue, depth, weights);
+        }
+        //Only the ones looked ahead from are comparable with each other
+        placements.truncate(SEARCH_WIDTH);
+        placements.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+    }
+
+    placements
+}
+
+pub fn best_placement(game: &game::Game, weights: &BotWeights) -> Option<Placement> {
+    search(game, weights, 0).into_iter().next()
+}
+
+//Takes the next input of a plan, skipping soft drops that gravity has already done since one
+//more would lock the piece early
+pub fn next_planned_action(plan: &mut VecDeque<key_bindings::Action>, game: &game::Game) -> Option<key_bindings::Action> {
+    match plan.pop_front() {
+        Some(key_bindings::Action::SoftDrop) => {
+            if movegen::step(game.piece(), key_bindings::Action::SoftDrop, game.board()).is_some() {
+                Some(key_bindings::Action::SoftDrop)
+            }
+            else {
+                None
+            }
+        },
+        action => action,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+    Expert,
+}
+
+impl Difficulty {
+
+    pub fn from_name(name: &str) -> Option<Difficulty> {
+        match name {
+            "easy" => Some(Difficulty::Easy),
+            "medium" => Some(Difficulty::Medium),
+            "hard" => Some(Difficulty::Hard),
+            "expert" => Some(Difficulty::Expert),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Difficulty::Easy => "easy",
+            Difficulty::Medium => "medium",
+            Difficulty::Hard => "hard",
+            Difficulty::Expert => "expert",
+        }
+    }
+
+    //The next one up, wrapping round to easy
+    pub fn next(&self) -> Difficulty {
+        match self {
+            Difficulty::Easy => Difficulty::Medium,
+            Difficulty::Medium => Difficulty::Hard,
+            Difficulty::Hard => Difficulty::Expert,
+            Difficulty::Expert => Difficulty::Easy,
+        }
+    }
+
+    //Pieces per second, pieces looked ahead and chance of a random placement
+    pub fn bot(&self) -> Bot {
+        let (pps, depth, mistake_rate) = match self {
+            Difficulty::Easy => (0.8, 0, 0.25),
+            Difficulty::Medium => (1.5, 0, 0.08),
+            Difficulty::Hard => (2.5, 1, 0.02),
+            Difficulty::Expert => (4.0, 2, 0.0),
+        };
+        let mut bot = Bot::new(BotWeights::default_weights(), pps);
+        bot.depth = depth;
+        bot.mistake_rate = mistake_rate;
+        bot
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Bot {
+    pub weights: BotWeights,
+    pub pps: f32,
+    pub depth: u32,
+    pub mistake_rate: f32,
+    plan: VecDeque<key_bindings::Action>,
+    planned_for: Option<u32>,
+    last_drop: Option<u32>,
+    last_action: Option<u32>,
+    rng: StdRng,
+}
+
+impl Bot {
+
+    pub fn new(weights: BotWeights, pps: f32) -> Bot {
+        Bot { weights, pps, depth: 0, mistake_rate: 0.0, plan: VecDeque::new(), planned_for: None, last_drop: None, last_action: None, rng: StdRng::from_entropy() }
+    }
+
+    //For repeatable mistakes, like in seeded tournaments
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    //One action at a time, hard drops held back to keep under the pieces per second cap.
+    //It's asked every frame in a window but once a tick headless, so it only acts once per game
+    //tick and plays the same either way as long as frames keep up with ticks.
+    pub fn think(&mut self, game: &game::Game) -> Option<key_bindings::Action> {
+
+        if game.topped_out || self.last_action == Some(game.ticks()) {
+            return None;
+        }
+
+        if self.planned_for != Some(game.pieces_placed()) {
+            //Wait out the line clear so the plan is made against the board the piece lands on. The
+            //game is only borrowed and its events are someone else's to take, so ask the board.
+            if game.board().did_zero {
+                return None;
+            }
+            let mut placements = search(game, &self.weights, self.depth);
+            let choice = if !placements.is_empty() && self.rng.gen::<f32>() < self.mistake_rate {
+                self.rng.gen::<usize>() % placements.len()
+            }
+            else {
+                0
+            };
+            self.plan = if placements.is_empty() { VecDeque::new() } else { placements.swap_remove(choice).path.into_iter().collect() };
+            self.planned_for = Some(game.pieces_placed());
+        }
+
+        match self.plan.front() {
+            Some(key_bindings::Action::HardDrop) => {
+                let gap = (game_options::TICKS_PER_SECOND / self.pps.max(0.01)) as u32;
+                if let Some(last) = self.last_drop {
+                    if game.ticks().wrapping_sub(last) < gap {
+                        return None;
+                    }
+                }
+                self.last_drop = Some(game.ticks());
+                self.last_action = Some(game.ticks());
+                self.plan.pop_front()
+            },
+            Some(_) => {
+                self.last_action = Some(game.ticks());
+                next_planned_action(&mut self.plan, game)
+            },
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Rows from the top down, columns in field order, # filled
+    fn field(rows: &[&str]) -> Field {
+        let mut cells = [[false; FIELD_WIDTH]; FIELD_HEIGHT];
+        for (i, row) in rows.iter().enumerate() {
+            let y = rows.len() - 1 - i;
+            for (x, c) in row.chars().enumerate() {
+                cells[y][x] = c == '#';
+            }
+        }
+        Field { cells }
+    }
+
+    #[test]
+    fn features_of_a_hand_built_field() {
+        let f = field(&[
+            "##........",
+            "#.#.......",
+            "###.######",
+        ]);
+        assert_eq!(f.column_heights(), [3, 3, 2, 0, 1, 1, 1, 1, 1, 1]);
+        let features = f.features(0);
+        assert_eq!(features.aggregate_height, 14.0);
+        assert_eq!(features.holes, 1.0);
+        assert_eq!(features.bumpiness, 4.0);
+        assert_eq!(features.wells, 1.0);
+        assert_eq!(features.lines_cleared, 0.0);
+    }
+
+    #[test]
+    fn walls_count_for_wells() {
+        //Two deep against the wall scores 1 + 2
+        let f = field(&[
+            "#########.",
+            "#########.",
+        ]);
+        let features = f.features(0);
+        assert_eq!(features.wells, 3.0);
+        assert_eq!(features.holes, 0.0);
+        assert_eq!(features.bumpiness, 2.0);
+    }
+
+    #[test]
+    fn clears_lines_and_drops_what_was_above() {
+        let mut f = field(&[
+            "#.........",
+            "##########",
+            ".#########",
+            "##########",
+        ]);
+        assert_eq!(f.clear_lines(), 2);
+        assert_eq!(f, field(&["#.........", ".#########"]));
+    }
+
+    #[test]
+    fn takes_the_obvious_line_clear() {
+        //Four rows with one gap, an I stood up in it clears them all
+        let mut board = tetris_board::TetrisBoard::new();
+        board.add_garbage(4, 7);
+        let field = Field::from_board(&board);
+        let weights = BotWeights::default_weights();
+        let piece = tetromino::Tetromino::new_piece(0);
+        let best = movegen::reachable_placements(&piece, &board).into_iter()
+            .filter_map(|m| evaluate(&field, &m.piece, &weights).map(|score| (score, m)))
+            .max_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+            .unwrap().1;
+        assert!(best.piece.cells().iter().all(|(x, _)| *x == 7));
+        assert_eq!(board_after(&board, &best.piece).1, 4);
+    }
+
+    //Pieces a preset's bot places in that many ticks of a seeded game, and how the game ended up
+    fn play(difficulty: Difficulty, ticks: u32) -> (u32, u64) {
+        let mut game = game::Game::new_player(key_bindings::KeyBindings::default_bindings(), [0.0, 0.0], 11);
+        let mut bot = difficulty.bot();
+        bot.reseed(5);
+        game.bot = Some(bot);
+        for _ in 0..ticks {
+            game.process_inputs([0, 0]);
+            game.update();
+        }
+        (game.pieces_placed(), game.state_hash())
+    }
+
+    #[test]
+    fn presets_get_harder() {
+        let presets = [Difficulty::Easy, Difficulty::Medium, Difficulty::Hard, Difficulty::Expert];
+        for pair in presets.windows(2) {
+            let (easier, harder) = (pair[0].bot(), pair[1].bot());
+            assert!(harder.pps > easier.pps);
+            assert!(harder.depth >= easier.depth);
+            assert!(harder.mistake_rate < easier.mistake_rate);
+            assert_eq!(pair[0].next(), pair[1]);
+            assert_eq!(Difficulty::from_name(pair[0].name()), Some(pair[0]));
+        }
+    }
+
+    #[test]
+    fn presets_play_differently() {
+        //Same seeds give the same game, faster presets place more in the same time
+        assert_eq!(play(Difficulty::Easy, 500), play(Difficulty::Easy, 500));
+        let (easy, _) = play(Difficulty::Easy, 500);
+        let (expert, _) = play(Difficulty::Expert, 500);
+        assert!(easy >= 4, "easy placed {}", easy);
+        assert!(expert > easy * 2, "expert placed {} to easy's {}", expert, easy);
+    }
+
+    #[test]
+    fn mistakes_change_the_plan() {
+        let game = game::Game::new_player(key_bindings::KeyBindings::default_bindings(), [0.0, 0.0], 11);
+        let best: VecDeque<key_bindings::Action> = search(&game, &BotWeights::default_weights(), 0)[0].path.iter().copied().collect();
+        let mut careful = Bot::new(BotWeights::default_weights(), 1.0);
+        let mut careless = Bot::new(BotWeights::default_weights(), 1.0);
+        careless.mistake_rate = 1.0;
+        careful.reseed(3);
+        careless.reseed(3);
+        //The plan is made on the first call and its first input taken straight away
+        let first = careful.think(&game);
+        careless.think(&game);
+        assert_eq!(first, best.front().copied());
+        assert_eq!(careful.plan.len() + 1, best.len());
+        assert_ne!(careful.plan, careless.plan);
+    }
+}
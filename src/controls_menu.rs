@@ -0,0 +1,172 @@
+use crate::input;
+use crate::r_backend;
+use crate::key_bindings;
+use crate::game_options;
+use crate::skins;
+use crate::text;
+
+use winit::event::*;
+
+pub struct ControlsMenu {
+    inputs: input::Inputs,
+    pub open: bool,
+    //Whose bindings are being edited, Left and Right switch
+    pub player: usize,
+    selected: usize,
+    capturing: bool,
+    //The player and action already using the key last pressed
+    conflict: Option<(usize, key_bindings::Action)>,
+    ticks: u32,
+}
+
+impl ControlsMenu {
+    pub fn new() -> ControlsMenu {
+        let inputs = input::Inputs::new();
+        let open = false;
+        let player = 0;
+        let selected = 0;
+        let capturing = false;
+        let conflict = None;
+        let ticks = 0;
+        ControlsMenu { inputs, open, player, selected, capturing, conflict, ticks }
+    }
+
+    pub fn toggle(&mut self, bindings: [&mut key_bindings::KeyBindings; 2]) {
+        self.open = !self.open;
+        self.capturing = false;
+        self.conflict = None;
+        self.inputs.last_pressed = None;
+        if self.open {
+            self.print(bindings[self.player]);
+        }
+    }
+
+    pub fn input(&mut self, event: &WindowEvent) {
+        self.inputs.input(event);
+    }
+
+    pub fn process_inputs(&mut self, bindings: [&mut key_bindings::KeyBindings; 2]) {
+
+        let [mine, other] = bindings;
+        let (bindings, other) = if self.player == 0 { (mine, other) } else { (other, mine) };
+
+        self.ticks = self.ticks.wrapping_add(1);
+
+        let key = match self.inputs.take_last_pressed() {
+            Some(k) => k,
+            None => return,
+        };
+
+        if self.capturing {
+            if key == VirtualKeyCode::Return || key == VirtualKeyCode::F1 {
+                self.capturing = false;
+                self.conflict = None;
+                println!("Rebind cancelled");
+                return;
+            }
+            if !key_bindings::is_bindable(key) {
+                println!("{:?} can't be bound", key);
+                return;
+            }
+            let action = key_bindings::ALL_ACTIONS[self.selected];
+            //Both players share the keyboard, so a key either of them uses is taken
+            let result = match other.action_for(key) {
+                Some(a) => Err((1 - self.player, a)),
+                None => bindings.rebind(action, key).map_err(|a| (self.player, a)),
+            };
+            match result {
+                Ok(()) => {
+                    self.capturing = false;
+                    self.conflict = None;
+                    self.save(bindings);
+                    self.print(bindings);
+                },
+                Err((player, a)) => {
+                    self.conflict = Some((player, a));
+                    println!("{} is already bound to player {}'s {}, pick another key", key_bindings::key_name(key), player + 1, a.name());
+                },
+            }
+            return;
+        }
+
+        match key {
+            VirtualKeyCode::Up => {
+                self.selected = (self.selected + key_bindings::ALL_ACTIONS.len() - 1) % key_bindings::ALL_ACTIONS.len();
+                self.print(bindings);
+            },
+            VirtualKeyCode::Down => {
+                self.selected = (self.selected + 1) % key_bindings::ALL_ACTIONS.len();
+                self.print(bindings);
+            },
+            VirtualKeyCode::Left | VirtualKeyCode::Right => {
+                self.player = 1 - self.player;
+                self.print(other);
+            },
+            VirtualKeyCode::Return => {
+                self.capturing = true;
+                println!("Press a key for {}", key_bindings::ALL_ACTIONS[self.selected].name());
+            },
+            VirtualKeyCode::Back => {
+                let (_, defaults) = key_bindings::KeyBindings::for_player(self.player);
+                //The other player may have taken one of the defaults since
+                if let Some(&action) = key_bindings::ALL_ACTIONS.iter().find(|a| other.action_for(defaults.key(**a)).is_some()) {
+                    println!("Player {} uses {}, can't reset to defaults", 2 - self.player, key_bindings::key_name(defaults.key(action)));
+                    return;
+                }
+                *bindings = defaults;
+                self.save(bindings);
+                self.print(bindings);
+            },
+            _ => (),
+        }
+    }
+
+    fn save(&self, bindings: &key_bindings::KeyBindings) {
+        let (path, _) = key_bindings::KeyBindings::for_player(self.player);
+        if let Err(e) = bindings.save(path) {
+            println!("Failed to save bindings to {}: {}", path, e);
+        }
+    }
+
+    fn print(&self, bindings: &key_bindings::KeyBindings) {
+        println!("Player {} controls (Up/Down select, Left/Right player, Enter rebind, Backspace reset, F1 close)", self.player + 1);
+        for (i, action) in key_bindings::ALL_ACTIONS.iter().enumerate() {
+            let marker = if i == self.selected { ">" } else { " " };
+            println!("{} {:<12} {}", marker, action.name(), key_bindings::key_name(bindings.key(*action)));
+        }
+    }
+
+    pub fn render(&self, r: &mut r_backend::Renderer, bindings: [&mut key_bindings::KeyBindings; 2]) {
+
+        let bindings = &bindings[self.player];
+        let title = format!("< Player {} >", self.player + 1);
+        r.render_text(&title, [2.5, 8.5 + game_options::SCALE], game_options::SCALE, text::Align::Left, [1.0, 1.0, 1.0, 1.0], -0.5, text::FONT.to_string());
+
+        //Each action is a row of tiles in its piece colour with its name and key beside it
+        for i in 0..key_bindings::ALL_ACTIONS.len() {
+            let y = 7.5 - (i as f32 * 1.0);
+            let x = 2.5;
+            let selected = i == self.selected;
+            let mut alpha = if selected { 1.0 } else { 0.4 };
+            if selected && self.capturing && (self.ticks / 20).is_multiple_of(2) {
+                alpha = 0.15;
+            }
+            let colour = if selected && self.conflict.is_some() { 6 } else { i as i32 };
+            if selected {
+                r.render_sprite_array([x - game_options::SCALE * 1.5, y], [game_options::SCALE, game_options::SCALE], -1.0, skins::SPRITE_ARRAY.to_string(), 8);
+            }
+            for t in 0..6 {
+                r.render_sprite_transparent([x + t as f32 * game_options::SCALE, y], [game_options::SCALE, game_options::SCALE], -1.0, skins::SPRITE_ARRAY.to_string(), colour, alpha);
+            }
+            let action = key_bindings::ALL_ACTIONS[i];
+            let label = format!("{:<12} {}", action.name(), key_bindings::key_name(bindings.key(action)));
+            r.render_text(&label, [x + 6.5 * game_options::SCALE, y + game_options::SCALE], game_options::SCALE, text::Align::Left, [1.0, 1.0, 1.0, alpha], -0.5, text::FONT.to_string());
+        }
+    }
+}
+
+impl Default for ControlsMenu {
+    fn default() -> ControlsMenu {
+        ControlsMenu::new()
+    }
+}
@@ -0,0 +1,40 @@
+/*
+What happened in a game, as it happens. The game pushes these as the rules play out and whoever
+presents or passes on the game takes them, so nothing has to watch its fields for changes.
+
+Stats   counted as they're pushed, inside the game
+Versus  takes both players' every tick, passes garbage across and sees who topped out, then
+        keeps them for the play mode to hand on
+Audio   plays the sound effects for the ones from players at this keyboard
+Gym     waits from a clear to its finish before the next observation
+
+The bots and trainers only borrow the game each tick and aren't the ones taking its events, so
+they look at the board for a pending clear instead. Over the network only inputs are sent, the
+events are what each side's game makes of them.
+
+Board positions are the game's own, x counting from the right hand wall and y up from the floor.
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GameEvent {
+    //A new piece at the top, from the queue or out of hold
+    PieceSpawned { kind: u8 },
+    //By the player, dy is -1 for a soft drop
+    Moved { dx: i8, dy: i8 },
+    //kick is which of the kick table's tests it fitted on, 0 for where it was
+    Rotated { direction: i8, kick: usize },
+    Held { kind: u8 },
+    Locked { kind: u8, cells: Vec<(u8, u8)>, hard_drop: bool, spin: bool },
+    //Rows the piece just filled, they're taken out a few ticks later. combo counts the clears
+    //in a row before this one.
+    LinesCleared { rows: Vec<usize>, spin: bool, combo: u32 },
+    //The rows announced so far are gone and the board has settled
+    ClearFinished { lines: u32 },
+    //Owed to the opponent when the rows are taken out
+    GarbageSent { lines: u32 },
+    GarbageReceived { lines: u32, hole: usize },
+    TopOut,
+    //A solo run's goal was reached, play stops there
+    Finished,
+    LevelUp { level: u32 },
+}
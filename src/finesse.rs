@@ -0,0 +1,213 @@
+use crate::movegen;
+use crate::r_backend;
+use crate::tetromino;
+use crate::tetris_board;
+use crate::key_bindings;
+use crate::skins;
+
+use std::collections::{HashMap, VecDeque};
+
+/*
+Finesse is how many shifts and rotations a piece took against the fewest that reach the same
+cells from spawn. The fewest comes from the move generator's search on the board the piece
+locked on, weighted so soft drops cost nothing and a tuck is only charged for its shifts and
+rotations. Soft drops are left out of both counts, holding them is how pieces get brought down
+and they aren't a finesse mistake, and so are hard drops and holds. A hold starts the count over.
+*/
+
+//How long a fault stays on screen
+const FLASH_TICKS: u32 = 45;
+
+#[derive(Debug, Clone)]
+pub struct Finesse {
+    inputs: u32,
+    pub pieces: u32,
+    pub faults: u32,
+    pub extra_inputs: u32,
+    //Extra inputs on the last fault and the tick it happened
+    flash: Option<(u32, u32)>,
+}
+
+fn counts(action: key_bindings::Action) -> bool {
+    matches!(action, key_bindings::Action::MoveLeft | key_bindings::Action::MoveRight | key_bindings::Action::RotateCw | key_bindings::Action::RotateCcw)
+}
+
+//Fewest shifts and rotations that hard drop into cells, with soft drops free
+fn minimal_inputs(spawn: &tetromino::Tetromino, board: &tetris_board::TetrisBoard, cells: &[(u8, u8)]) -> Option<u32> {
+
+    if !board.check_piece_fits(spawn) {
+        return None;
+    }
+
+    let key = |p: &tetromino::Tetromino| (p.x, p.y, p.rotation());
+    let mut best: HashMap<(u8, u8, u8), u32> = HashMap::new();
+    let mut queue = VecDeque::new();
+    best.insert(key(spawn), 0);
+    queue.push_back((spawn.clone(), 0));
+
+    //Free edges go on the front so states come off in order of cost
+    while let Some((current, cost)) = queue.pop_front() {
+        if matches!(best.get(&key(&current)), Some(c) if *c < cost) {
+            continue;
+        }
+
+        let mut dropped = current.clone();
+        dropped.y = dropped.get_ghost(board).1;
+        if dropped.cells() == cells {
+            return Some(cost);
+        }
+
+        for action in movegen::EDGES.iter() {
+            if let Some(next) = movegen::step(&current, *action, board) {
+                let next_cost = if counts(*action) { cost + 1 } else { cost };
+                if !matches!(best.get(&key(&next)), Some(c) if *c <= next_cost) {
+                    best.insert(key(&next), next_cost);
+                    if next_cost == cost {
+                        queue.push_front((next, next_cost));
+                    }
+                    else {
+                        queue.push_back((next, next_cost));
+                    }
+                }
+            }
+        }
+    }
+    None
+}
+
+impl Finesse {
+
+    pub fn new() -> Finesse {
+        Finesse { inputs: 0, pieces: 0, faults: 0, extra_inputs: 0, flash: None }
+    }
+
+    pub fn record(&mut self, action: key_bindings::Action) {
+        if counts(action) {
+            self.inputs += 1;
+        }
+    }
+
+    pub fn held(&mut self) {
+        self.inputs = 0;
+    }
+
+    //board is the one the piece locked on, without the piece in it
+    pub fn piece_locked(&mut self, piece: &tetromino::Tetromino, board: &tetris_board::TetrisBoard, ticks: u32) {
+
+        let used = std::mem::replace(&mut self.inputs, 0);
+        let cells = piece.cells();
+        let spawn = tetromino::Tetromino::new_piece(piece.kind);
+        let minimal = match minimal_inputs(&spawn, board, &cells) {
+            Some(m) => m,
+            //Got there some way the search doesn't, nothing to judge it against
+            None => return,
+        };
+
+        self.pieces += 1;
+        if used > minimal {
+            self.faults += 1;
+            self.extra_inputs += used - minimal;
+            self.flash = Some((used - minimal, ticks));
+        }
+    }
+
+    pub fn accuracy(&self) -> f32 {
+        if self.pieces == 0 {
+            return 1.0;
+        }
+        1.0 - self.faults as f32 / self.pieces as f32
+    }
+
+    pub fn summary(&self) -> String {
+        format!("Finesse: {} faults in {} pieces ({:.1}%), {} extra inputs", self.faults, self.pieces, self.accuracy() * 100.0, self.extra_inputs)
+    }
+
+    //A red tile for each wasted input under the hold box, fading out
+    pub fn render(&self, r: &mut r_backend::Renderer, ticks: u32, origin: [f32; 2], scale: f32) {
+
+        let (extra, at) = match self.flash {
+            Some(f) => f,
+            None => return,
+        };
+        let age = ticks.saturating_sub(at);
+        if age >= FLASH_TICKS {
+            return;
+        }
+        let alpha = 0.9 * (1.0 - age as f32 / FLASH_TICKS as f32);
+        for i in 0..extra.min(8) {
+            let x = -5.0 - i as f32 * 0.6;
+            r.render_sprite_transparent([origin[0] + x * -scale, origin[1] + 18.25 * scale], [0.5 * scale, 0.5 * scale], -1.0, skins::SPRITE_ARRAY.to_string(), 6, alpha);
+        }
+    }
+}
+
+impl Default for Finesse {
+    fn default() -> Finesse {
+        Finesse::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use key_bindings::Action;
+
+    const T: u8 = 5;
+
+    //Where the piece lands after the inputs, played from spawn then hard dropped
+    fn landed(board: &tetris_board::TetrisBoard, actions: &[Action]) -> tetromino::Tetromino {
+        let mut piece = tetromino::Tetromino::new_piece(T);
+        for a in actions {
+            piece = movegen::step(&piece, *a, board).unwrap();
+        }
+        piece.y = piece.get_ghost(board).1;
+        piece
+    }
+
+    fn judge(board: &tetris_board::TetrisBoard, actions: &[Action], lands: &[Action]) -> Finesse {
+        let mut finesse = Finesse::new();
+        for a in actions {
+            finesse.record(*a);
+        }
+        finesse.piece_locked(&landed(board, lands), board, 0);
+        finesse
+    }
+
+    #[test]
+    fn a_straight_drop_is_clean() {
+        let board = tetris_board::TetrisBoard::new();
+        let finesse = judge(&board, &[Action::HardDrop], &[]);
+        assert_eq!((finesse.pieces, finesse.faults, finesse.extra_inputs), (1, 0, 0));
+        assert_eq!(finesse.accuracy(), 1.0);
+    }
+
+    #[test]
+    fn going_left_then_right_is_a_fault() {
+        let board = tetris_board::TetrisBoard::new();
+        let finesse = judge(&board, &[Action::MoveLeft, Action::MoveRight, Action::HardDrop], &[]);
+        assert_eq!((finesse.pieces, finesse.faults, finesse.extra_inputs), (1, 1, 2));
+        assert_eq!(finesse.accuracy(), 0.0);
+    }
+
+    #[test]
+    fn drops_are_free() {
+        let board = tetris_board::TetrisBoard::new();
+        let finesse = judge(&board, &[Action::SoftDrop, Action::SoftDrop, Action::MoveLeft, Action::SoftDrop, Action::HardDrop], &[Action::MoveLeft]);
+        assert_eq!((finesse.pieces, finesse.faults), (1, 0));
+        //The fewest is just the shifts, whatever soft drops the search takes on the way
+        let minimal = minimal_inputs(&tetromino::Tetromino::new_piece(T), &board, &landed(&board, &[Action::MoveRight, Action::MoveRight]).cells());
+        assert_eq!(minimal, Some(2));
+    }
+
+    #[test]
+    fn holding_starts_over() {
+        let board = tetris_board::TetrisBoard::new();
+        let mut finesse = Finesse::new();
+        finesse.record(Action::MoveLeft);
+        finesse.record(Action::MoveLeft);
+        finesse.held();
+        finesse.record(Action::MoveRight);
+        finesse.piece_locked(&landed(&board, &[Action::MoveRight]), &board, 0);
+        assert_eq!(finesse.faults, 0);
+    }
+}
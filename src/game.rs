@@ -5,9 +5,29 @@ use crate::r_backend;
 use crate::tetromino;
 use crate::game_options;
 use crate::tetris_board;
+use crate::key_bindings;
+use crate::bot;
+use crate::pc_solver;
+use crate::finesse;
+use crate::stats;
+use crate::piece_set;
+use crate::skins;
+use crate::settings;
+use crate::goals;
+use crate::replay;
+use crate::text;
+use crate::events::GameEvent;
 
 use winit::event::*;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use std::hash::{Hash, Hasher};
+use std::collections::hash_map::DefaultHasher;
 
+//Events kept for a caller that isn't taking them
+pub const MAX_EVENTS: usize = 256;
+
+#[derive(Clone)]
 pub struct Game {
     inputs: input::Inputs,
     tet: tetromino::Tetromino,
@@ -18,12 +38,56 @@ pub struct Game {
     down_tick: u32,
     clear_tick: u32,
     next_pieces: [u8; 6],
+    pub bindings: key_bindings::KeyBindings,
+    pub origin: [f32; 2],
+    pub scale: f32,
+    pub topped_out: bool,
+    //What a solo run is for, and whether it got there
+    pub goal: goals::Goal,
+    pub finished: bool,
+    pending_garbage: u32,
+    piece_rng: StdRng,
+    garbage_rng: StdRng,
+    pub queue_actions: bool,
+    queued_actions: Vec<key_bindings::Action>,
+    hold: Option<u8>,
+    hold_used: bool,
+    pieces_placed: u32,
+    lines_cleared: u32,
+    pub bot: Option<bot::Bot>,
+    hints: Option<bot::BotWeights>,
+    hint: Option<tetromino::Tetromino>,
+    hint_for: Option<(u32, bool)>,
+    pc_trainer: Option<pc_solver::Trainer>,
+    pub finesse: finesse::Finesse,
+    pub stats: stats::Stats,
+    events: Vec<GameEvent>,
+    //Pieces in a row that cleared lines
+    combo: u32,
+    last_rotated: bool,
+    //Rows waiting to clear that already had their event
+    rows_announced: Vec<usize>,
+    seed: u64,
+    //Solo runs keep every action so they can be saved if they place
+    pub recording: Option<replay::Replay>,
+    //Actions from a saved run, live input is ignored while it plays
+    playback: Option<replay::Playback>,
 }
 
 impl Game {
+    //A solo game, recorded from the start
     pub fn new() -> Game {
+        let mut game = Game::new_player(key_bindings::KeyBindings::load(key_bindings::BINDINGS_PATH, key_bindings::KeyBindings::default_bindings()), [0.0, 0.0], rand::random::<u64>());
+        game.recording = Some(replay::Replay::new(game.seed, game.goal.name(), piece_set::active().fingerprint()));
+        game
+    }
+
+    //Everything random comes from the seed so two games with the same seed and inputs stay identical
+    pub fn new_player(bindings: key_bindings::KeyBindings, origin: [f32; 2], seed: u64) -> Game {
+        let mut piece_rng = StdRng::seed_from_u64(seed);
+        let garbage_rng = StdRng::seed_from_u64(seed ^ 0x9e37_79b9_7f4a_7c15);
         let inputs = input::Inputs::new();
-        let tet = tetromino::Tetromino::new_piece(rand::random::<u8>() % 7);
+        let tet = tetromino::Tetromino::new_piece(Game::random_piece(&mut piece_rng));
         let board = tetris_board::TetrisBoard::new();
         let left = false;
         let right = false;
@@ -31,18 +95,48 @@ impl Game {
         let down_tick = 60;
         let clear_tick = 20;
         let next_pieces = [
-            rand::random::<u8>() % 7,
-            rand::random::<u8>() % 7,
-            rand::random::<u8>() % 7,
-            rand::random::<u8>() % 7,
-            rand::random::<u8>() % 7,
-            rand::random::<u8>() % 7,
+            Game::random_piece(&mut piece_rng),
+            Game::random_piece(&mut piece_rng),
+            Game::random_piece(&mut piece_rng),
+            Game::random_piece(&mut piece_rng),
+            Game::random_piece(&mut piece_rng),
+            Game::random_piece(&mut piece_rng),
         ];
-        Game { inputs, tet, board, left, right, ticks, down_tick, clear_tick, next_pieces }
+        let topped_out = false;
+        let goal = goals::Goal::Endless;
+        let finished = false;
+        let pending_garbage = 0;
+        let queue_actions = false;
+        let queued_actions = Vec::new();
+        let hold = None;
+        let hold_used = false;
+        let pieces_placed = 0;
+        let lines_cleared = 0;
+        let bot = None;
+        let scale = game_options::SCALE;
+        let hints = None;
+        let hint = None;
+        let hint_for = None;
+        let pc_trainer = None;
+        let finesse = finesse::Finesse::new();
+        let stats = stats::Stats::new();
+        let events = vec![GameEvent::PieceSpawned { kind: tet.kind }];
+        let combo = 0;
+        let last_rotated = false;
+        let rows_announced = Vec::new();
+        let recording = None;
+        let playback = None;
+        Game { inputs, tet, board, left, right, ticks, down_tick, clear_tick, next_pieces, bindings, origin, scale, topped_out, goal, finished, pending_garbage, piece_rng, garbage_rng, queue_actions, queued_actions, hold, hold_used, pieces_placed, lines_cleared, bot, hints, hint, hint_for, pc_trainer, finesse, stats, events, combo, last_rotated, rows_announced, seed, recording, playback }
     }
 
-    pub fn init(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, r: &mut r_backend::Renderer) {
-        r.load_sprite_array(device, queue, "./res/pieces/default/*.png", "default_pieces".to_string());
+    //Any piece of the active set, all equally likely
+    fn random_piece(rng: &mut StdRng) -> u8 {
+        rng.gen::<u8>() % piece_set::active().len() as u8
+    }
+
+    pub fn init(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, r: &mut r_backend::Renderer, skins: &mut skins::Skins) {
+        let settings = settings::Settings::load(settings::SETTINGS_PATH);
+        skins.load_or_default(device, queue, r, &settings.skin);
         //r.load_sprite(device, queue, std::path::Path::new("./res/piece.png"), "red".to_string());
         //r.load_sprite(device, queue, std::path::Path::new("./res/grey_piece.png"), "grey".to_string());
     }
@@ -50,6 +144,9 @@ impl Game {
     pub fn input(&mut self, event: &WindowEvent) {
 
         self.inputs.input(event);
+        if self.playback.is_some() {
+            return;
+        }
 
         match event {
             WindowEvent::KeyboardInput {
@@ -62,87 +159,517 @@ impl Game {
                 ..
             } => {
                 let pressed = *state == ElementState::Pressed;
-                if *keycode == VirtualKeyCode::Space && pressed {
-                    while !self.tet.force_down(&mut self.board) {
-                        //Push all the way down
+                if pressed {
+                    match self.bindings.action_for(*keycode) {
+                        //Rotations are edge triggered in process_inputs so holding doesn't spin
+                        Some(key_bindings::Action::RotateCw) | Some(key_bindings::Action::RotateCcw) => (),
+                        Some(key_bindings::Action::Pause) => (),
+                        Some(action) => self.handle_action(action),
+                        None => (),
                     }
-                    self.update_next_pieces();
-                    if !self.board.check_piece_fits(&self.tet) {
-                        self.board = tetris_board::TetrisBoard::new();
-                    }
-                    self.clear_tick = self.ticks + 20;
-                }
-                if *keycode == VirtualKeyCode::Down && pressed {
-                    if self.tet.force_down(&mut self.board) {
-                        self.update_next_pieces();
-                        if !self.board.check_piece_fits(&self.tet) {
-                            self.board = tetris_board::TetrisBoard::new();
-                        }
-                        self.clear_tick = self.ticks + 20;
-                    }
-                }
-                if *keycode == VirtualKeyCode::Left && pressed {
-                    self.tet.move_piece(1, 0, &self.board);
-                }
-                if *keycode == VirtualKeyCode::Right && pressed {
-                    self.tet.move_piece(-1, 0, &self.board);
                 }
             }
             _ => (),
         }
     }
 
+    pub fn release_keys(&mut self) {
+        self.inputs.release_all();
+    }
+
     pub fn process_inputs(&mut self, w_di: [u32; 2]) {
 
-        if self.inputs.keys[VirtualKeyCode::Z as usize] && !self.right {
-            self.tet.rotate_piece(-1, &self.board);
+        if self.playback.is_some() {
+            return;
+        }
+
+        let rotate_ccw = self.inputs.keys[self.bindings.key(key_bindings::Action::RotateCcw) as usize];
+        let rotate_cw = self.inputs.keys[self.bindings.key(key_bindings::Action::RotateCw) as usize];
+
+        if rotate_ccw && !self.right {
+            self.handle_action(key_bindings::Action::RotateCcw);
             self.right = true;
         }
-        if !self.inputs.keys[VirtualKeyCode::Z as usize] {
+        if !rotate_ccw {
             self.right = false;
         }
-        if self.inputs.keys[VirtualKeyCode::X as usize] && !self.left {
-            self.tet.rotate_piece(1, &self.board);
+        if rotate_cw && !self.left {
+            self.handle_action(key_bindings::Action::RotateCw);
             self.left = true;
         }
-        if !self.inputs.keys[VirtualKeyCode::X as usize] {
+        if !rotate_cw {
             self.left = false;
         }
+
+        //Taken out while it thinks so it can look at the rest of the game
+        if let Some(mut bot) = self.bot.take() {
+            if let Some(action) = bot.think(self) {
+                self.handle_action(action);
+            }
+            self.bot = Some(bot);
+        }
+
+        self.update_hint();
+        if let Some(mut trainer) = self.pc_trainer.take() {
+            trainer.update(self);
+            self.pc_trainer = Some(trainer);
+        }
+    }
+
+    //Hints use the bot's weights file so they agree with how it plays
+    pub fn toggle_hints(&mut self) {
+        if self.hints.is_some() {
+            self.hints = None;
+            self.hint = None;
+            println!("Hints off");
+        }
+        else {
+            self.hints = Some(bot::BotWeights::load(bot::WEIGHTS_PATH));
+            self.hint_for = None;
+            self.stats.assisted = true;
+            println!("Hints on, this run is marked as assisted");
+        }
+    }
+
+    //Cycles the perfect clear trainer through 4 lines, 6 lines and off
+    pub fn toggle_pc_trainer(&mut self) {
+        self.pc_trainer = match &self.pc_trainer {
+            None => Some(pc_solver::Trainer::new(4)),
+            Some(t) if t.max_lines < pc_solver::MAX_LINES => Some(pc_solver::Trainer::new(pc_solver::MAX_LINES)),
+            Some(_) => None,
+        };
+        match &self.pc_trainer {
+            Some(t) => {
+                self.stats.assisted = true;
+                println!("Perfect clear trainer on, up to {} lines", t.max_lines);
+            },
+            None => println!("Perfect clear trainer off"),
+        }
+    }
+
+    //Worked out again whenever a new piece spawns or hold swaps it
+    fn update_hint(&mut self) {
+        let weights = match &self.hints {
+            Some(w) => w,
+            None => return,
+        };
+        let key = (self.pieces_placed, self.hold_used);
+        if self.hint_for == Some(key) || self.board.did_zero {
+            return;
+        }
+        self.hint = bot::best_placement(self, weights).map(|p| p.piece);
+        self.hint_for = Some(key);
+    }
+
+    //Queued actions are held back for a caller that applies them on a fixed tick, like netplay
+    pub fn handle_action(&mut self, action: key_bindings::Action) {
+        if self.queue_actions {
+            self.queued_actions.push(action);
+        }
+        else {
+            self.apply_action(action);
+        }
+    }
+
+    //Rolls the simulation back to a saved copy but keeps the live keyboard state
+    pub fn load_state(&mut self, saved: &Game) {
+        let mut restored = saved.clone();
+        std::mem::swap(&mut restored.inputs, &mut self.inputs);
+        std::mem::swap(&mut restored.queued_actions, &mut self.queued_actions);
+        std::mem::swap(&mut restored.bot, &mut self.bot);
+        std::mem::swap(&mut restored.events, &mut self.events);
+        restored.left = self.left;
+        restored.right = self.right;
+        *self = restored;
+    }
+
+    pub fn take_queued_actions(&mut self) -> Vec<key_bindings::Action> {
+        std::mem::take(&mut self.queued_actions)
+    }
+
+    //Everything that happened since the last call
+    pub fn take_events(&mut self) -> Vec<GameEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    //Nobody may be taking them, so old ones are dropped rather than piling up
+    fn emit(&mut self, event: GameEvent) {
+        self.stats.record(&event);
+        if self.events.len() >= MAX_EVENTS {
+            self.events.remove(0);
+        }
+        self.events.push(event);
+    }
+
+    pub fn apply_action(&mut self, action: key_bindings::Action) {
+
+        if self.topped_out || self.finished {
+            return;
+        }
+
+        self.finesse.record(action);
+        self.stats.keys += 1;
+        if let Some(recording) = &mut self.recording {
+            recording.record(self.ticks, action);
+        }
+        match action {
+            key_bindings::Action::HardDrop => {
+                while !self.tet.force_down(&mut self.board) {
+                    //Push all the way down
+                    self.last_rotated = false;
+                }
+                self.piece_locked(true);
+            },
+            key_bindings::Action::SoftDrop => {
+                if self.tet.force_down(&mut self.board) {
+                    self.piece_locked(false);
+                }
+                else {
+                    self.last_rotated = false;
+                    self.emit(GameEvent::Moved { dx: 0, dy: -1 });
+                }
+            },
+            key_bindings::Action::MoveLeft => self.move_piece(1),
+            key_bindings::Action::MoveRight => self.move_piece(-1),
+            key_bindings::Action::RotateCw => self.rotate_piece(1),
+            key_bindings::Action::RotateCcw => self.rotate_piece(-1),
+            key_bindings::Action::Hold => {
+                if !self.hold_used {
+                    self.finesse.held();
+                    let held = self.tet.kind;
+                    self.emit(GameEvent::Held { kind: held });
+                    match self.hold.replace(held) {
+                        Some(kind) => {
+                            self.tet = tetromino::Tetromino::new_piece(kind);
+                            self.emit(GameEvent::PieceSpawned { kind });
+                        },
+                        None => self.update_next_pieces(),
+                    }
+                    self.hold_used = true;
+                    self.last_rotated = false;
+                    if !self.board.check_piece_fits(&self.tet) {
+                        self.top_out();
+                    }
+                }
+            },
+            key_bindings::Action::Pause => (),
+        }
     }
 
     pub fn update(&mut self) {
 
+        if self.topped_out || self.finished {
+            return;
+        }
+
+        //Actions recorded after the last tick go in before this one, as they did live
+        if let Some(mut playback) = self.playback.take() {
+            for action in playback.take(self.ticks) {
+                self.apply_action(action);
+            }
+            self.playback = Some(playback);
+            //The last of them can end the run, as it did before this tick
+            if self.topped_out || self.finished {
+                return;
+            }
+        }
+
         self.ticks += 1;
+        self.stats.ticks += 1;
         if self.ticks % self.down_tick == 0 {
             if self.tet.force_down(&mut self.board) {
-                self.update_next_pieces();
-                if !self.board.check_piece_fits(&self.tet) {
-                    self.board = tetris_board::TetrisBoard::new();
-                }
-                self.clear_tick = self.ticks + 20;
+                self.piece_locked(false);
+            }
+            else {
+                self.last_rotated = false;
             }
         }
 
         if self.board.did_zero {
             if self.ticks == self.clear_tick {
-                self.board.clear_lines();
+                let cleared = self.board.clear_lines();
                 self.board.did_zero = false;
+                self.rows_announced.clear();
+                if cleared > 0 {
+                    self.emit(GameEvent::ClearFinished { lines: cleared as u32 });
+                }
+                let level = self.level();
+                self.lines_cleared += cleared as u32;
+                if self.level() > level {
+                    self.emit(GameEvent::LevelUp { level: self.level() });
+                }
+                let lines = Game::garbage_for_clear(cleared);
+                if lines > 0 {
+                    self.emit(GameEvent::GarbageSent { lines });
+                }
+                //The piece in play rides up on the garbage rather than ending up inside it
+                let lines = self.apply_garbage();
+                self.lift_piece(lines);
+            }
+        }
+
+        if !self.topped_out && self.goal.reached(self) {
+            self.finished = true;
+            self.emit(GameEvent::Finished);
+        }
+    }
+
+    //Starts the run over for another goal with the same seed, dig lays its garbage down
+    //straight away
+    pub fn set_goal(&mut self, goal: goals::Goal) {
+        self.goal = goal;
+        self.restart_seeded(self.seed);
+    }
+
+    //Plays a saved run from the start, it has to be for the same goal and pieces
+    pub fn play_back(&mut self, saved: &replay::Replay) -> anyhow::Result<()> {
+        if saved.pieces != piece_set::active().fingerprint() {
+            anyhow::bail!("The replay was played with a different piece set");
+        }
+        let goal = match goals::Goal::from_name(&saved.goal) {
+            Some(g) => g,
+            None => anyhow::bail!("Unknown goal {} in replay", saved.goal),
+        };
+        let actions = saved.decode_actions()?;
+        self.bot = None;
+        self.goal = goal;
+        self.restart_seeded(saved.seed);
+        self.recording = None;
+        self.playback = Some(replay::Playback::new(actions));
+        Ok(())
+    }
+
+    pub fn is_playback(&self) -> bool {
+        self.playback.is_some()
+    }
+
+    fn lay_dig_garbage(&mut self) {
+        for _ in 0..goals::DIG_ROWS {
+            let hole = 1 + self.garbage_rng.gen::<usize>() % (game_options::BOARD_WIDTH - 2);
+            self.board.add_garbage(1, hole);
+        }
+    }
+
+    //Up to rows higher until it's clear of the stack, topped out if that isn't enough
+    fn lift_piece(&mut self, rows: u32) {
+        if self.topped_out || self.board.check_piece_fits(&self.tet) {
+            return;
+        }
+        let y = self.tet.y;
+        for _ in 0..rows {
+            self.tet.y += 1;
+            if self.board.check_piece_fits(&self.tet) {
+                return;
+            }
+        }
+        self.tet.y = y;
+        self.top_out();
+    }
+
+    //Only counts when the piece actually moved, not when it bumps a wall
+    fn move_piece(&mut self, dx: i8) {
+        let x = self.tet.x;
+        self.tet.move_piece(dx, 0, &self.board);
+        if self.tet.x != x {
+            self.last_rotated = false;
+            self.emit(GameEvent::Moved { dx, dy: 0 });
+        }
+    }
+
+    fn rotate_piece(&mut self, direction: i8) {
+        if let Some(kick) = self.tet.rotate_piece(direction, &self.board) {
+            self.last_rotated = true;
+            self.emit(GameEvent::Rotated { direction, kick });
+        }
+    }
+
+    //A T that got where it is by rotating, with three of the four corners around its centre filled.
+    //Walls and the floor count as filled.
+    fn is_t_spin(&self, before: &tetris_board::TetrisBoard) -> bool {
+        if !self.last_rotated || self.tet.rotation_constant != 3 || piece_set::active().piece(self.tet.kind).name != "T" {
+            return false;
+        }
+        let filled = |x: u8, y: u8| {
+            let (x, y) = (x as usize, y as usize);
+            x >= game_options::BOARD_WIDTH || y >= game_options::BOARD_HEIGHT || before.cell(x, y) > 0
+        };
+        let (x, y) = (self.tet.x, self.tet.y);
+        let corners = [(x, y), (x.wrapping_sub(2), y), (x, y.wrapping_sub(2)), (x.wrapping_sub(2), y.wrapping_sub(2))];
+        corners.iter().filter(|(cx, cy)| filled(*cx, *cy)).count() >= 3
+    }
+
+    fn top_out(&mut self) {
+        if self.topped_out {
+            return;
+        }
+        self.topped_out = true;
+        self.emit(GameEvent::TopOut);
+    }
+
+    fn piece_locked(&mut self, hard_drop: bool) {
+
+        let mut before = self.board.clone();
+        before.remove_piece_from_board(&self.tet);
+        self.finesse.piece_locked(&self.tet, &before, self.ticks);
+
+        let spin = self.is_t_spin(&before);
+        self.last_rotated = false;
+        self.emit(GameEvent::Locked { kind: self.tet.kind, cells: self.tet.cells(), hard_drop, spin });
+        //Rows are taken out a few ticks later but count as the piece locks, a piece locking before then can add to them
+        let rows: Vec<usize> = self.board.rows_to_clear().into_iter().filter(|r| !self.rows_announced.contains(r)).collect();
+        if !rows.is_empty() {
+            self.rows_announced.extend(rows.iter().copied());
+            self.emit(GameEvent::LinesCleared { rows, spin, combo: self.combo });
+            self.combo += 1;
+        }
+        else {
+            self.combo = 0;
+        }
+
+        //Garbage waits for a pending clear so the cleared rows don't move under it
+        if !self.board.did_zero {
+            self.apply_garbage();
+        }
+        self.pieces_placed += 1;
+        self.hold_used = false;
+        self.update_next_pieces();
+        if !self.board.check_piece_fits(&self.tet) {
+            self.top_out();
+        }
+        self.clear_tick = self.ticks + 20;
+    }
+
+    fn garbage_for_clear(lines: usize) -> u32 {
+        match lines {
+            0 | 1 => 0,
+            2 => 1,
+            3 => 2,
+            _ => 4,
+        }
+    }
+
+    pub fn receive_garbage(&mut self, lines: u32) {
+        self.pending_garbage += lines;
+    }
+
+    pub fn ticks(&self) -> u32 {
+        self.ticks
+    }
+
+    pub fn pending_garbage(&self) -> u32 {
+        self.pending_garbage
+    }
+
+    //Rows added
+    fn apply_garbage(&mut self) -> u32 {
+
+        let lines = self.pending_garbage;
+        if lines > 0 {
+            let hole = 1 + self.garbage_rng.gen::<usize>() % (game_options::BOARD_WIDTH - 2);
+            let kept = self.board.add_garbage(lines as usize, hole);
+            self.emit(GameEvent::GarbageReceived { lines, hole });
+            self.pending_garbage = 0;
+            if !kept {
+                self.top_out();
             }
         }
+        lines
+    }
+
+    pub fn restart(&mut self) {
+        self.restart_seeded(rand::random::<u64>());
+    }
+
+    //A fresh run from the seed, keeping the player's bindings, keys held, helpers and goal
+    pub fn restart_seeded(&mut self, seed: u64) {
+        let mut fresh = Game::new_player(self.bindings.clone(), self.origin, seed);
+        std::mem::swap(&mut fresh.inputs, &mut self.inputs);
+        fresh.left = self.left;
+        fresh.right = self.right;
+        fresh.scale = self.scale;
+        fresh.queue_actions = self.queue_actions;
+        fresh.bot = self.bot.take();
+        fresh.hints = self.hints.take();
+        fresh.pc_trainer = self.pc_trainer.take();
+        fresh.stats.assisted = fresh.hints.is_some() || fresh.pc_trainer.is_some();
+        fresh.goal = self.goal;
+        if self.recording.is_some() {
+            fresh.recording = Some(replay::Replay::new(seed, fresh.goal.name(), piece_set::active().fingerprint()));
+        }
+        *self = fresh;
+        if self.goal == goals::Goal::Dig {
+            self.lay_dig_garbage();
+        }
     }
 
     fn update_next_pieces(&mut self) {
         self.tet = tetromino::Tetromino::new_piece(self.next_pieces[0]);
+        self.emit(GameEvent::PieceSpawned { kind: self.tet.kind });
         for i in 0..5 {
             self.next_pieces[i] = self.next_pieces[i + 1]; 
         } 
-        self.next_pieces[5] =  rand::random::<u8>() % 7;
+        self.next_pieces[5] = Game::random_piece(&mut self.piece_rng);
+    }
+
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.board.hash(&mut hasher);
+        self.tet.hash(&mut hasher);
+        self.ticks.hash(&mut hasher);
+        self.clear_tick.hash(&mut hasher);
+        self.next_pieces.hash(&mut hasher);
+        self.topped_out.hash(&mut hasher);
+        self.pending_garbage.hash(&mut hasher);
+        self.hold.hash(&mut hasher);
+        self.hold_used.hash(&mut hasher);
+        self.pieces_placed.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    pub fn piece(&self) -> &tetromino::Tetromino {
+        &self.tet
+    }
+
+    pub fn board(&self) -> &tetris_board::TetrisBoard {
+        &self.board
+    }
+
+    pub fn next_pieces(&self) -> &[u8; 6] {
+        &self.next_pieces
+    }
+
+    pub fn hold_piece(&self) -> Option<u8> {
+        self.hold
+    }
+
+    pub fn can_hold(&self) -> bool {
+        !self.hold_used
+    }
+
+    pub fn pieces_placed(&self) -> u32 {
+        self.pieces_placed
+    }
+
+    pub fn lines_cleared(&self) -> u32 {
+        self.lines_cleared
+    }
+
+    //Up one every 10 lines
+    pub fn level(&self) -> u32 {
+        self.lines_cleared / 10 + 1
     }
 
     pub fn render(&mut self, r: &mut r_backend::Renderer, camera: &mut camera::Camera) {
 
-        self.board.render(r);
-        self.tet.render(r, &self.board);
+        self.board.render(r, self.origin, self.scale);
+        self.tet.render(r, &self.board, self.origin, self.scale);
+        if let Some(hint) = &self.hint {
+            hint.render_outline(r, self.origin, self.scale);
+        }
+        if let Some(trainer) = &self.pc_trainer {
+            trainer.render(r, self.origin, self.scale);
+        }
+        self.finesse.render(r, self.ticks, self.origin, self.scale);
         //println!("{:?}", camera.position);
         let speed = 0.03;
         if self.inputs.keys[VirtualKeyCode::T as usize] {
@@ -159,9 +686,181 @@ impl Game {
             camera.position.x -= 1.0 * speed;
         }
 
+        if let Some(kind) = self.hold {
+            let held = tetromino::Tetromino::new_piece(kind);
+            held.render_force_position(r, -5.0, 21.0, self.origin, self.scale);
+        }
+
         for i in 0..6 {
             let next_up = tetromino::Tetromino::new_piece(self.next_pieces[i]);
-            next_up.render_force_position(r, -5.0, 20.0 - (i as f32 * 3.0));
+            next_up.render_force_position(r, -5.0, 17.0 - (i as f32 * 3.0), self.origin, self.scale);
+        }
+
+        self.render_hud(r);
+    }
+
+    //Stats in a line under the board, and a solo goal's progress off its left side where
+    //there's only ever the one board
+    fn render_hud(&self, r: &mut r_backend::Renderer) {
+        let scale = self.scale;
+        let hud = format!("{} lines  PPS {:.2}  APM {:.1}", self.stats.lines, self.stats.pps(), self.stats.apm());
+        r.render_text(&hud, [self.origin[0] - 5.0 * scale, self.origin[1] - 0.1 * scale], 0.6 * scale, text::Align::Centre, [0.8, 0.8, 0.8, 1.0], -0.5, text::FONT.to_string());
+        if self.goal != goals::Goal::Endless {
+            let progress = format!("{}\n{}", self.goal.title(), self.goal.progress(self));
+            r.render_text(&progress, [self.origin[0] - 12.0 * scale, self.origin[1] + 20.0 * scale], scale, text::Align::Right, [1.0, 1.0, 1.0, 1.0], -0.5, text::FONT.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use key_bindings::Action;
+
+    //The first seed whose first piece is the given kind of the standard set
+    fn seed_for(kind: u8) -> u64 {
+        (0..).find(|seed| Game::new_player(key_bindings::KeyBindings::default_bindings(), [0.0, 0.0], *seed).piece().kind == kind).unwrap()
+    }
+
+    fn game_with(kind: u8) -> Game {
+        Game::new_player(key_bindings::KeyBindings::default_bindings(), [0.0, 0.0], seed_for(kind))
+    }
+
+    #[test]
+    fn hints_show_the_best_placement_and_mark_the_run() {
+        let mut game = game_with(5);
+        game.toggle_hints();
+        assert!(game.stats.assisted);
+        game.process_inputs([0, 0]);
+        let best = bot::best_placement(&game, &bot::BotWeights::load(bot::WEIGHTS_PATH)).unwrap();
+        assert_eq!(game.hint.as_ref().map(|h| h.cells()), Some(best.piece.cells()));
+        game.toggle_hints();
+        assert!(game.hint.is_none());
+        assert!(game.stats.assisted);
+    }
+
+    #[test]
+    fn clears_send_garbage_to_the_opponent() {
+        //Two rows with the same gap and an I stood up in it, a double sends one line
+        let bindings = [key_bindings::KeyBindings::default_bindings(), key_bindings::KeyBindings::player_two_bindings()];
+        let mut versus = crate::versus::Versus::new_seeded(bindings, seed_for(0));
+        let player = &mut versus.players[0];
+        player.apply_action(Action::RotateCw);
+        let column = player.piece().cells()[0].0;
+        player.board.add_garbage(2, column as usize);
+        player.apply_action(Action::HardDrop);
+        for _ in 0..30 {
+            versus.update();
+        }
+        assert_eq!(versus.players[0].lines_cleared(), 2);
+        assert_eq!(versus.players[1].pending_garbage(), 1);
+        assert_eq!(versus.players[0].pending_garbage(), 0);
+    }
+
+    #[test]
+    fn garbage_lifts_the_piece_in_play() {
+        let mut game = game_with(3);
+        for _ in 0..3 {
+            game.apply_action(Action::SoftDrop);
+        }
+        game.receive_garbage(17);
+        let lines = game.apply_garbage();
+        game.lift_piece(lines);
+        assert!(!game.topped_out);
+        assert!(game.board().check_piece_fits(game.piece()));
+        assert!(game.piece().cells().iter().all(|(_, y)| *y > 17));
+    }
+
+    #[test]
+    fn garbage_with_no_room_tops_out() {
+        let mut game = game_with(3);
+        game.receive_garbage(20);
+        let lines = game.apply_garbage();
+        game.lift_piece(lines);
+        assert!(game.topped_out);
+
+        //Pushing a locked piece off the top is just as final
+        let mut game = game_with(3);
+        game.apply_action(Action::HardDrop);
+        assert!(!game.topped_out);
+        game.receive_garbage(19);
+        game.apply_garbage();
+        assert!(game.topped_out);
+    }
+
+    #[test]
+    fn moves_and_rotations_are_events() {
+        let mut game = game_with(5);
+        assert_eq!(game.take_events(), vec![GameEvent::PieceSpawned { kind: 5 }]);
+        game.apply_action(Action::MoveLeft);
+        game.apply_action(Action::MoveRight);
+        game.apply_action(Action::SoftDrop);
+        game.apply_action(Action::RotateCw);
+        assert_eq!(game.take_events(), vec![
+            GameEvent::Moved { dx: 1, dy: 0 },
+            GameEvent::Moved { dx: -1, dy: 0 },
+            GameEvent::Moved { dx: 0, dy: -1 },
+            GameEvent::Rotated { direction: 1, kick: 0 },
+        ]);
+
+        //Bumping the wall isn't a move, and turning back from against it takes a kick
+        for _ in 0..10 {
+            game.apply_action(Action::MoveLeft);
+        }
+        assert_eq!(game.take_events().len(), 4);
+        game.apply_action(Action::RotateCcw);
+        assert_eq!(game.take_events(), vec![GameEvent::Rotated { direction: -1, kick: 1 }]);
+    }
+
+    #[test]
+    fn locking_and_clearing_are_events() {
+        //A row with one gap and an I stood up over it
+        let mut game = game_with(0);
+        game.apply_action(Action::RotateCw);
+        let column = game.piece().cells()[0].0;
+        game.board.add_garbage(1, column as usize);
+        let next = game.next_pieces()[0];
+        game.take_events();
+
+        game.apply_action(Action::HardDrop);
+        let cells: Vec<(u8, u8)> = (1..=4).map(|y| (column, y)).collect();
+        assert_eq!(game.take_events(), vec![
+            GameEvent::Locked { kind: 0, cells, hard_drop: true, spin: false },
+            GameEvent::LinesCleared { rows: vec![1], spin: false, combo: 0 },
+            GameEvent::PieceSpawned { kind: next },
+        ]);
+
+        //The row goes a few ticks later
+        for _ in 0..30 {
+            game.update();
+        }
+        assert_eq!(game.lines_cleared(), 1);
+        assert_eq!(game.board().garbage_rows(), 0);
+        let events: Vec<GameEvent> = game.take_events().into_iter().filter(|e| !matches!(e, GameEvent::Moved { .. })).collect();
+        assert_eq!(events, vec![GameEvent::ClearFinished { lines: 1 }]);
+    }
+
+    #[test]
+    fn incoming_garbage_is_an_event() {
+        let mut game = game_with(3);
+        game.receive_garbage(2);
+        game.take_events();
+        game.apply_action(Action::HardDrop);
+        //It comes in as the piece locks, or once the clear the lock started has finished
+        for _ in 0..30 {
+            game.update();
+        }
+        let events = game.take_events();
+        assert!(matches!(events[0], GameEvent::Locked { kind: 3, hard_drop: true, .. }));
+        let received: Vec<&GameEvent> = events.iter().filter(|e| matches!(e, GameEvent::GarbageReceived { .. })).collect();
+        match received[..] {
+            [GameEvent::GarbageReceived { lines: 2, hole }] => {
+                assert_eq!(game.board().cell(*hole, 1), 0);
+                assert_eq!(game.board().cell(*hole, 2), 0);
+            },
+            ref other => panic!("expected one lot of garbage, got {:?}", other),
         }
+        assert_eq!(game.pending_garbage(), 0);
+        assert_eq!(game.board().garbage_rows(), 2);
     }
 }
\ No newline at end of file
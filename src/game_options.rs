@@ -1,4 +1,6 @@
 pub const SCALE: f32 = 0.38;
+//Game ticks are 16 ms apart
+pub const TICKS_PER_SECOND: f32 = 1000.0 / 16.0;
 
 pub const BOARD_WIDTH: usize = 12;
 pub const BOARD_HEIGHT: usize = 22;
\ No newline at end of file
@@ -0,0 +1,160 @@
+use crate::game;
+use crate::game_options;
+
+/*
+What a solo run is played for. Endless goes on until the stack tops out, the others end on
+their own and each has its own high score table, named the same as the goal.
+marathon  150 lines, ranked by score
+sprint    40 lines, ranked by time
+ultra     two minutes, ranked by score
+dig       clear the 10 rows of garbage it starts with, ranked by time
+A sprint or dig that tops out first doesn't place, a marathon or ultra keeps its score.
+*/
+
+pub const MARATHON_LINES: u32 = 150;
+pub const SPRINT_LINES: u32 = 40;
+pub const ULTRA_TICKS: u32 = (120.0 * game_options::TICKS_PER_SECOND) as u32;
+pub const DIG_ROWS: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Goal {
+    Endless,
+    Marathon,
+    Sprint,
+    Ultra,
+    Dig,
+}
+
+pub const ALL_GOALS: [Goal; 5] = [Goal::Endless, Goal::Marathon, Goal::Sprint, Goal::Ultra, Goal::Dig];
+
+impl Goal {
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Goal::Endless => "endless",
+            Goal::Marathon => "marathon",
+            Goal::Sprint => "sprint",
+            Goal::Ultra => "ultra",
+            Goal::Dig => "dig",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Goal> {
+        ALL_GOALS.iter().copied().find(|g| g.name() == name)
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            Goal::Endless => "Endless",
+            Goal::Marathon => "Marathon",
+            Goal::Sprint => "Sprint",
+            Goal::Ultra => "Ultra",
+            Goal::Dig => "Dig",
+        }
+    }
+
+    pub fn next(&self) -> Goal {
+        let i = ALL_GOALS.iter().position(|g| g == self).unwrap_or(0);
+        ALL_GOALS[(i + 1) % ALL_GOALS.len()]
+    }
+
+    pub fn reached(&self, g: &game::Game) -> bool {
+        match self {
+            Goal::Endless => false,
+            Goal::Marathon => g.lines_cleared() >= MARATHON_LINES,
+            Goal::Sprint => g.lines_cleared() >= SPRINT_LINES,
+            Goal::Ultra => g.stats.ticks >= ULTRA_TICKS,
+            Goal::Dig => g.board().garbage_rows() == 0,
+        }
+    }
+
+    //What the run is ranked by, None if it ended without the goal that's timed
+    pub fn result(&self, g: &game::Game) -> Option<u32> {
+        match self {
+            //Endless play has no score of its own, it's ranked by lines
+            Goal::Endless => Some(g.stats.lines),
+            Goal::Marathon | Goal::Ultra => Some(g.stats.score),
+            Goal::Sprint | Goal::Dig if g.finished => Some(g.stats.ticks),
+            Goal::Sprint | Goal::Dig => None,
+        }
+    }
+
+    //How far along the run is, for next to the board
+    pub fn progress(&self, g: &game::Game) -> String {
+        match self {
+            Goal::Endless => format!("Level {}", g.level()),
+            Goal::Marathon => format!("{}/{} lines", g.lines_cleared().min(MARATHON_LINES), MARATHON_LINES),
+            Goal::Sprint => format!("{} lines left", SPRINT_LINES.saturating_sub(g.lines_cleared())),
+            Goal::Ultra => {
+                let left = ULTRA_TICKS.saturating_sub(g.stats.ticks) as f32 / game_options::TICKS_PER_SECOND;
+                format!("{}:{:02} left", (left / 60.0) as u32, left as u32 % 60)
+            },
+            Goal::Dig => format!("{} rows left", g.board().garbage_rows()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_bindings;
+    use crate::events::GameEvent;
+
+    fn game(goal: Goal) -> game::Game {
+        let mut g = game::Game::new_player(key_bindings::KeyBindings::default_bindings(), [0.0, 0.0], 9);
+        g.set_goal(goal);
+        g
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for goal in ALL_GOALS.iter() {
+            assert_eq!(Goal::from_name(goal.name()), Some(*goal));
+        }
+        assert_eq!(Goal::from_name("zen"), None);
+        let mut goal = Goal::Endless;
+        for _ in 0..ALL_GOALS.len() {
+            goal = goal.next();
+        }
+        assert_eq!(goal, Goal::Endless);
+    }
+
+    #[test]
+    fn dig_starts_with_its_garbage() {
+        let g = game(Goal::Dig);
+        assert_eq!(g.board().garbage_rows(), DIG_ROWS as usize);
+        assert_eq!(Goal::Dig.progress(&g), "10 rows left");
+        assert!(!Goal::Dig.reached(&g));
+        assert_eq!(game(Goal::Sprint).board().garbage_rows(), 0);
+    }
+
+    #[test]
+    fn ultra_stops_the_run_on_time() {
+        let mut g = game(Goal::Ultra);
+        assert_eq!(Goal::Ultra.progress(&g), "2:00 left");
+        g.stats.ticks = ULTRA_TICKS - 1;
+        g.update();
+        assert!(g.finished);
+        assert!(g.take_events().contains(&GameEvent::Finished));
+        let ticks = g.ticks();
+        g.update();
+        assert_eq!(g.ticks(), ticks);
+        assert_eq!(Goal::Ultra.progress(&g), "0:00 left");
+    }
+
+    #[test]
+    fn results_rank_by_the_goal() {
+        let mut g = game(Goal::Sprint);
+        g.stats.ticks = 3000;
+        g.stats.lines = 12;
+        g.stats.score = 900;
+        //A sprint that didn't get there has no time to rank
+        assert_eq!(Goal::Sprint.result(&g), None);
+        assert_eq!(Goal::Endless.result(&g), Some(12));
+        assert_eq!(Goal::Marathon.result(&g), Some(900));
+        assert_eq!(Goal::Ultra.result(&g), Some(900));
+        g.finished = true;
+        assert_eq!(Goal::Sprint.result(&g), Some(3000));
+        assert_eq!(Goal::Dig.result(&g), Some(3000));
+    }
+}
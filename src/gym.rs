@@ -0,0 +1,309 @@
+use crate::game;
+use crate::events::GameEvent;
+use crate::movegen;
+use crate::game_options;
+use crate::key_bindings;
+use crate::piece_set;
+
+/*
+Gym style environment for training agents. Everything the agent sees lives in one flat f32
+buffer that is rewritten in place after every step, laid out as
+board     FIELD_WIDTH * FIELD_HEIGHT occupancy, row 0 at the bottom, columns in board order
+          which runs right to left on screen, 1.0 filled
+piece     kind one hot, x and y scaled to 0..1, rotation one hot (4)
+queue     kind one hot for each of the next 6 pieces
+hold      kind one hot, then 1.0 if holding is allowed
+A one hot kind has a slot for every piece in the active set, 7 with the standard pieces.
+*/
+
+pub const FIELD_WIDTH: usize = game_options::BOARD_WIDTH - 2;
+pub const FIELD_HEIGHT: usize = game_options::BOARD_HEIGHT - 2;
+const QUEUE_LENGTH: usize = 6;
+
+pub const BOARD_OFFSET: usize = 0;
+pub const PIECE_OFFSET: usize = BOARD_OFFSET + FIELD_WIDTH * FIELD_HEIGHT;
+
+//Where each part of the observation starts, for a set with this many kinds of piece
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Layout {
+    pub kinds: usize,
+    pub queue_offset: usize,
+    pub hold_offset: usize,
+    pub length: usize,
+}
+
+impl Layout {
+
+    pub fn new(kinds: usize) -> Layout {
+        let queue_offset = PIECE_OFFSET + kinds + 2 + 4;
+        let hold_offset = queue_offset + kinds * QUEUE_LENGTH;
+        Layout { kinds, queue_offset, hold_offset, length: hold_offset + kinds + 1 }
+    }
+
+    //The layout for the pieces being played with now
+    pub fn active() -> Layout {
+        Layout::new(piece_set::active().len())
+    }
+}
+
+//Reward for each line cleared, indexed by how many went at once
+const LINE_REWARDS: [f32; 5] = [0.0, 1.0, 3.0, 5.0, 8.0];
+const TOP_OUT_REWARD: f32 = -10.0;
+//A placement index past the end of the list does nothing but costs this
+const ILLEGAL_ACTION_REWARD: f32 = -1.0;
+//A placement step plays the piece then waits out at most this many ticks for its line clear to finish
+const SETTLE_TICKS: u32 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GymAction {
+    //Does nothing for a tick, gravity still applies
+    Idle,
+    //One input then one tick
+    Raw(key_bindings::Action),
+    //Index into the placements listed by legal_actions, played out in full
+    Placement(usize),
+}
+
+pub struct Step<'a> {
+    pub observation: &'a [f32],
+    pub reward: f32,
+    pub done: bool,
+}
+
+pub struct Env {
+    game: game::Game,
+    placements: Vec<movegen::Move>,
+    layout: Layout,
+    observation: Vec<f32>,
+}
+
+impl Env {
+
+    pub fn new(seed: u64) -> Env {
+        let layout = Layout::active();
+        let mut env = Env {
+            game: game::Game::new_player(key_bindings::KeyBindings::default_bindings(), [0.0, 0.0], seed),
+            placements: Vec::new(),
+            layout,
+            observation: vec![0.0; layout.length],
+        };
+        env.placements = movegen::placements_with_hold(&env.game);
+        env.write_observation();
+        env
+    }
+
+    pub fn reset(&mut self, seed: u64) -> &[f32] {
+        *self = Env::new(seed);
+        &self.observation
+    }
+
+    pub fn game(&self) -> &game::Game {
+        &self.game
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    pub fn observation(&self) -> &[f32] {
+        &self.observation
+    }
+
+    //The placement actions line up with placements(), raw inputs come after them
+    pub fn legal_actions(&self) -> Vec<GymAction> {
+        let mut actions: Vec<GymAction> = (0..self.placements.len()).map(GymAction::Placement).collect();
+        actions.push(GymAction::Idle);
+        for action in key_bindings::ALL_ACTIONS.iter() {
+            if *action == key_bindings::Action::Pause {
+                continue;
+            }
+            if *action != key_bindings::Action::Hold || self.game.can_hold() {
+                actions.push(GymAction::Raw(*action));
+            }
+        }
+        actions
+    }
+
+    pub fn placements(&self) -> &[movegen::Move] {
+        &self.placements
+    }
+
+    pub fn step(&mut self, action: GymAction) -> Step<'_> {
+
+        let lines_before = self.game.lines_cleared();
+        let mut illegal = false;
+
+        if !self.game.topped_out {
+            match action {
+                GymAction::Idle => self.game.update(),
+                GymAction::Raw(a) => {
+                    self.game.apply_action(a);
+                    self.game.update();
+                },
+                GymAction::Placement(i) => {
+                    if let Some(m) = self.placements.get(i) {
+                        //Nothing else takes the game's events, so older ones are dropped here
+                        self.game.take_events();
+                        for a in m.path.clone() {
+                            self.game.apply_action(a);
+                        }
+                        //Let any line clear finish so the next observation is a settled board
+                        let mut clearing = self.game.take_events().iter().any(|e| matches!(e, GameEvent::LinesCleared { .. }));
+                        let mut ticks = 0;
+                        while clearing && !self.game.topped_out && ticks < SETTLE_TICKS {
+                            self.game.update();
+                            clearing = !self.game.take_events().iter().any(|e| matches!(e, GameEvent::ClearFinished { .. }));
+                            ticks += 1;
+                        }
+                    }
+                    else {
+                        illegal = true;
+                    }
+                },
+            }
+        }
+
+        let cleared = (self.game.lines_cleared() - lines_before) as usize;
+        let mut reward = LINE_REWARDS[cleared.min(4)];
+        if self.game.topped_out {
+            reward += TOP_OUT_REWARD;
+        }
+        if illegal {
+            reward += ILLEGAL_ACTION_REWARD;
+        }
+
+        //Any step can move the piece, so paths from where it was are stale
+        self.placements = movegen::placements_with_hold(&self.game);
+        self.write_observation();
+
+        Step { observation: &self.observation, reward, done: self.game.topped_out }
+    }
+
+    fn write_observation(&mut self) {
+
+        let layout = self.layout;
+        let obs = &mut self.observation;
+        for v in obs.iter_mut() {
+            *v = 0.0;
+        }
+
+        let board = self.game.board();
+        for y in 0..FIELD_HEIGHT {
+            for x in 0..FIELD_WIDTH {
+                if board.cell(x + 1, y + 1) > 0 {
+                    obs[BOARD_OFFSET + y * FIELD_WIDTH + x] = 1.0;
+                }
+            }
+        }
+
+        let piece = self.game.piece();
+        let kinds = layout.kinds;
+        obs[PIECE_OFFSET + piece.kind as usize % kinds] = 1.0;
+        obs[PIECE_OFFSET + kinds] = piece.x as f32 / game_options::BOARD_WIDTH as f32;
+        obs[PIECE_OFFSET + kinds + 1] = piece.y as f32 / game_options::BOARD_HEIGHT as f32;
+        obs[PIECE_OFFSET + kinds + 2 + piece.rotation() as usize % 4] = 1.0;
+
+        for (i, kind) in self.game.next_pieces().iter().take(QUEUE_LENGTH).enumerate() {
+            obs[layout.queue_offset + i * kinds + *kind as usize % kinds] = 1.0;
+        }
+
+        if let Some(kind) = self.game.hold_piece() {
+            obs[layout.hold_offset + kind as usize % kinds] = 1.0;
+        }
+        if self.game.can_hold() {
+            obs[layout.hold_offset + kinds] = 1.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use key_bindings::Action;
+
+    fn filled(env: &Env) -> Vec<(usize, usize)> {
+        let board = env.game().board();
+        let mut cells = Vec::new();
+        for y in 1..=FIELD_HEIGHT {
+            for x in 1..=FIELD_WIDTH {
+                if board.cell(x, y) > 0 {
+                    cells.push((x, y));
+                }
+            }
+        }
+        cells
+    }
+
+    #[test]
+    fn resets_the_same_way_for_a_seed() {
+        let mut env = Env::new(7);
+        let first = env.observation().to_vec();
+        let first_actions = env.legal_actions();
+        for _ in 0..5 {
+            env.step(GymAction::Placement(0));
+        }
+        assert_eq!(env.reset(7), &first[..]);
+        assert_eq!(env.legal_actions(), first_actions);
+        assert_ne!(Env::new(8).game().next_pieces(), env.game().next_pieces());
+    }
+
+    #[test]
+    fn observations_match_the_layout() {
+        let mut env = Env::new(3);
+        let layout = env.layout();
+        assert_eq!(layout, Layout::active());
+        assert_eq!(env.observation().len(), layout.length);
+        assert_eq!(env.step(GymAction::Idle).observation.len(), layout.length);
+        assert_eq!(env.step(GymAction::Placement(0)).observation.len(), layout.length);
+        assert_eq!(env.step(GymAction::Raw(Action::MoveLeft)).observation.len(), layout.length);
+    }
+
+    #[test]
+    fn placements_lock_where_they_say() {
+        let count = Env::new(11).placements().len();
+        assert!(count > 0);
+        for i in 0..count {
+            let mut env = Env::new(11);
+            let mut expected: Vec<(usize, usize)> = env.placements()[i].piece.cells().iter()
+                .map(|&(x, y)| (x as usize, y as usize)).collect();
+            expected.sort();
+            let step = env.step(GymAction::Placement(i));
+            assert!(!step.done);
+            let mut cells = filled(&env);
+            cells.sort();
+            assert_eq!(cells, expected, "placement {}", i);
+        }
+    }
+
+    #[test]
+    fn topping_out_ends_the_episode() {
+        let mut env = Env::new(5);
+        let mut steps = 0;
+        loop {
+            let step = env.step(GymAction::Raw(Action::HardDrop));
+            if step.done {
+                assert!(step.reward <= TOP_OUT_REWARD);
+                break;
+            }
+            steps += 1;
+            assert!(steps < 100, "never topped out");
+        }
+        assert!(env.game().topped_out);
+        //Nothing more happens once it's over
+        let board = filled(&env);
+        assert!(env.step(GymAction::Placement(0)).done);
+        assert_eq!(filled(&env), board);
+    }
+
+    #[test]
+    fn bad_placements_are_penalised() {
+        let mut env = Env::new(9);
+        let before = env.observation().to_vec();
+        let count = env.placements().len();
+        let step = env.step(GymAction::Placement(count));
+        assert_eq!(step.reward, ILLEGAL_ACTION_REWARD);
+        assert!(!step.done);
+        assert_eq!(env.observation(), &before[..]);
+        assert!(filled(&env).is_empty());
+    }
+}
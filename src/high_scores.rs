@@ -0,0 +1,273 @@
+use crate::stats;
+use crate::game_options;
+use crate::replay;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/*
+Top runs for each mode, all in one json file keyed by mode name. Score modes keep the highest
+values, time modes the lowest, with time stored in ticks. Each entry has the path of the replay
+saved with it, removed again when the run falls off the table. A file that can't be read is moved
+to HIGH_SCORES_PATH.bak and the tables start over empty.
+*/
+
+pub const HIGH_SCORES_PATH: &str = "./high_scores.json";
+pub const TABLE_SIZE: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ranking {
+    //Higher is better
+    Score,
+    //Lower is better
+    Time,
+}
+
+pub fn ranking_for(mode: &str) -> Ranking {
+    match mode {
+        "sprint" | "dig" => Ranking::Time,
+        _ => Ranking::Score,
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub name: String,
+    pub date: String,
+    pub value: u32,
+    pub stats: stats::Stats,
+    pub replay: Option<String>,
+}
+
+impl Entry {
+
+    pub fn new(value: u32, stats: &stats::Stats) -> Entry {
+        Entry { name: player_name(), date: today(), value, stats: stats.clone(), replay: None }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HighScores {
+    tables: BTreeMap<String, Vec<Entry>>,
+}
+
+//--player <name>, otherwise the login name
+pub fn player_name() -> String {
+    let args: Vec<String> = std::env::args().collect();
+    args.iter().position(|a| a == "--player").and_then(|i| args.get(i + 1).cloned())
+        .or_else(|| std::env::var("USER").ok())
+        .or_else(|| std::env::var("USERNAME").ok())
+        .unwrap_or_else(|| "Player".to_string())
+}
+
+//UTC date as year-month-day, from days since 1970 using Howard Hinnant's civil_from_days
+pub fn today() -> String {
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() / 86400).unwrap_or(0) as i64;
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+impl HighScores {
+
+    pub fn load(path: &str) -> HighScores {
+
+        //Not there yet is the only failure that isn't worth keeping the file for, anything else
+        //(bad json, not utf-8, unreadable) is moved out of the way before it can be saved over
+        let error = match fs::read_to_string(path) {
+            Ok(contents) => match serde_json::from_str(&contents) {
+                Ok(scores) => return scores,
+                Err(e) => e.to_string(),
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return HighScores::default(),
+            Err(e) => e.to_string(),
+        };
+        let backup = format!("{}.bak", path);
+        match fs::rename(path, &backup) {
+            Ok(_) => println!("High score file is damaged ({}), moved it to {} and started over", error, backup),
+            Err(re) => println!("High score file is damaged ({}) and couldn't be moved: {:?}", error, re),
+        }
+        HighScores::default()
+    }
+
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let contents = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        fs::write(path, contents)
+    }
+
+    pub fn table(&self, mode: &str) -> &[Entry] {
+        self.tables.get(mode).map(|t| t.as_slice()).unwrap_or(&[])
+    }
+
+    //Index a run with this value would go in at, past the end if it wouldn't make the table.
+    //Ties go below older runs.
+    fn position(&self, mode: &str, value: u32) -> usize {
+        let ranking = ranking_for(mode);
+        let table = self.table(mode);
+        table.iter().position(|e| match ranking {
+            Ranking::Score => value > e.value,
+            Ranking::Time => value < e.value,
+        }).unwrap_or(table.len())
+    }
+
+    pub fn would_place(&self, mode: &str, value: u32) -> bool {
+        self.position(mode, value) < TABLE_SIZE
+    }
+
+    //Place in the table counting from 1, or None if it didn't make it. Whatever falls off the
+    //bottom is returned too, so its replay can go with it.
+    pub fn submit(&mut self, mode: &str, entry: Entry) -> (Option<usize>, Vec<Entry>) {
+
+        let place = self.position(mode, entry.value);
+        if place >= TABLE_SIZE {
+            return (None, Vec::new());
+        }
+        let table = self.tables.entry(mode.to_string()).or_default();
+        table.insert(place, entry);
+        let dropped = if table.len() > TABLE_SIZE { table.split_off(TABLE_SIZE) } else { Vec::new() };
+        (Some(place + 1), dropped)
+    }
+}
+
+pub fn format_value(mode: &str, value: u32) -> String {
+    match ranking_for(mode) {
+        Ranking::Score => value.to_string(),
+        Ranking::Time => {
+            let seconds = value as f32 / game_options::TICKS_PER_SECOND;
+            format!("{}:{:05.2}", (seconds / 60.0) as u32, seconds % 60.0)
+        },
+    }
+}
+
+//Saves the run and prints the table with where it landed. The replay is only written out if
+//the run makes the table.
+pub fn record(mode: &str, mut entry: Entry, replay: Option<&replay::Replay>) -> Option<usize> {
+
+    let mut scores = HighScores::load(HIGH_SCORES_PATH);
+    if let (true, Some(r)) = (scores.would_place(mode, entry.value), replay) {
+        let finished = replay::Replay { stats: Some(entry.stats.clone()), ..r.clone() };
+        match finished.save() {
+            Ok(path) => entry.replay = Some(path),
+            Err(e) => println!("{:#}", e),
+        }
+    }
+    let (place, dropped) = scores.submit(mode, entry);
+    match place {
+        Some(p) => {
+            println!("New high score, #{} in {}", p, mode);
+            if let Err(e) = scores.save(HIGH_SCORES_PATH) {
+                println!("Couldn't save high scores: {:?}", e);
+            }
+            for path in dropped.iter().filter_map(|e| e.replay.as_ref()) {
+                if let Err(e) = fs::remove_file(path) {
+                    println!("Couldn't remove replay {}: {}", path, e);
+                }
+            }
+        },
+        None => println!("Didn't make the {} table", mode),
+    }
+
+    for (i, e) in scores.table(mode).iter().enumerate() {
+        let marker = if Some(i + 1) == place { ">" } else { " " };
+        println!("{}{:2}. {:<16} {:>8}  {}  PPS {:.2}{}", marker, i + 1, e.name, format_value(mode, e.value), e.date, e.stats.pps(), if e.stats.assisted { "  assisted" } else { "" });
+    }
+    place
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, value: u32) -> Entry {
+        Entry { name: name.to_string(), date: "2024-01-01".to_string(), value, stats: stats::Stats::new(), replay: None }
+    }
+
+    fn names(scores: &HighScores, mode: &str) -> Vec<String> {
+        scores.table(mode).iter().map(|e| e.name.clone()).collect()
+    }
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("tetris-scores-{}-{}.json", name, std::process::id())).to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn score_tables_keep_the_highest() {
+        let mut scores = HighScores::default();
+        assert_eq!(scores.submit("marathon", entry("a", 100)).0, Some(1));
+        assert_eq!(scores.submit("marathon", entry("b", 300)).0, Some(1));
+        assert_eq!(scores.submit("marathon", entry("c", 200)).0, Some(2));
+        //A tie goes below the run already there
+        assert_eq!(scores.submit("marathon", entry("d", 200)).0, Some(3));
+        assert_eq!(names(&scores, "marathon"), vec!["b", "c", "d", "a"]);
+        assert!(scores.table("sprint").is_empty());
+    }
+
+    #[test]
+    fn time_tables_keep_the_lowest() {
+        let mut scores = HighScores::default();
+        scores.submit("sprint", entry("slow", 5000));
+        scores.submit("sprint", entry("fast", 3000));
+        assert_eq!(names(&scores, "sprint"), vec!["fast", "slow"]);
+        assert!(scores.would_place("sprint", 9000));
+    }
+
+    #[test]
+    fn full_tables_drop_the_last() {
+        let mut scores = HighScores::default();
+        for i in 0..TABLE_SIZE as u32 {
+            scores.submit("ultra", entry(&i.to_string(), (i + 1) * 10));
+        }
+        assert!(!scores.would_place("ultra", 10));
+        let (place, dropped) = scores.submit("ultra", entry("low", 5));
+        assert_eq!(place, None);
+        assert!(dropped.is_empty());
+        let (place, dropped) = scores.submit("ultra", entry("high", 1000));
+        assert_eq!(place, Some(1));
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].value, 10);
+        assert_eq!(scores.table("ultra").len(), TABLE_SIZE);
+    }
+
+    #[test]
+    fn formats_by_ranking() {
+        assert_eq!(format_value("marathon", 1234), "1234");
+        //A tick is 16ms
+        assert_eq!(format_value("sprint", 5000), "1:20.00");
+        assert_eq!(format_value("dig", 125), "0:02.00");
+        assert_eq!(ranking_for("dig"), Ranking::Time);
+        assert_eq!(ranking_for("endless"), Ranking::Score);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = temp_path("save");
+        let mut scores = HighScores::default();
+        scores.submit("dig", entry("a", 4000));
+        scores.save(&path).unwrap();
+        let loaded = HighScores::load(&path);
+        assert_eq!(names(&loaded, "dig"), vec!["a"]);
+        assert_eq!(loaded.table("dig")[0].value, 4000);
+        fs::remove_file(&path).unwrap();
+        assert!(HighScores::load(&path).table("dig").is_empty());
+    }
+
+    #[test]
+    fn damaged_file_is_backed_up() {
+        let path = temp_path("damaged");
+        let backup = format!("{}.bak", path);
+        fs::write(&path, [0xff, 0xfe, b'{']).unwrap();
+        assert!(HighScores::load(&path).table("marathon").is_empty());
+        assert!(!std::path::Path::new(&path).exists());
+        assert_eq!(fs::read(&backup).unwrap(), vec![0xff, 0xfe, b'{']);
+        fs::remove_file(&backup).unwrap();
+    }
+}
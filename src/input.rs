@@ -1,9 +1,11 @@
 use winit::event::*;
 
+#[derive(Clone)]
 pub struct Inputs {
     pub keys: [bool; 1024],
     pub mouse_pos: [f32; 2],
     pub mouse_btns: [bool; 128],
+    pub last_pressed: Option<VirtualKeyCode>,
 }
 
 impl Inputs {
@@ -12,7 +14,20 @@ impl Inputs {
         let keys: [bool; 1024] = [false; 1024];
         let mouse_pos: [f32; 2] = [0.0, 0.0];
         let mouse_btns: [bool; 128] = [false; 128];
-        Inputs { keys, mouse_pos, mouse_btns }
+        let last_pressed = None;
+        Inputs { keys, mouse_pos, mouse_btns, last_pressed }
+    }
+
+    //Hands out the most recent key press once, used when capturing a key to rebind
+    pub fn take_last_pressed(&mut self) -> Option<VirtualKeyCode> {
+        self.last_pressed.take()
+    }
+
+    //For when the window loses focus and the releases will never come
+    pub fn release_all(&mut self) {
+        self.keys = [false; 1024];
+        self.mouse_btns = [false; 128];
+        self.last_pressed = None;
     }
 
     pub fn input(&mut self, event: &WindowEvent) {
@@ -31,6 +46,9 @@ impl Inputs {
                 if *keycode as usize >= 1024 {
                     println!("Key out of range {:?}", *keycode);
                 }
+                if pressed && !self.keys[*keycode as usize] {
+                    self.last_pressed = Some(*keycode);
+                }
                 self.keys[*keycode as usize] = pressed;
             }
             WindowEvent::CursorMoved  { position, .. } => {
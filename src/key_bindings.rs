@@ -0,0 +1,322 @@
+use winit::event::VirtualKeyCode;
+
+use std::fs;
+
+pub const BINDINGS_PATH: &str = "./bindings.cfg";
+pub const BINDINGS_PATH_P2: &str = "./bindings_p2.cfg";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    SoftDrop,
+    HardDrop,
+    RotateCw,
+    RotateCcw,
+    Hold,
+    //Handled by the scene the game is in, never reaches the game itself
+    Pause,
+}
+
+pub const NUM_ACTIONS: usize = 8;
+
+pub const ALL_ACTIONS: [Action; NUM_ACTIONS] = [
+    Action::MoveLeft,
+    Action::MoveRight,
+    Action::SoftDrop,
+    Action::HardDrop,
+    Action::RotateCw,
+    Action::RotateCcw,
+    Action::Hold,
+    Action::Pause,
+];
+
+impl Action {
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::MoveLeft => "move_left",
+            Action::MoveRight => "move_right",
+            Action::SoftDrop => "soft_drop",
+            Action::HardDrop => "hard_drop",
+            Action::RotateCw => "rotate_cw",
+            Action::RotateCcw => "rotate_ccw",
+            Action::Hold => "hold",
+            Action::Pause => "pause",
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        ALL_ACTIONS.iter().position(|a| a == self).unwrap()
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        ALL_ACTIONS.iter().copied().find(|a| a.name() == name)
+    }
+}
+
+//Keys that can be written to and read back from the bindings file
+const BINDABLE_KEYS: [VirtualKeyCode; 62] = [
+    VirtualKeyCode::A, VirtualKeyCode::B, VirtualKeyCode::C, VirtualKeyCode::D, VirtualKeyCode::E,
+    VirtualKeyCode::F, VirtualKeyCode::G, VirtualKeyCode::H, VirtualKeyCode::I, VirtualKeyCode::J,
+    VirtualKeyCode::K, VirtualKeyCode::L, VirtualKeyCode::M, VirtualKeyCode::N, VirtualKeyCode::O,
+    VirtualKeyCode::P, VirtualKeyCode::Q, VirtualKeyCode::R, VirtualKeyCode::S, VirtualKeyCode::T,
+    VirtualKeyCode::U, VirtualKeyCode::V, VirtualKeyCode::W, VirtualKeyCode::X, VirtualKeyCode::Y,
+    VirtualKeyCode::Z,
+    VirtualKeyCode::Key0, VirtualKeyCode::Key1, VirtualKeyCode::Key2, VirtualKeyCode::Key3, VirtualKeyCode::Key4,
+    VirtualKeyCode::Key5, VirtualKeyCode::Key6, VirtualKeyCode::Key7, VirtualKeyCode::Key8, VirtualKeyCode::Key9,
+    VirtualKeyCode::Numpad0, VirtualKeyCode::Numpad1, VirtualKeyCode::Numpad2, VirtualKeyCode::Numpad3, VirtualKeyCode::Numpad4,
+    VirtualKeyCode::Numpad5, VirtualKeyCode::Numpad6, VirtualKeyCode::Numpad7, VirtualKeyCode::Numpad8, VirtualKeyCode::Numpad9,
+    VirtualKeyCode::Left, VirtualKeyCode::Right, VirtualKeyCode::Up, VirtualKeyCode::Down,
+    VirtualKeyCode::Space, VirtualKeyCode::Tab,
+    VirtualKeyCode::LShift, VirtualKeyCode::RShift, VirtualKeyCode::LControl, VirtualKeyCode::RControl,
+    VirtualKeyCode::LAlt, VirtualKeyCode::RAlt,
+    VirtualKeyCode::Comma, VirtualKeyCode::Period, VirtualKeyCode::Slash, VirtualKeyCode::Semicolon,
+];
+
+pub fn key_name(key: VirtualKeyCode) -> String {
+    format!("{:?}", key)
+}
+
+pub fn key_from_name(name: &str) -> Option<VirtualKeyCode> {
+    BINDABLE_KEYS.iter().copied().find(|k| key_name(*k) == name)
+}
+
+pub fn is_bindable(key: VirtualKeyCode) -> bool {
+    BINDABLE_KEYS.contains(&key)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyBindings {
+    keys: [VirtualKeyCode; NUM_ACTIONS],
+}
+
+impl KeyBindings {
+
+    pub fn default_bindings() -> KeyBindings {
+        KeyBindings {
+            keys: [
+                VirtualKeyCode::Left,
+                VirtualKeyCode::Right,
+                VirtualKeyCode::Down,
+                VirtualKeyCode::Space,
+                VirtualKeyCode::X,
+                VirtualKeyCode::Z,
+                VirtualKeyCode::C,
+                VirtualKeyCode::P,
+            ],
+        }
+    }
+
+    pub fn player_two_bindings() -> KeyBindings {
+        KeyBindings {
+            keys: [
+                VirtualKeyCode::A,
+                VirtualKeyCode::D,
+                VirtualKeyCode::S,
+                VirtualKeyCode::W,
+                VirtualKeyCode::E,
+                VirtualKeyCode::Q,
+                VirtualKeyCode::R,
+                VirtualKeyCode::Tab,
+            ],
+        }
+    }
+
+    //Where each player's bindings live and what they start as, player 0 first
+    pub fn for_player(player: usize) -> (&'static str, KeyBindings) {
+        match player {
+            0 => (BINDINGS_PATH, KeyBindings::default_bindings()),
+            _ => (BINDINGS_PATH_P2, KeyBindings::player_two_bindings()),
+        }
+    }
+
+    pub fn load_player(player: usize) -> KeyBindings {
+        let (path, defaults) = KeyBindings::for_player(player);
+        KeyBindings::load(path, defaults)
+    }
+
+    //Falls back to the defaults for anything missing from the file
+    pub fn load(path: &str, defaults: KeyBindings) -> KeyBindings {
+
+        let contents = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(_) => {
+                println!("No bindings file at {}, using defaults", path);
+                return defaults;
+            }
+        };
+
+        let mut from_file: [Option<VirtualKeyCode>; NUM_ACTIONS] = [None; NUM_ACTIONS];
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(2, '=');
+            let action = parts.next().map(|s| s.trim()).and_then(Action::from_name);
+            let key = parts.next().map(|s| s.trim()).and_then(key_from_name);
+            match (action, key) {
+                (Some(action), Some(key)) => {
+                    if from_file.contains(&Some(key)) {
+                        println!("Bindings file binds {} more than once, ignoring {}", key_name(key), action.name());
+                        continue;
+                    }
+                    from_file[action.index()] = Some(key);
+                },
+                _ => println!("Bad line in bindings file: {}", line),
+            }
+        }
+
+        //Anything the file leaves out, like actions added since it was written, gets its default
+        //key or the first free one if the file already uses that
+        let mut bindings = defaults.clone();
+        for action in ALL_ACTIONS.iter() {
+            if let Some(key) = from_file[action.index()] {
+                bindings.keys[action.index()] = key;
+            }
+        }
+        for action in ALL_ACTIONS.iter() {
+            if from_file[action.index()].is_some() {
+                continue;
+            }
+            let taken = |key: VirtualKeyCode| ALL_ACTIONS.iter().any(|a| a != action && bindings.key(*a) == key);
+            let default = defaults.key(*action);
+            let key = if !taken(default) {
+                default
+            }
+            else {
+                //Staying off both players' defaults so it won't clash with the other player either,
+                //and player two looks from the other end so they don't both land on the same key
+                let spare = |key: VirtualKeyCode| [KeyBindings::default_bindings(), KeyBindings::player_two_bindings()].iter().all(|b| b.action_for(key).is_none());
+                let mut keys: Vec<VirtualKeyCode> = BINDABLE_KEYS.to_vec();
+                if defaults != KeyBindings::default_bindings() {
+                    keys.reverse();
+                }
+                match keys.into_iter().find(|k| !taken(*k) && spare(*k)) {
+                    Some(k) => k,
+                    None => return defaults,
+                }
+            };
+            if key != default {
+                println!("Bindings file already uses {}, {} is on {}", key_name(default), action.name(), key_name(key));
+            }
+            bindings.keys[action.index()] = key;
+        }
+
+        bindings
+    }
+
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+
+        let mut contents = String::new();
+        for action in ALL_ACTIONS.iter() {
+            contents.push_str(&format!("{}={}\n", action.name(), key_name(self.key(*action))));
+        }
+        fs::write(path, contents)
+    }
+
+    pub fn key(&self, action: Action) -> VirtualKeyCode {
+        self.keys[action.index()]
+    }
+
+    pub fn action_for(&self, key: VirtualKeyCode) -> Option<Action> {
+        ALL_ACTIONS.iter().copied().find(|a| self.key(*a) == key)
+    }
+
+    //Returns the action already using the key if there is one, and leaves the bindings unchanged
+    pub fn rebind(&mut self, action: Action, key: VirtualKeyCode) -> Result<(), Action> {
+
+        match self.action_for(key) {
+            Some(other) if other != action => Err(other),
+            _ => {
+                self.keys[action.index()] = key;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //A file of its own per test so they can run side by side
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("tetris-bindings-{}-{}.cfg", name, std::process::id())).to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for action in ALL_ACTIONS.iter() {
+            assert_eq!(Action::from_name(action.name()), Some(*action));
+        }
+        for key in BINDABLE_KEYS.iter() {
+            assert_eq!(key_from_name(&key_name(*key)), Some(*key));
+        }
+        assert_eq!(Action::from_name("jump"), None);
+        assert!(!is_bindable(VirtualKeyCode::Escape));
+    }
+
+    #[test]
+    fn defaults_dont_clash() {
+        let one = KeyBindings::default_bindings();
+        let two = KeyBindings::player_two_bindings();
+        for action in ALL_ACTIONS.iter() {
+            assert_eq!(one.action_for(one.key(*action)), Some(*action));
+            assert_eq!(two.action_for(two.key(*action)), Some(*action));
+            assert_eq!(two.action_for(one.key(*action)), None);
+        }
+    }
+
+    #[test]
+    fn rebind_refuses_a_used_key() {
+        let mut bindings = KeyBindings::default_bindings();
+        assert_eq!(bindings.rebind(Action::Hold, VirtualKeyCode::Space), Err(Action::HardDrop));
+        assert_eq!(bindings.key(Action::Hold), VirtualKeyCode::C);
+        assert_eq!(bindings.rebind(Action::Hold, VirtualKeyCode::V), Ok(()));
+        assert_eq!(bindings.action_for(VirtualKeyCode::V), Some(Action::Hold));
+        assert_eq!(bindings.rebind(Action::Hold, VirtualKeyCode::V), Ok(()));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = temp_path("save");
+        let mut bindings = KeyBindings::default_bindings();
+        bindings.rebind(Action::Hold, VirtualKeyCode::LShift).unwrap();
+        bindings.save(&path).unwrap();
+        assert_eq!(KeyBindings::load(&path, KeyBindings::default_bindings()), bindings);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn missing_file_gives_defaults() {
+        let path = temp_path("missing");
+        assert_eq!(KeyBindings::load(&path, KeyBindings::player_two_bindings()), KeyBindings::player_two_bindings());
+    }
+
+    #[test]
+    fn bad_and_repeated_lines_are_skipped() {
+        let path = temp_path("bad");
+        fs::write(&path, "# comment\nmove_left=J\njump=K\nhold=nothing\nmove_right=J\n").unwrap();
+        let bindings = KeyBindings::load(&path, KeyBindings::default_bindings());
+        assert_eq!(bindings.key(Action::MoveLeft), VirtualKeyCode::J);
+        assert_eq!(bindings.key(Action::MoveRight), VirtualKeyCode::Right);
+        assert_eq!(bindings.key(Action::Hold), VirtualKeyCode::C);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn missing_action_gets_a_free_key() {
+        //A file from before pause existed, with P already on hold
+        let path = temp_path("free");
+        fs::write(&path, "move_left=Left\nmove_right=Right\nsoft_drop=Down\nhard_drop=Space\nrotate_cw=X\nrotate_ccw=Z\nhold=P\n").unwrap();
+        let bindings = KeyBindings::load(&path, KeyBindings::default_bindings());
+        let pause = bindings.key(Action::Pause);
+        assert_eq!(bindings.key(Action::Hold), VirtualKeyCode::P);
+        assert_ne!(pause, VirtualKeyCode::P);
+        assert_eq!(bindings.action_for(pause), Some(Action::Pause));
+        assert_eq!(KeyBindings::player_two_bindings().action_for(pause), None);
+        fs::remove_file(&path).unwrap();
+    }
+}
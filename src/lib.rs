@@ -0,0 +1,41 @@
+#![allow(unused_variables)]
+#![allow(unused_imports)]
+
+//The engine as a library so tools like the tournament runner can use it without a window
+pub mod texture;
+pub mod r_state;
+pub mod r_render_pipeline;
+pub mod camera;
+pub mod uniform;
+pub mod game;
+pub mod input;
+pub mod r_backend;
+pub mod tetromino;
+pub mod game_options;
+pub mod tetris_board;
+pub mod key_bindings;
+pub mod controls_menu;
+pub mod versus;
+pub mod net;
+pub mod rollback;
+pub mod play_mode;
+pub mod lobby;
+pub mod bot;
+pub mod movegen;
+pub mod tbp;
+pub mod gym;
+pub mod pc_solver;
+pub mod finesse;
+pub mod stats;
+pub mod high_scores;
+pub mod piece_set;
+pub mod polyomino;
+pub mod settings;
+pub mod skins;
+pub mod text;
+pub mod scene;
+pub mod audio;
+pub mod music;
+pub mod events;
+pub mod goals;
+pub mod replay;
@@ -0,0 +1,291 @@
+use crate::input;
+use crate::r_backend;
+use crate::key_bindings;
+use crate::game_options;
+use crate::skins;
+use crate::text;
+
+use anyhow::{Context, Result};
+use winit::event::*;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+pub const DISCOVERY_PORT: u16 = 47474;
+const MAGIC: &str = "tetris-lobby";
+const ADVERTISE_EVERY: Duration = Duration::from_millis(500);
+//A lobby that hasn't been heard from in this long has closed or started
+const LOBBY_TIMEOUT: Duration = Duration::from_secs(3);
+//Longer names are cut short so the row fits on screen
+const NAME_LENGTH: usize = 20;
+//Each player gets a slot drawn in the row, a packet claiming more than this isn't one of ours
+const MAX_PLAYERS: u32 = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LobbyInfo {
+    pub id: u64,
+    pub name: String,
+    pub mode: String,
+    pub players: u32,
+    pub max_players: u32,
+    pub port: u16,
+}
+
+impl LobbyInfo {
+
+    //tetris-lobby <id> <game port> <players> <max players> <mode> <name, may have spaces>
+    pub fn encode(&self) -> String {
+        format!("{} {} {} {} {} {} {}", MAGIC, self.id, self.port, self.players, self.max_players, self.mode, self.name)
+    }
+
+    pub fn decode(text: &str) -> Option<LobbyInfo> {
+
+        let mut parts = text.splitn(7, ' ');
+        if parts.next()? != MAGIC {
+            return None;
+        }
+        let id = parts.next()?.parse().ok()?;
+        let port = parts.next()?.parse().ok()?;
+        let players = parts.next()?.parse().ok()?;
+        let max_players = parts.next()?.parse().ok()?;
+        if max_players == 0 || max_players > MAX_PLAYERS || players > max_players {
+            return None;
+        }
+        let mode = parts.next()?.to_string();
+        let name = parts.next().unwrap_or("").to_string();
+        Some(LobbyInfo { id, name, mode, players, max_players, port })
+    }
+}
+
+pub struct Advertiser {
+    socket: UdpSocket,
+    pub info: LobbyInfo,
+    last_sent: Option<Instant>,
+}
+
+impl Advertiser {
+
+    pub fn new(info: LobbyInfo) -> Result<Advertiser> {
+
+        let socket = UdpSocket::bind(("0.0.0.0", 0)).context("Unable to open lobby socket")?;
+        socket.set_broadcast(true)?;
+        Ok(Advertiser { socket, info, last_sent: None })
+    }
+
+    pub fn update(&mut self) {
+
+        if let Some(last) = self.last_sent {
+            if last.elapsed() < ADVERTISE_EVERY {
+                return;
+            }
+        }
+        self.last_sent = Some(Instant::now());
+
+        //Loopback as well as broadcast so a browser on the same machine always hears it
+        let message = self.info.encode();
+        for ip in [Ipv4Addr::BROADCAST, Ipv4Addr::LOCALHOST].iter() {
+            if let Err(e) = self.socket.send_to(message.as_bytes(), (*ip, DISCOVERY_PORT)) {
+                println!("Couldn't advertise lobby to {}: {}", ip, e);
+            }
+        }
+    }
+}
+
+pub struct Lobby {
+    pub info: LobbyInfo,
+    pub ip: IpAddr,
+    last_seen: Instant,
+}
+
+impl Lobby {
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.ip, self.info.port)
+    }
+}
+
+pub struct LobbyBrowser {
+    socket: UdpSocket,
+    inputs: input::Inputs,
+    pub lobbies: Vec<Lobby>,
+    selected: usize,
+    join: Option<(SocketAddr, String)>,
+    pub bindings: key_bindings::KeyBindings,
+}
+
+impl LobbyBrowser {
+
+    pub fn new(bindings: key_bindings::KeyBindings) -> Result<LobbyBrowser> {
+
+        let socket = UdpSocket::bind(("0.0.0.0", DISCOVERY_PORT)).context("Unable to listen for lobbies, is another browser open?")?;
+        socket.set_nonblocking(true)?;
+        println!("Looking for games on the local network (Up/Down select, Enter join)");
+
+        Ok(LobbyBrowser {
+            socket,
+            inputs: input::Inputs::new(),
+            lobbies: Vec::new(),
+            selected: 0,
+            join: None,
+            bindings,
+        })
+    }
+
+    pub fn input(&mut self, event: &WindowEvent) {
+        self.inputs.input(event);
+    }
+
+    pub fn process_inputs(&mut self) {
+
+        let key = match self.inputs.take_last_pressed() {
+            Some(k) => k,
+            None => return,
+        };
+
+        if self.lobbies.is_empty() {
+            return;
+        }
+
+        match key {
+            VirtualKeyCode::Up => {
+                self.selected = (self.selected + self.lobbies.len() - 1) % self.lobbies.len();
+                self.print();
+            },
+            VirtualKeyCode::Down => {
+                self.selected = (self.selected + 1) % self.lobbies.len();
+                self.print();
+            },
+            VirtualKeyCode::Return => {
+                let lobby = &self.lobbies[self.selected];
+                if lobby.info.players >= lobby.info.max_players {
+                    println!("{} is full", lobby.info.name);
+                }
+                else {
+                    self.join = Some((lobby.addr(), lobby.info.mode.clone()));
+                }
+            },
+            _ => (),
+        }
+    }
+
+    pub fn update(&mut self) {
+
+        let mut changed = false;
+        let mut buf = [0u8; 512];
+        loop {
+            match self.socket.recv_from(&mut buf) {
+                Ok((n, from)) => {
+                    let info = match LobbyInfo::decode(&String::from_utf8_lossy(&buf[..n])) {
+                        Some(i) => i,
+                        None => continue,
+                    };
+                    //The same host is heard over loopback and broadcast, the id makes it one lobby
+                    let ip = from.ip();
+                    match self.lobbies.iter_mut().find(|l| l.info.id == info.id) {
+                        Some(lobby) => {
+                            changed |= lobby.info != info;
+                            lobby.info = info;
+                            lobby.last_seen = Instant::now();
+                        },
+                        None => {
+                            self.lobbies.push(Lobby { info, ip, last_seen: Instant::now() });
+                            changed = true;
+                        },
+                    }
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    println!("Lobby socket error: {}", e);
+                    break;
+                },
+            }
+        }
+
+        let count = self.lobbies.len();
+        self.lobbies.retain(|l| l.last_seen.elapsed() < LOBBY_TIMEOUT);
+        changed |= count != self.lobbies.len();
+
+        if self.selected >= self.lobbies.len() {
+            self.selected = self.lobbies.len().saturating_sub(1);
+        }
+        if changed {
+            self.print();
+        }
+    }
+
+    pub fn take_join(&mut self) -> Option<(SocketAddr, String)> {
+        self.join.take()
+    }
+
+    fn print(&self) {
+        println!("Games found: {}", self.lobbies.len());
+        for (i, lobby) in self.lobbies.iter().enumerate() {
+            let marker = if i == self.selected { ">" } else { " " };
+            println!("{} {} ({}) {}/{} at {}", marker, lobby.info.name, lobby.info.mode, lobby.info.players, lobby.info.max_players, lobby.addr());
+        }
+    }
+
+    pub fn render(&self, r: &mut r_backend::Renderer) {
+
+        let title = if self.lobbies.is_empty() { "Looking for games..." } else { "Games on the local network" };
+        r.render_text(title, [-1.0, 8.5], game_options::SCALE * 1.5, text::Align::Centre, [1.0, 1.0, 1.0, 1.0], -0.5, text::FONT.to_string());
+
+        //One row per lobby with a tile for each player slot, filled slots solid, then its name,
+        //mode and player count. Full lobbies are dimmed as they can't be joined.
+        for (i, lobby) in self.lobbies.iter().enumerate() {
+            let y = 7.0 - (i as f32 * 1.2);
+            let x = -7.0;
+            let alpha = if lobby.info.players >= lobby.info.max_players { 0.4 } else { 1.0 };
+            let name: String = lobby.info.name.chars().take(NAME_LENGTH).collect();
+            let label = format!("{:<width$} {:<8} {}/{}", name, lobby.info.mode, lobby.info.players, lobby.info.max_players, width = NAME_LENGTH);
+            let label_x = x + (lobby.info.max_players as f32 + 0.5) * game_options::SCALE;
+            r.render_text(&label, [label_x, y + game_options::SCALE], game_options::SCALE, text::Align::Left, [1.0, 1.0, 1.0, alpha], -0.5, text::FONT.to_string());
+            if i == self.selected {
+                r.render_sprite_array([x - game_options::SCALE * 1.5, y], [game_options::SCALE, game_options::SCALE], -1.0, skins::SPRITE_ARRAY.to_string(), 8);
+            }
+            for slot in 0..lobby.info.max_players {
+                let alpha = if slot < lobby.info.players { 1.0 } else { 0.3 };
+                r.render_sprite_transparent([x + slot as f32 * game_options::SCALE, y], [game_options::SCALE, game_options::SCALE], -1.0, skins::SPRITE_ARRAY.to_string(), i as i32 % 7, alpha);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> LobbyInfo {
+        LobbyInfo { id: 77, name: "Friday night game".to_string(), mode: "rollback".to_string(), players: 1, max_players: 2, port: 7878 }
+    }
+
+    #[test]
+    fn info_round_trips() {
+        let text = info().encode();
+        assert_eq!(text, "tetris-lobby 77 7878 1 2 rollback Friday night game");
+        assert_eq!(LobbyInfo::decode(&text), Some(info()));
+
+        let mut unnamed = info();
+        unnamed.name = String::new();
+        assert_eq!(LobbyInfo::decode(&unnamed.encode()), Some(unnamed));
+    }
+
+    #[test]
+    fn rejects_malformed_packets() {
+        assert_eq!(LobbyInfo::decode(""), None);
+        assert_eq!(LobbyInfo::decode("some-other-game 77 7878 1 2 lockstep name"), None);
+        assert_eq!(LobbyInfo::decode("tetris-lobby 77 7878 1"), None);
+        assert_eq!(LobbyInfo::decode("tetris-lobby x 7878 1 2 lockstep name"), None);
+        assert_eq!(LobbyInfo::decode("tetris-lobby 77 99999 1 2 lockstep name"), None);
+        assert_eq!(LobbyInfo::decode("tetris-lobby 77 7878 -1 2 lockstep name"), None);
+    }
+
+    #[test]
+    fn rejects_impossible_player_counts() {
+        assert_eq!(LobbyInfo::decode("tetris-lobby 77 7878 1 4294967295 lockstep name"), None);
+        assert_eq!(LobbyInfo::decode("tetris-lobby 77 7878 1 9 lockstep name"), None);
+        assert_eq!(LobbyInfo::decode("tetris-lobby 77 7878 0 0 lockstep name"), None);
+        assert_eq!(LobbyInfo::decode("tetris-lobby 77 7878 3 2 lockstep name"), None);
+        assert!(LobbyInfo::decode("tetris-lobby 77 7878 2 2 lockstep name").is_some());
+        assert!(LobbyInfo::decode("tetris-lobby 77 7878 0 8 lockstep name").is_some());
+    }
+}
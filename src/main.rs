@@ -1,17 +1,41 @@
 #![allow(unused_variables)]
 #![allow(unused_imports)]
 
-mod texture;
-mod r_state;
-mod r_render_pipeline;
-mod camera;
-mod uniform;
-mod game;
-mod input;
-mod r_backend;
-mod tetromino;
-mod game_options;
-mod tetris_board;
+use tetris::{
+    texture,
+    r_state,
+    r_render_pipeline,
+    camera,
+    uniform,
+    game,
+    input,
+    r_backend,
+    tetromino,
+    game_options,
+    tetris_board,
+    key_bindings,
+    controls_menu,
+    versus,
+    net,
+    rollback,
+    play_mode,
+    lobby,
+    bot,
+    movegen,
+    tbp,
+    pc_solver,
+    stats,
+    piece_set,
+    polyomino,
+    settings,
+    skins,
+    text,
+    scene,
+    audio,
+    music,
+    goals,
+    replay,
+};
 
 //Game tick every 16 ms
 const NUM_TICKS: u128 = 16;
@@ -26,6 +50,111 @@ use winit::{
 use futures::executor::block_on;
 use std::time::Instant;
 
+fn arg_value(name: &str) -> Option<String> {
+    let args: Vec<String> = std::env::args().collect();
+    args.iter().position(|a| a == name).and_then(|i| args.get(i + 1).cloned())
+}
+
+//--goal <endless|marathon|sprint|ultra|dig> for a solo run, --replay <file> to watch one again,
+//--versus for local split screen, --cpu <easy|medium|hard|expert> against the computer,
+//--host <port> or --connect <address:port> for online,
+//--browse to find games hosted on the local network, --name <lobby name> when hosting,
+//with --rollback for rollback, and --latency <ms> and --loss <0..1> for testing either over loopback.
+//Without any of them the title screen comes up, unless a bot was asked for.
+fn start_mode(mut game: game::Game) -> anyhow::Result<Option<play_mode::PlayMode>> {
+
+    if let Some(path) = arg_value("--replay") {
+        game.play_back(&replay::Replay::load(&path)?)?;
+        return Ok(Some(play_mode::PlayMode::Single(game)));
+    }
+    if let Some(name) = arg_value("--goal") {
+        match goals::Goal::from_name(&name) {
+            Some(goal) => game.set_goal(goal),
+            None => anyhow::bail!("Unknown goal {}, try endless, marathon, sprint, ultra or dig", name),
+        }
+        return Ok(Some(play_mode::PlayMode::Single(game)));
+    }
+
+    if let Some(name) = arg_value("--cpu") {
+        let difficulty = match bot::Difficulty::from_name(&name) {
+            Some(d) => d,
+            None => anyhow::bail!("Unknown difficulty {}, try easy, medium, hard or expert", name),
+        };
+        return Ok(Some(play_mode::PlayMode::Local(versus::Versus::against_cpu(game.bindings, difficulty))));
+    }
+    if std::env::args().any(|a| a == "--versus") {
+        return Ok(Some(play_mode::PlayMode::Local(versus::Versus::new())));
+    }
+    if std::env::args().any(|a| a == "--browse") {
+        return Ok(Some(play_mode::PlayMode::Browser(lobby::LobbyBrowser::new(game.bindings)?)));
+    }
+
+    let mode = if std::env::args().any(|a| a == "--rollback") { "rollback" } else { "lockstep" };
+    let name = arg_value("--name").unwrap_or_else(|| "Tetris".to_string());
+
+    let latency = match arg_value("--latency") {
+        Some(l) => std::time::Duration::from_millis(l.parse::<u64>()?),
+        None => std::time::Duration::from_millis(0),
+    };
+    let loss = match arg_value("--loss") {
+        Some(l) => l.parse::<f32>()?,
+        None => 0.0,
+    };
+
+    //The host waits for someone to join inside the window, the play mode swaps itself over
+    if let Some(port) = arg_value("--host") {
+        let mut host = net::Host::new(port.parse::<u16>()?, &name, mode, game.bindings)?;
+        host.latency = latency;
+        host.loss = loss;
+        return Ok(Some(play_mode::PlayMode::Hosting(host)));
+    }
+
+    let (mut connection, seed, local) = match arg_value("--connect") {
+        Some(addr) => net::Connection::connect(&addr)?,
+        None if std::env::args().any(|a| a == "--bot" || a == "--tbp") => return Ok(Some(play_mode::PlayMode::Single(game))),
+        None => return Ok(None),
+    };
+    connection.latency = latency;
+    connection.loss = loss;
+
+    Ok(Some(play_mode::PlayMode::online(connection, seed, local, mode, game.bindings)))
+}
+
+//--bot lets the heuristic bot play, --bot-pps <pieces per second> and --bot-weights <file> tune it
+fn attach_bot(mode: &mut play_mode::PlayMode) {
+
+    if !std::env::args().any(|a| a == "--bot") {
+        return;
+    }
+    let weights_path = arg_value("--bot-weights").unwrap_or_else(|| bot::WEIGHTS_PATH.to_string());
+    let pps = arg_value("--bot-pps").and_then(|p| p.parse::<f32>().ok()).unwrap_or(2.0);
+    match mode.bot_player() {
+        Some(player) => player.bot = Some(bot::Bot::new(bot::BotWeights::load(&weights_path), pps)),
+        None => println!("No player for the bot to take over in this mode"),
+    }
+}
+
+fn save_settings(scenes: &scene::SceneStack) {
+    if let Err(e) = scenes.settings.save(settings::SETTINGS_PATH) {
+        println!("Couldn't save settings: {:?}", e);
+    }
+}
+
+//Keys held as the menu opens or closes are released somewhere the game won't hear, so it
+//forgets them either way rather than keep moving the piece
+fn toggle_controls(menu: &mut controls_menu::ControlsMenu, scenes: &mut scene::SceneStack) {
+    scenes.release_keys();
+    menu.toggle(scenes.bindings_mut());
+}
+
+//Moves on to the next skin and remembers it for next time
+fn cycle_skin(skins: &mut skins::Skins, r_state: &mut r_state::State, scenes: &mut scene::SceneStack) {
+    if let Some(skin) = skins.cycle(&r_state.device, &r_state.queue, &mut r_state.renderer) {
+        scenes.set_skin(&skin);
+        save_settings(scenes);
+    }
+}
+
 fn main() {
 
     env_logger::init();
@@ -36,10 +165,64 @@ fn main() {
     //window.set_cursor_grab(true).unwrap();
     //window.set_cursor_visible(true);
     let mut r_state = block_on(r_state::State::new(&window));
+    //--pieces <file> plays with a piece set from a json file, see res/piece_sets
+    if let Some(path) = arg_value("--pieces") {
+        match piece_set::PieceSet::load(&path) {
+            Ok(set) => piece_set::set_active(set),
+            Err(e) => println!("{:#}, using the standard pieces", e),
+        }
+    }
+    //--polyominoes <n or min-max>[,fixed|one_sided|free] plays with every polyomino of those sizes
+    if let Some(spec) = arg_value("--polyominoes") {
+        match polyomino::from_spec(&spec) {
+            Ok(set) => piece_set::set_active(set),
+            Err(e) => println!("{:#}, using the standard pieces", e),
+        }
+    }
     let mut game = game::Game::new();
-    game.init(&r_state.device, &r_state.queue, &mut r_state.renderer);
+    let mut skins = skins::Skins::new();
+    game.init(&r_state.device, &r_state.queue, &mut r_state.renderer, &mut skins);
+    if let Err(e) = r_state.renderer.load_font(&r_state.device, &r_state.queue, text::DEFAULT_FONT, text::FONT.to_string()) {
+        println!("{:#}", e);
+    }
+    let settings = settings::Settings::load(settings::SETTINGS_PATH);
+    //--no-audio keeps quiet, mixing into the null backend instead of a device
+    let mut audio = if std::env::args().any(|a| a == "--no-audio") {
+        let mut audio = audio::Audio::null();
+        audio.set_volume(&settings);
+        audio
+    }
+    else {
+        audio::Audio::new(&settings)
+    };
+    let mut scenes = scene::SceneStack::new(settings);
+    scenes.set_skin(&skins.current);
+    match start_mode(game) {
+        Ok(Some(mut mode)) => {
+            attach_bot(&mut mode);
+            scenes.push(scene::Scene::playing(mode, None));
+        },
+        Ok(None) => (),
+        Err(e) => {
+            println!("Couldn't start game: {:?}", e);
+            return;
+        }
+    }
+    //--tbp "<command>" hands the bot's seat to an external Tetris Bot Protocol program instead
+    let mut tbp_bot = match arg_value("--tbp") {
+        Some(command) => match tbp::TbpBot::launch(&command) {
+            Ok(b) => Some(b),
+            Err(e) => {
+                println!("Couldn't start bot: {:?}", e);
+                None
+            }
+        },
+        None => None,
+    };
+    let mut controls_menu = controls_menu::ControlsMenu::new();
 
     let mut fps: i32 = 0;
+    let mut fps_shown: i32 = 0;
     let mut run_time = Instant::now();
     let mut tick_time = Instant::now();
 
@@ -50,7 +233,12 @@ fn main() {
                 window_id,
             } if window_id == window.id() => {
                 //r_state.input(event);
-                game.input(event);
+                if controls_menu.open {
+                    controls_menu.input(event);
+                }
+                else {
+                    scenes.input(event);
+                }
                 match event {
                     WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
                     WindowEvent::KeyboardInput {
@@ -60,12 +248,37 @@ fn main() {
                         match input {
                             KeyboardInput {
                                 state: ElementState::Pressed,
-                                virtual_keycode: Some(VirtualKeyCode::Escape),
+                                virtual_keycode: Some(VirtualKeyCode::F1),
+                                ..
+                            } => toggle_controls(&mut controls_menu, &mut scenes),
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F2),
+                                ..
+                            } => {
+                                if let Some(player) = scenes.playing().and_then(|m| m.human_player()) {
+                                    player.toggle_hints();
+                                }
+                            },
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F3),
+                                ..
+                            } => {
+                                if let Some(player) = scenes.playing().and_then(|m| m.human_player()) {
+                                    player.toggle_pc_trainer();
+                                }
+                            },
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F4),
                                 ..
-                            } => *control_flow = ControlFlow::Exit,
+                            } => cycle_skin(&mut skins, &mut r_state, &mut scenes),
                             _ => {}
                         }
                     },
+                    //Pause when the player looks away
+                    WindowEvent::Focused(false) => scenes.focus_lost(),
                     WindowEvent::Resized(physical_size) => {
                         r_state.resize(*physical_size);
                     }
@@ -78,7 +291,11 @@ fn main() {
             Event::RedrawRequested(_) => {
 
                 r_state.update();
-                game.render(&mut r_state.renderer, &mut r_state.camera);
+                scenes.render(&mut r_state.renderer, &mut r_state.camera);
+                if controls_menu.open {
+                    controls_menu.render(&mut r_state.renderer, scenes.bindings_mut());
+                }
+                r_state.renderer.render_text(&format!("{} fps", fps_shown), [6.9, 8.5], 0.25, text::Align::Right, [0.6, 0.6, 0.6, 1.0], -0.5, text::FONT.to_string());
                 match r_state.render() {
                     Ok(_) => {}
                     Err(wgpu::SwapChainError::Lost) => r_state.resize(r_state.size),
@@ -87,16 +304,50 @@ fn main() {
                 }
             }
             Event::MainEventsCleared => {
-                game.process_inputs([r_state.size.width, r_state.size.height]);
+                if controls_menu.open {
+                    controls_menu.process_inputs(scenes.bindings_mut());
+                }
+                else {
+                    match scenes.process_inputs([r_state.size.width, r_state.size.height]) {
+                        scene::Transition::Quit => *control_flow = ControlFlow::Exit,
+                        scene::Transition::CycleSkin => cycle_skin(&mut skins, &mut r_state, &mut scenes),
+                        scene::Transition::OpenControls => toggle_controls(&mut controls_menu, &mut scenes),
+                        scene::Transition::SettingsChanged => {
+                            audio.set_volume(&scenes.settings);
+                            save_settings(&scenes);
+                        },
+                        _ => (),
+                    }
+                    if let (Some(b), Some(player)) = (tbp_bot.as_mut(), scenes.playing().and_then(|m| m.bot_player())) {
+                        b.update(player);
+                    }
+                    if tbp_bot.as_ref().map_or(false, |b| b.failed()) {
+                        tbp_bot = None;
+                    }
+                }
                 fps += 1;
                 if run_time.elapsed().as_millis() >= EVERY_SECOND {
-                    println!("fps {}", fps);
+                    fps_shown = fps;
+                    match scenes.playing().and_then(|m| m.human_player()) {
+                        Some(player) => window.set_title(&format!("Tetris  {}", player.stats.hud())),
+                        None => window.set_title("Tetris"),
+                    }
                     fps = 0;
                     run_time = Instant::now();
                 }
                 while tick_time.elapsed().as_millis() >= NUM_TICKS {
                     tick_time = Instant::now();
-                    game.update();
+                    if controls_menu.open {
+                        continue;
+                    }
+                    if let scene::Transition::Quit = scenes.update() {
+                        *control_flow = ControlFlow::Exit;
+                    }
+                    for event in scenes.take_events() {
+                        audio.on_event(&event);
+                    }
+                    audio.cue_music(scenes.music());
+                    audio.tick();
                 }
                 window.request_redraw();
             }
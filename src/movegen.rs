@@ -0,0 +1,220 @@
+use crate::game;
+use crate::tetromino;
+use crate::tetris_board;
+use crate::game_options;
+use crate::key_bindings;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/*
+Breadth first search over everything the piece can do before it locks: shifts, rotations with
+their kicks and single row soft drops. Every state can be hard dropped, and the first path to
+reach each set of final cells is kept, so paths are as short as the inputs allow. Tucks and
+spins come out of the soft drop and rotation edges.
+*/
+
+pub const EDGES: [key_bindings::Action; 5] = [
+    key_bindings::Action::MoveLeft,
+    key_bindings::Action::MoveRight,
+    key_bindings::Action::RotateCw,
+    key_bindings::Action::RotateCcw,
+    key_bindings::Action::SoftDrop,
+];
+
+#[derive(Debug, Clone)]
+pub struct Move {
+    pub piece: tetromino::Tetromino,
+    //Ends with the hard drop that locks the piece
+    pub path: Vec<key_bindings::Action>,
+    //The last thing the piece did before locking was rotate, without dropping after
+    pub spin: bool,
+}
+
+//The piece after one input, or None if the input does nothing or would lock the piece
+pub fn step(piece: &tetromino::Tetromino, action: key_bindings::Action, board: &tetris_board::TetrisBoard) -> Option<tetromino::Tetromino> {
+
+    let mut next = piece.clone();
+    match action {
+        key_bindings::Action::MoveLeft => next.move_piece(1, 0, board),
+        key_bindings::Action::MoveRight => next.move_piece(-1, 0, board),
+        key_bindings::Action::SoftDrop => next.move_piece(0, -1, board),
+        key_bindings::Action::RotateCw => { next.rotate_piece(1, board); },
+        key_bindings::Action::RotateCcw => { next.rotate_piece(-1, board); },
+        _ => return None,
+    }
+
+    if next.x == piece.x && next.y == piece.y && next.rotation() == piece.rotation() {
+        return None;
+    }
+    Some(next)
+}
+
+fn state_key(piece: &tetromino::Tetromino) -> (u8, u8, u8) {
+    (piece.x, piece.y, piece.rotation())
+}
+
+pub fn reachable_placements(piece: &tetromino::Tetromino, board: &tetris_board::TetrisBoard) -> Vec<Move> {
+
+    let mut moves = Vec::new();
+    if !board.check_piece_fits(piece) {
+        return moves;
+    }
+
+    let mut seen_states = HashSet::new();
+    let mut seen_cells: HashMap<Vec<(u8, u8)>, usize> = HashMap::new();
+    let mut queue = VecDeque::new();
+    seen_states.insert(state_key(piece));
+    queue.push_back((piece.clone(), Vec::new()));
+
+    while let Some((current, path)) = queue.pop_front() {
+
+        let mut dropped = current.clone();
+        let (_, ghost_y) = dropped.get_ghost(board);
+        let spin = ghost_y == current.y && matches!(path.last(), Some(key_bindings::Action::RotateCw) | Some(key_bindings::Action::RotateCcw));
+        dropped.y = ghost_y;
+
+        let cells = dropped.cells();
+        match seen_cells.get(&cells) {
+            //A spin into the same cells is worth knowing about even if a plain drop got there first
+            Some(i) => {
+                if spin && !moves[*i].spin {
+                    let mut full_path = path.clone();
+                    full_path.push(key_bindings::Action::HardDrop);
+                    moves[*i] = Move { piece: dropped, path: full_path, spin };
+                }
+            },
+            None => {
+                let mut full_path = path.clone();
+                full_path.push(key_bindings::Action::HardDrop);
+                seen_cells.insert(cells, moves.len());
+                moves.push(Move { piece: dropped, path: full_path, spin });
+            },
+        }
+
+        for action in EDGES.iter() {
+            if let Some(next) = step(&current, *action, board) {
+                if seen_states.insert(state_key(&next)) {
+                    let mut next_path = path.clone();
+                    next_path.push(*action);
+                    queue.push_back((next, next_path));
+                }
+            }
+        }
+    }
+
+    moves
+}
+
+//Placements of the current piece and, if holding is allowed, of the piece holding would bring
+//in with Hold at the front of the path. Ones locking above the top of the board are left out.
+pub fn placements_with_hold(game: &game::Game) -> Vec<Move> {
+
+    let board = game.board();
+    let mut options = vec![(game.piece().clone(), false)];
+    if game.can_hold() {
+        let kind = game.hold_piece().unwrap_or(game.next_pieces()[0]);
+        options.push((tetromino::Tetromino::new_piece(kind), true));
+    }
+
+    let mut moves = Vec::new();
+    for (piece, hold) in options.iter() {
+        for mut m in reachable_placements(piece, board) {
+            if m.piece.cells().iter().any(|(_, y)| *y as usize >= game_options::BOARD_HEIGHT - 1) {
+                continue;
+            }
+            if *hold {
+                m.path.insert(0, key_bindings::Action::Hold);
+            }
+            moves.push(m);
+        }
+    }
+    moves
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use key_bindings::Action;
+
+    //Standard set kinds
+    const I: u8 = 0;
+    const O: u8 = 3;
+    const T: u8 = 5;
+
+    fn placement_cells(kind: u8, board: &tetris_board::TetrisBoard) -> Vec<Vec<(u8, u8)>> {
+        reachable_placements(&tetromino::Tetromino::new_piece(kind), board).iter().map(|m| m.piece.cells()).collect()
+    }
+
+    #[test]
+    fn every_column_and_rotation_on_an_empty_board() {
+        let board = tetris_board::TetrisBoard::new();
+        let counts = [17, 34, 34, 9, 17, 34, 17];
+        for (kind, count) in counts.iter().enumerate() {
+            assert_eq!(placement_cells(kind as u8, &board).len(), *count, "kind {}", kind);
+        }
+    }
+
+    #[test]
+    fn paths_lead_to_their_placement() {
+        let mut board = tetris_board::TetrisBoard::new();
+        board.add_garbage(3, 4);
+        for kind in 0..7 {
+            for m in reachable_placements(&tetromino::Tetromino::new_piece(kind), &board) {
+                assert_eq!(m.path.last(), Some(&Action::HardDrop));
+                let mut piece = tetromino::Tetromino::new_piece(kind);
+                for action in m.path[..m.path.len() - 1].iter() {
+                    piece = step(&piece, *action, &board).expect("every input in a path does something");
+                }
+                let (_, ghost_y) = piece.get_ghost(&board);
+                piece.y = ghost_y;
+                assert_eq!(piece.cells(), m.piece.cells());
+                assert!(board.check_piece_fits(&m.piece));
+            }
+        }
+    }
+
+    #[test]
+    fn fills_a_well() {
+        let mut board = tetris_board::TetrisBoard::new();
+        board.add_garbage(4, 5);
+        let well = vec![(5, 1), (5, 2), (5, 3), (5, 4)];
+        assert!(placement_cells(I, &board).contains(&well));
+        assert!(!placement_cells(T, &board).iter().any(|cells| cells.contains(&(5, 1))));
+    }
+
+    #[test]
+    fn tucks_under_an_overhang() {
+        //An O floating over columns 3 and 4, the space under it is only reached sideways
+        let mut board = tetris_board::TetrisBoard::new();
+        let mut overhang = tetromino::Tetromino::new_piece(O);
+        overhang.x = 5;
+        overhang.y = 4;
+        board.add_piece_to_board(&overhang);
+
+        let under = vec![(3, 1), (3, 2), (4, 1), (4, 2)];
+        let moves = reachable_placements(&tetromino::Tetromino::new_piece(O), &board);
+        let tuck = moves.iter().find(|m| m.piece.cells() == under).expect("tuck under the overhang");
+        let drop = tuck.path.iter().position(|a| *a == Action::SoftDrop).expect("soft drops first");
+        assert!(tuck.path[drop..].contains(&Action::MoveRight));
+        assert!(!tuck.spin);
+    }
+
+    #[test]
+    fn no_moves_when_blocked() {
+        let mut board = tetris_board::TetrisBoard::new();
+        board.add_garbage(game_options::BOARD_HEIGHT - 2, 1);
+        assert!(reachable_placements(&tetromino::Tetromino::new_piece(T), &board).is_empty());
+    }
+
+    #[test]
+    fn spins_end_on_a_rotation() {
+        let mut board = tetris_board::TetrisBoard::new();
+        board.add_garbage(2, 6);
+        for kind in 0..7 {
+            for m in reachable_placements(&tetromino::Tetromino::new_piece(kind), &board).iter().filter(|m| m.spin) {
+                let last = m.path[m.path.len() - 2];
+                assert!(last == Action::RotateCw || last == Action::RotateCcw);
+            }
+        }
+    }
+}
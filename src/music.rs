@@ -0,0 +1,424 @@
+use crate::audio::{self, Oscillator, Wave};
+
+use anyhow::{bail, Result};
+
+/*
+Music is written into the game as patterns for the same chip channels the sound effects use, and
+sequenced as it plays, so no recordings ship with it. A pattern is one token a step:
+C4 Eb5 F#3  start a note
+-           keep the last note going
+.           silence
+x o         noise hits, high and low, for the drums
+
+A track is layers of patterns that loop together. Each layer plays always, only while the stack is
+low, or only while it's high, and which set is playing only changes on a bar line. The tempo goes
+up with the level.
+*/
+
+pub const STEPS_PER_BEAT: usize = 4;
+pub const STEPS_PER_BAR: usize = 16;
+//The stack has to get this high for the danger layers, then back down below CALM_HEIGHT to leave them
+pub const DANGER_HEIGHT: usize = 14;
+pub const CALM_HEIGHT: usize = 10;
+//Tempo at level 1 is the track's own, each level after adds this much, up to MAX_TEMPO times it
+const TEMPO_PER_LEVEL: f32 = 0.05;
+const MAX_TEMPO: f32 = 1.6;
+
+pub const ENDLESS_TRACK: &str = "drift";
+pub const VERSUS_TRACK: &str = "climb";
+pub const TRACKS: [&str; 2] = [ENDLESS_TRACK, VERSUS_TRACK];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    Note(f32),
+    Hold,
+    Rest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Intensity {
+    Always,
+    Calm,
+    Danger,
+}
+
+#[derive(Debug, Clone)]
+pub struct Layer {
+    pub wave: Wave,
+    pub volume: f32,
+    //How fast a note fades, per second, 0 holds it at full volume until the next step changes it
+    pub decay: f32,
+    pub when: Intensity,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Track {
+    pub name: String,
+    pub bpm: f32,
+    pub layers: Vec<Layer>,
+}
+
+//What the game wants playing, from the player whose game it is
+#[derive(Debug, Clone, Copy)]
+pub struct Cue {
+    pub track: &'static str,
+    pub level: u32,
+    pub stack_height: usize,
+    pub paused: bool,
+}
+
+//C4 is middle C, A4 is 440
+pub fn note_frequency(name: &str) -> Option<f32> {
+    let mut chars = name.chars();
+    let mut semitones = match chars.next()? {
+        'C' => -9,
+        'D' => -7,
+        'E' => -5,
+        'F' => -4,
+        'G' => -2,
+        'A' => 0,
+        'B' => 2,
+        _ => return None,
+    };
+    let rest: String = chars.collect();
+    let octave = if let Some(o) = rest.strip_prefix('#') {
+        semitones += 1;
+        o
+    } else if let Some(o) = rest.strip_prefix('b') {
+        semitones -= 1;
+        o
+    } else {
+        &rest
+    };
+    let octave = octave.parse::<i32>().ok()?;
+    Some(audio::note(semitones + (octave - 4) * 12))
+}
+
+pub fn parse_pattern(pattern: &str) -> Result<Vec<Step>> {
+    let mut steps = Vec::new();
+    for token in pattern.split_whitespace() {
+        steps.push(match token {
+            "-" => Step::Hold,
+            "." => Step::Rest,
+            //Noise is clocked at these rates, fast for a hat and slow for a kick
+            "x" => Step::Note(8000.0),
+            "o" => Step::Note(700.0),
+            note => match note_frequency(note) {
+                Some(f) => Step::Note(f),
+                None => bail!("Bad step {} in pattern {}", note, pattern),
+            },
+        });
+    }
+    if steps.is_empty() {
+        bail!("Empty pattern");
+    }
+    Ok(steps)
+}
+
+impl Layer {
+
+    pub fn new(wave: Wave, volume: f32, decay: f32, when: Intensity, bars: &[&str]) -> Result<Layer> {
+        let steps = parse_pattern(&bars.join(" "))?;
+        Ok(Layer { wave, volume, decay, when, steps })
+    }
+}
+
+impl Track {
+
+    pub fn builtin(name: &str) -> Option<Track> {
+        let track = match name {
+            "drift" => Track::drift(),
+            "climb" => Track::climb(),
+            _ => return None,
+        };
+        Some(track.expect("built in tracks parse"))
+    }
+
+    //Steps before it loops, the longest layer's
+    pub fn length(&self) -> usize {
+        self.layers.iter().map(|l| l.steps.len()).max().unwrap_or(1)
+    }
+
+    //A minor, calm arpeggios and a walking bass, sixteenths over the top when it gets busy
+    fn drift() -> Result<Track> {
+        Ok(Track {
+            name: "drift".to_string(),
+            bpm: 112.0,
+            layers: vec![
+                Layer::new(Wave::Square, 0.10, 3.0, Intensity::Calm, &[
+                    "A4 - C5 - E5 - A5 - G5 - E5 - C5 - D5 -",
+                    "E5 - - - D5 - C5 - B4 - G4 - B4 - - -",
+                    "A4 - C5 - E5 - A5 - B5 - A5 - G5 - E5 -",
+                    "F5 - E5 - D5 - B4 - C5 - - - A4 - - -",
+                ])?,
+                Layer::new(Wave::Square, 0.09, 6.0, Intensity::Danger, &[
+                    "A5 E5 C5 E5 A5 E5 C5 E5 F5 C5 A4 C5 F5 C5 A4 C5",
+                    "G5 D5 B4 D5 G5 D5 B4 D5 E5 B4 G#4 B4 E5 B4 G#4 B4",
+                    "A5 E5 C5 E5 A5 E5 C5 E5 F5 C5 A4 C5 F5 C5 A4 C5",
+                    "D5 A4 F4 A4 D5 A4 F4 A4 E5 B4 G#4 B4 E5 G#5 B5 E6",
+                ])?,
+                Layer::new(Wave::Triangle, 0.25, 0.0, Intensity::Always, &[
+                    "A2 . A3 . A2 . A3 . F2 . F3 . F2 . F3 .",
+                    "G2 . G3 . G2 . G3 . E2 . E3 . E2 . E3 .",
+                    "A2 . A3 . A2 . A3 . F2 . F3 . F2 . F3 .",
+                    "D2 . D3 . D2 . D3 . E2 . E3 . E2 . G#2 .",
+                ])?,
+                Layer::new(Wave::Noise, 0.06, 25.0, Intensity::Calm, &[
+                    "o . x . x . x . o . x . x . x .",
+                ])?,
+                Layer::new(Wave::Noise, 0.08, 25.0, Intensity::Danger, &[
+                    "o x x x o x x o o x x x o x o x",
+                ])?,
+            ],
+        })
+    }
+
+    //D dorian, punchier, for playing against someone
+    fn climb() -> Result<Track> {
+        Ok(Track {
+            name: "climb".to_string(),
+            bpm: 128.0,
+            layers: vec![
+                Layer::new(Wave::Square, 0.10, 4.0, Intensity::Calm, &[
+                    "D5 - . D5 F5 - A5 - G5 - F5 - E5 - C5 -",
+                    "D5 - . D5 F5 - A5 - C6 - B5 - A5 - - -",
+                    "G5 - . G5 F5 - E5 - F5 - E5 - D5 - C5 -",
+                    "D5 - A4 - C5 - E5 - D5 - - - . . . .",
+                ])?,
+                Layer::new(Wave::Square, 0.09, 6.0, Intensity::Danger, &[
+                    "D6 A5 F5 A5 D6 A5 F5 A5 C6 G5 E5 G5 C6 G5 E5 G5",
+                    "B5 G5 D5 G5 B5 G5 D5 G5 A5 F5 D5 F5 A5 F5 D5 F5",
+                    "G5 D5 B4 D5 G5 D5 B4 D5 F5 C5 A4 C5 F5 C5 A4 C5",
+                    "E5 C5 A4 C5 E5 C5 A4 C5 A5 E5 C#5 E5 A5 C#6 E6 A6",
+                ])?,
+                Layer::new(Wave::Triangle, 0.25, 0.0, Intensity::Always, &[
+                    "D2 D2 D3 D2 . D2 D3 D2 C2 C2 C3 C2 . C2 C3 C2",
+                    "G2 G2 G3 G2 . G2 G3 G2 A2 A2 A3 A2 . A2 A3 A2",
+                ])?,
+                Layer::new(Wave::Noise, 0.07, 25.0, Intensity::Calm, &[
+                    "o . x . o . x . o . x . o x x .",
+                ])?,
+                Layer::new(Wave::Noise, 0.08, 30.0, Intensity::Danger, &[
+                    "o x o x o x o x o x o x o o x x",
+                ])?,
+            ],
+        })
+    }
+}
+
+pub fn tempo_for_level(level: u32) -> f32 {
+    (1.0 + level.saturating_sub(1) as f32 * TEMPO_PER_LEVEL).min(MAX_TEMPO)
+}
+
+struct Channel {
+    osc: Oscillator,
+    freq: f32,
+    envelope: f32,
+}
+
+//Plays a track on a loop, one sample at a time at whatever rate the output wants
+pub struct Sequencer {
+    pub track: Track,
+    sample_rate: f32,
+    channels: Vec<Channel>,
+    step: usize,
+    //Samples into the current step
+    elapsed: f32,
+    pub tempo: f32,
+    //Asked for by the game, danger_playing catches up on the next bar
+    pub danger: bool,
+    danger_playing: bool,
+    pub paused: bool,
+}
+
+impl Sequencer {
+
+    pub fn new(track: Track, sample_rate: u32) -> Sequencer {
+        let channels = track.layers.iter().map(|l| Channel { osc: Oscillator::new(l.wave), freq: 0.0, envelope: 0.0 }).collect();
+        let mut sequencer = Sequencer {
+            track,
+            sample_rate: sample_rate as f32,
+            channels,
+            step: 0,
+            elapsed: 0.0,
+            tempo: 1.0,
+            danger: false,
+            danger_playing: false,
+            paused: false,
+        };
+        sequencer.trigger();
+        sequencer
+    }
+
+    pub fn set_level(&mut self, level: u32) {
+        self.tempo = tempo_for_level(level);
+    }
+
+    //Rows filled from the floor up, with some room either side so it doesn't flip back and forth
+    pub fn set_stack_height(&mut self, height: usize) {
+        if height >= DANGER_HEIGHT {
+            self.danger = true;
+        }
+        else if height < CALM_HEIGHT {
+            self.danger = false;
+        }
+    }
+
+    pub fn step(&self) -> usize {
+        self.step
+    }
+
+    pub fn danger_playing(&self) -> bool {
+        self.danger_playing
+    }
+
+    fn samples_per_step(&self) -> f32 {
+        self.sample_rate * 60.0 / (self.track.bpm * self.tempo * STEPS_PER_BEAT as f32)
+    }
+
+    fn audible(&self, layer: &Layer) -> bool {
+        match layer.when {
+            Intensity::Always => true,
+            Intensity::Calm => !self.danger_playing,
+            Intensity::Danger => self.danger_playing,
+        }
+    }
+
+    //Starts or stops each channel's note for the step just reached
+    fn trigger(&mut self) {
+        if self.step.is_multiple_of(STEPS_PER_BAR) {
+            self.danger_playing = self.danger;
+        }
+        for (layer, channel) in self.track.layers.iter().zip(self.channels.iter_mut()) {
+            match layer.steps[self.step % layer.steps.len()] {
+                Step::Note(f) => {
+                    channel.freq = f;
+                    channel.envelope = 1.0;
+                },
+                Step::Hold => (),
+                Step::Rest => channel.envelope = 0.0,
+            }
+        }
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+
+        if self.paused {
+            return 0.0;
+        }
+
+        let mut sample = 0.0;
+        for i in 0..self.channels.len() {
+            let layer = &self.track.layers[i];
+            let audible = self.audible(layer);
+            let channel = &mut self.channels[i];
+            //Channels that aren't heard keep running so they come back in time
+            let s = channel.osc.next(channel.freq) * channel.envelope * layer.volume;
+            if audible {
+                sample += s;
+            }
+            channel.envelope *= (-layer.decay / self.sample_rate).exp();
+        }
+
+        self.elapsed += 1.0;
+        let per_step = self.samples_per_step();
+        if self.elapsed >= per_step {
+            self.elapsed -= per_step;
+            self.step = (self.step + 1) % self.track.length();
+            self.trigger();
+        }
+        sample
+    }
+
+    pub fn render(&mut self, frames: usize) -> Vec<f32> {
+        (0..frames).map(|_| self.next_sample()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 0.01
+    }
+
+    #[test]
+    fn note_names() {
+        assert_eq!(note_frequency("A4"), Some(440.0));
+        assert!(close(note_frequency("C4").unwrap(), 261.63));
+        assert!(close(note_frequency("A5").unwrap(), 880.0));
+        assert_eq!(note_frequency("A#3"), note_frequency("Bb3"));
+        assert!(close(note_frequency("E6").unwrap(), 1318.51));
+        assert_eq!(note_frequency("H4"), None);
+        assert_eq!(note_frequency("C"), None);
+        assert_eq!(note_frequency("Cx4"), None);
+    }
+
+    #[test]
+    fn patterns() {
+        let steps = parse_pattern("A4 - .  x\to").unwrap();
+        assert_eq!(steps, vec![Step::Note(440.0), Step::Hold, Step::Rest, Step::Note(8000.0), Step::Note(700.0)]);
+        assert!(parse_pattern("A4 Q4").is_err());
+        assert!(parse_pattern("   ").is_err());
+    }
+
+    #[test]
+    fn builtin_tracks_loop_on_bars() {
+        for name in TRACKS.iter() {
+            let track = Track::builtin(name).unwrap();
+            assert_eq!(track.name, *name);
+            for layer in track.layers.iter() {
+                assert_eq!(layer.steps.len() % STEPS_PER_BAR, 0, "{}", name);
+                assert_eq!(track.length() % layer.steps.len(), 0, "{}", name);
+            }
+        }
+        assert!(Track::builtin("silence").is_none());
+    }
+
+    #[test]
+    fn tempo_rises_with_level_to_a_cap() {
+        assert_eq!(tempo_for_level(0), 1.0);
+        assert_eq!(tempo_for_level(1), 1.0);
+        assert!(close(tempo_for_level(5), 1.2));
+        assert_eq!(tempo_for_level(100), MAX_TEMPO);
+    }
+
+    #[test]
+    fn danger_has_room_either_side() {
+        let mut sequencer = Sequencer::new(Track::builtin(ENDLESS_TRACK).unwrap(), audio::SAMPLE_RATE);
+        sequencer.set_stack_height(DANGER_HEIGHT - 1);
+        assert!(!sequencer.danger);
+        sequencer.set_stack_height(DANGER_HEIGHT);
+        assert!(sequencer.danger);
+        sequencer.set_stack_height(CALM_HEIGHT);
+        assert!(sequencer.danger);
+        sequencer.set_stack_height(CALM_HEIGHT - 1);
+        assert!(!sequencer.danger);
+    }
+
+    #[test]
+    fn danger_waits_for_the_bar_line() {
+        let mut sequencer = Sequencer::new(Track::builtin(ENDLESS_TRACK).unwrap(), audio::SAMPLE_RATE);
+        //Into the second step of the first bar
+        sequencer.render(10000);
+        sequencer.set_stack_height(DANGER_HEIGHT);
+        assert!(sequencer.step() % STEPS_PER_BAR != 0);
+        assert!(!sequencer.danger_playing());
+        while sequencer.step() % STEPS_PER_BAR != 0 {
+            sequencer.next_sample();
+        }
+        assert!(sequencer.danger_playing());
+    }
+
+    #[test]
+    fn paused_is_silent() {
+        let mut sequencer = Sequencer::new(Track::builtin(VERSUS_TRACK).unwrap(), audio::SAMPLE_RATE);
+        assert!(sequencer.render(4410).iter().any(|s| *s != 0.0));
+        sequencer.paused = true;
+        let step = sequencer.step();
+        assert!(sequencer.render(44100).iter().all(|s| *s == 0.0));
+        assert_eq!(sequencer.step(), step);
+    }
+}
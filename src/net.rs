@@ -0,0 +1,621 @@
+use crate::game;
+use crate::camera;
+use crate::versus;
+use crate::r_backend;
+use crate::key_bindings;
+use crate::lobby;
+use crate::events::GameEvent;
+use crate::text;
+use crate::piece_set;
+
+use anyhow::{bail, Context, Result};
+use winit::event::*;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+//Local inputs are scheduled this many ticks ahead so the opponent has time to receive them
+pub const INPUT_DELAY: u32 = 3;
+//Local inputs are resent until the opponent can't still be waiting on them, so a lost packet
+//doesn't stall both sides. Neither side gets more than INPUT_DELAY ticks ahead of the other.
+const RESEND_TICKS: u32 = INPUT_DELAY * 2;
+
+/*
+Line based protocol. Whoever joins sends "join <piece set fingerprint>" and the host answers
+"seed <n>", or "refused <reason>" if they aren't playing with the same pieces. Then both sides send
+i <tick> <action,action,...|->   inputs for that tick
+c <tick> <state hash> <garbage>  check of the sender's own board after that tick
+Game events aren't sent. Each side makes them from the same inputs, and the check catches a
+game that went differently even where its events would look the same.
+*/
+
+//Connects to a host on a thread of its own, handshake and all, so the window keeps going while
+//it does
+pub struct Joining {
+    pub addr: SocketAddr,
+    //What the lobby was advertised as, lockstep or rollback
+    pub mode: String,
+    pub bindings: key_bindings::KeyBindings,
+    result: mpsc::Receiver<Result<(Connection, u64, usize)>>,
+}
+
+impl Joining {
+
+    pub fn start(addr: SocketAddr, mode: &str, bindings: key_bindings::KeyBindings) -> Joining {
+        let (sender, result) = mpsc::channel();
+        std::thread::spawn(move || {
+            //Nobody is listening any more if the join was given up on
+            let _ = sender.send(Connection::connect(&addr.to_string()));
+        });
+        Joining { addr, mode: mode.to_string(), bindings, result }
+    }
+
+    //Called every tick, the connection, seed and local player once the host has answered
+    pub fn update(&mut self) -> Result<Option<(Connection, u64, usize)>> {
+        match self.result.try_recv() {
+            Ok(joined) => joined.map(Some),
+            Err(mpsc::TryRecvError::Empty) => Ok(None),
+            Err(mpsc::TryRecvError::Disconnected) => bail!("Lost the connection to {} while joining", self.addr),
+        }
+    }
+
+    pub fn render(&self, r: &mut r_backend::Renderer) {
+        r.render_text(&format!("Joining {}", self.addr), [-1.0, 7.4], 0.4, text::Align::Centre, [1.0, 1.0, 1.0, 1.0], -0.5, text::FONT.to_string());
+    }
+}
+
+pub enum Message {
+    Input(u32, Vec<key_bindings::Action>),
+    Check(u32, u64, u32),
+}
+
+pub struct Connection {
+    stream: TcpStream,
+    read_buf: Vec<u8>,
+    outgoing: VecDeque<(Instant, Vec<u8>)>,
+    //Artificial conditions for testing over loopback
+    pub latency: Duration,
+    pub loss: f32,
+    pub disconnected: bool,
+}
+
+//Waits for an opponent without holding up the window, the lobby is advertised on the local
+//network until someone joins
+pub struct Host {
+    listener: TcpListener,
+    advertiser: lobby::Advertiser,
+    pub bindings: key_bindings::KeyBindings,
+    //Given to the connection once someone joins
+    pub latency: Duration,
+    pub loss: f32,
+    //Someone who has connected but not yet said which pieces they have, with what they've sent
+    joining: Option<(TcpStream, SocketAddr, Vec<u8>)>,
+}
+
+impl Host {
+
+    pub fn new(port: u16, lobby_name: &str, mode: &str, bindings: key_bindings::KeyBindings) -> Result<Host> {
+
+        let listener = TcpListener::bind(("0.0.0.0", port)).context("Unable to listen")?;
+        listener.set_nonblocking(true)?;
+        println!("Hosting {} on port {}, waiting for opponent", lobby_name, port);
+
+        let advertiser = lobby::Advertiser::new(lobby::LobbyInfo {
+            id: rand::random::<u64>(),
+            name: lobby_name.to_string(),
+            mode: mode.to_string(),
+            players: 1,
+            max_players: 2,
+            port,
+        })?;
+
+        Ok(Host { listener, advertiser, bindings, latency: Duration::from_millis(0), loss: 0.0, joining: None })
+    }
+
+    pub fn info(&self) -> &lobby::LobbyInfo {
+        &self.advertiser.info
+    }
+
+    //Called every tick, returns the connection, the seed both sides play with and which player
+    //is local once someone has joined
+    pub fn update(&mut self) -> Result<Option<(Connection, u64, usize)>> {
+
+        self.advertiser.update();
+
+        if self.joining.is_none() {
+            match self.listener.accept() {
+                Ok((stream, addr)) => {
+                    stream.set_nonblocking(true)?;
+                    self.joining = Some((stream, addr, Vec::new()));
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let line = match self.read_join() {
+            Some(l) => l,
+            None => return Ok(None),
+        };
+        let (mut stream, addr, _) = self.joining.take().unwrap();
+        let pieces = piece_set::active().fingerprint();
+        match line.strip_prefix("join ").map(|f| f.trim().parse::<u64>()) {
+            Some(Ok(f)) if f == pieces => (),
+            Some(Ok(_)) => {
+                println!("{} is playing with different pieces, still waiting", addr);
+                let _ = stream.write_all(b"refused the host is playing with a different piece set\n");
+                return Ok(None);
+            },
+            _ => {
+                println!("Unexpected handshake from {}: {}", addr, line);
+                return Ok(None);
+            },
+        }
+        stream.set_nonblocking(false)?;
+        println!("Opponent connected from {}", addr);
+
+        let seed = rand::random::<u64>();
+        stream.write_all(format!("seed {}\n", seed).as_bytes())?;
+
+        let mut connection = Connection::new(stream)?;
+        connection.latency = self.latency;
+        connection.loss = self.loss;
+        Ok(Some((connection, seed, 0)))
+    }
+
+    //The joining line once it's all in, one byte at a time so nothing after it gets swallowed
+    fn read_join(&mut self) -> Option<String> {
+
+        let (stream, addr, line) = self.joining.as_mut()?;
+        let mut byte = [0u8; 1];
+        loop {
+            match stream.read(&mut byte) {
+                Ok(0) => {
+                    println!("{} left before joining", addr);
+                    break;
+                },
+                Ok(_) if byte[0] == b'\n' => return Some(String::from_utf8_lossy(line).to_string()),
+                Ok(_) => line.push(byte[0]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return None,
+                Err(e) => {
+                    println!("Lost {} while joining: {}", addr, e);
+                    break;
+                },
+            }
+        }
+        self.joining = None;
+        None
+    }
+
+    pub fn render(&self, r: &mut r_backend::Renderer) {
+        //Where the menus put their titles
+        let info = self.info();
+        let details = format!("{} on port {}\nWaiting for an opponent", info.mode, info.port);
+        r.render_text(&info.name, [-1.0, 7.4], 0.8, text::Align::Centre, [1.0, 1.0, 1.0, 1.0], -0.5, text::FONT.to_string());
+        r.render_text(&details, [-1.0, 6.0], 0.4, text::Align::Centre, [0.8, 0.8, 0.8, 1.0], -0.5, text::FONT.to_string());
+    }
+}
+
+impl Connection {
+
+    pub fn connect(addr: &str) -> Result<(Connection, u64, usize)> {
+
+        let mut stream = TcpStream::connect(addr).context("Unable to connect")?;
+        println!("Connected to {}", addr);
+        stream.write_all(format!("join {}\n", piece_set::active().fingerprint()).as_bytes())?;
+
+        //Read byte by byte so nothing after the handshake line gets swallowed
+        let mut line = Vec::new();
+        let mut byte = [0u8; 1];
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                bail!("Host closed the connection during handshake");
+            }
+            if byte[0] == b'\n' {
+                break;
+            }
+            line.push(byte[0]);
+        }
+        let line = String::from_utf8(line)?;
+        if let Some(reason) = line.strip_prefix("refused ") {
+            bail!("Host refused to play: {}", reason);
+        }
+        let seed = match line.strip_prefix("seed ") {
+            Some(s) => s.trim().parse::<u64>()?,
+            None => bail!("Unexpected handshake: {}", line),
+        };
+
+        Ok((Connection::new(stream)?, seed, 1))
+    }
+
+    fn new(stream: TcpStream) -> Result<Connection> {
+
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+
+        Ok(Connection {
+            stream,
+            read_buf: Vec::new(),
+            outgoing: VecDeque::new(),
+            latency: Duration::from_millis(0),
+            loss: 0.0,
+            disconnected: false,
+        })
+    }
+
+    //Lines sent together are delayed or dropped together, like one packet
+    pub fn send(&mut self, lines: &[String]) {
+
+        if self.loss > 0.0 && rand::random::<f32>() < self.loss {
+            return;
+        }
+        let mut packet = Vec::new();
+        for line in lines {
+            packet.extend_from_slice(line.as_bytes());
+            packet.push(b'\n');
+        }
+        self.outgoing.push_back((Instant::now() + self.latency, packet));
+        self.flush();
+    }
+
+    fn flush(&mut self) {
+
+        while let Some((due, _)) = self.outgoing.front() {
+            if *due > Instant::now() {
+                break;
+            }
+            let (_, packet) = self.outgoing.pop_front().unwrap();
+            if let Err(e) = self.stream.write_all(&packet) {
+                println!("Lost connection: {}", e);
+                self.disconnected = true;
+                break;
+            }
+        }
+    }
+
+    pub fn poll(&mut self) -> Vec<Message> {
+
+        self.flush();
+
+        let mut buf = [0u8; 4096];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => {
+                    println!("Opponent disconnected");
+                    self.disconnected = true;
+                    break;
+                }
+                Ok(n) => self.read_buf.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    println!("Lost connection: {}", e);
+                    self.disconnected = true;
+                    break;
+                }
+            }
+        }
+
+        let mut messages = Vec::new();
+        while let Some(end) = self.read_buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.read_buf.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line).trim().to_string();
+            match parse_message(&line) {
+                Some(m) => messages.push(m),
+                None => {
+                    println!("Bad message from opponent: {}", line);
+                    self.disconnected = true;
+                }
+            }
+        }
+        messages
+    }
+}
+
+pub struct NetVersus {
+    versus: versus::Versus,
+    local: usize,
+    connection: Connection,
+    tick: u32,
+    local_inputs: BTreeMap<u32, Vec<key_bindings::Action>>,
+    remote_inputs: BTreeMap<u32, Vec<key_bindings::Action>>,
+    remote_checks: BTreeMap<u32, (u64, u32)>,
+    expected_checks: BTreeMap<u32, (u64, u32)>,
+    pub desynced: bool,
+}
+
+impl NetVersus {
+
+    pub fn new(connection: Connection, seed: u64, local: usize, bindings: key_bindings::KeyBindings) -> NetVersus {
+
+        let mut versus = versus::Versus::new_seeded([bindings.clone(), bindings], seed);
+        versus.players[0].queue_actions = true;
+        versus.players[1].queue_actions = true;
+
+        let mut local_inputs = BTreeMap::new();
+        let mut remote_inputs = BTreeMap::new();
+        for t in 0..INPUT_DELAY {
+            local_inputs.insert(t, Vec::new());
+            remote_inputs.insert(t, Vec::new());
+        }
+
+        NetVersus {
+            versus,
+            local,
+            connection,
+            tick: 0,
+            local_inputs,
+            remote_inputs,
+            remote_checks: BTreeMap::new(),
+            expected_checks: BTreeMap::new(),
+            desynced: false,
+        }
+    }
+
+    pub fn local_player(&mut self) -> &mut game::Game {
+        &mut self.versus.players[self.local]
+    }
+
+    //Only what happened to the player here, the other board is someone else's to hear
+    pub fn take_local_events(&mut self) -> Vec<GameEvent> {
+        let local = self.local;
+        self.versus.take_events().into_iter().filter(|(i, _)| *i == local).map(|(_, e)| e).collect()
+    }
+
+    pub fn input(&mut self, event: &WindowEvent) {
+        self.versus.players[self.local].input(event);
+    }
+
+    pub fn process_inputs(&mut self, w_di: [u32; 2]) {
+        self.versus.players[self.local].process_inputs(w_di);
+    }
+
+    pub fn update(&mut self) {
+
+        if self.desynced || self.connection.disconnected {
+            return;
+        }
+
+        for message in self.connection.poll() {
+            match message {
+                Message::Input(tick, actions) => {
+                    //Resends of ticks already played
+                    if tick >= self.tick {
+                        self.remote_inputs.insert(tick, actions);
+                    }
+                },
+                Message::Check(tick, hash, garbage) => {
+                    self.remote_checks.insert(tick, (hash, garbage));
+                },
+            }
+        }
+
+        let mut lines = Vec::new();
+        //Lockstep, the simulation only moves once the opponent's inputs for this tick are in
+        if self.versus.outcome.is_none() {
+            if let Some(remote_actions) = self.remote_inputs.remove(&self.tick) {
+                lines.push(self.step(remote_actions));
+            }
+        }
+
+        //Resent even while stalled, the opponent may be waiting on one that was lost
+        for (t, actions) in self.local_inputs.range(self.tick.saturating_sub(RESEND_TICKS)..) {
+            lines.push(format!("i {} {}", t, encode_actions(actions)));
+        }
+        self.connection.send(&lines);
+
+        let oldest = self.tick.saturating_sub(RESEND_TICKS);
+        self.local_inputs = self.local_inputs.split_off(&oldest);
+        //Checks the opponent never sent, because of packet loss, would otherwise pile up
+        self.expected_checks = self.expected_checks.split_off(&self.tick.saturating_sub(600));
+        self.remote_checks = self.remote_checks.split_off(&self.tick.saturating_sub(600));
+    }
+
+    //Plays this tick with both sides' inputs and returns the check line for it
+    fn step(&mut self, remote_actions: Vec<key_bindings::Action>) -> String {
+
+        let queued = self.versus.players[self.local].take_queued_actions();
+        self.local_inputs.insert(self.tick + INPUT_DELAY, queued);
+        let local_actions = self.local_inputs.get(&self.tick).cloned().unwrap_or_default();
+
+        let remote = 1 - self.local;
+        let mut actions = [Vec::new(), Vec::new()];
+        actions[self.local] = local_actions;
+        actions[remote] = remote_actions;
+        for (player, actions) in self.versus.players.iter_mut().zip(actions.iter()) {
+            for action in actions.iter() {
+                player.apply_action(*action);
+            }
+        }
+        self.versus.update();
+
+        let local_check = (self.versus.players[self.local].state_hash(), self.versus.last_sent[self.local]);
+        let check_line = format!("c {} {} {}", self.tick, local_check.0, local_check.1);
+        let remote_check = (self.versus.players[remote].state_hash(), self.versus.last_sent[remote]);
+        self.expected_checks.insert(self.tick, remote_check);
+        self.compare_checks();
+
+        self.tick += 1;
+        check_line
+    }
+
+    fn compare_checks(&mut self) {
+
+        let ready: Vec<u32> = self.remote_checks.keys().copied().filter(|t| self.expected_checks.contains_key(t)).collect();
+        for t in ready {
+            let remote = self.remote_checks.remove(&t).unwrap();
+            let expected = self.expected_checks.remove(&t).unwrap();
+            if remote != expected {
+                println!("Desync at tick {}: opponent reported hash {} garbage {}, expected hash {} garbage {}", t, remote.0, remote.1, expected.0, expected.1);
+                self.desynced = true;
+            }
+        }
+    }
+
+    pub fn render(&mut self, r: &mut r_backend::Renderer, camera: &mut camera::Camera) {
+        self.versus.render(r, camera);
+    }
+}
+
+pub fn parse_message(line: &str) -> Option<Message> {
+
+    let parts: Vec<&str> = line.split(' ').collect();
+    match parts.as_slice() {
+        ["i", tick, actions] => Some(Message::Input(tick.parse().ok()?, decode_actions(actions)?)),
+        ["c", tick, hash, garbage] => Some(Message::Check(tick.parse().ok()?, hash.parse().ok()?, garbage.parse().ok()?)),
+        _ => None,
+    }
+}
+
+pub fn encode_actions(actions: &[key_bindings::Action]) -> String {
+
+    if actions.is_empty() {
+        return "-".to_string();
+    }
+    actions.iter().map(|a| a.name()).collect::<Vec<&str>>().join(",")
+}
+
+pub fn decode_actions(text: &str) -> Option<Vec<key_bindings::Action>> {
+
+    if text == "-" {
+        return Some(Vec::new());
+    }
+    text.split(',').map(key_bindings::Action::from_name).collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use key_bindings::Action;
+
+    //Both ends of a loopback connection, as they'd be after the handshake
+    pub(crate) fn connected_pair() -> (Connection, Connection) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (Connection::new(server).unwrap(), Connection::new(client).unwrap())
+    }
+
+    #[test]
+    fn actions_round_trip() {
+        let actions = vec![Action::MoveLeft, Action::RotateCw, Action::HardDrop];
+        let text = encode_actions(&actions);
+        assert_eq!(text, "move_left,rotate_cw,hard_drop");
+        assert_eq!(decode_actions(&text), Some(actions));
+        assert_eq!(encode_actions(&[]), "-");
+        assert_eq!(decode_actions("-"), Some(Vec::new()));
+        assert_eq!(decode_actions("move_left,jump"), None);
+    }
+
+    #[test]
+    fn parses_messages() {
+        match parse_message("i 12 move_left,soft_drop") {
+            Some(Message::Input(12, actions)) => assert_eq!(actions, vec![Action::MoveLeft, Action::SoftDrop]),
+            _ => panic!("input not parsed"),
+        }
+        assert!(matches!(parse_message("c 7 123456789 4"), Some(Message::Check(7, 123456789, 4))));
+        assert!(parse_message("i 12").is_none());
+        assert!(parse_message("c x 1 2").is_none());
+        assert!(parse_message("seed 5").is_none());
+    }
+
+    fn play(sides: &mut [NetVersus; 2], ticks: u32) {
+        let start = std::time::Instant::now();
+        while sides.iter().any(|s| s.tick < ticks) {
+            for side in sides.iter_mut() {
+                side.update();
+            }
+            assert!(start.elapsed() < Duration::from_secs(10), "stalled at ticks {} {}", sides[0].tick, sides[1].tick);
+        }
+    }
+
+    #[test]
+    fn stays_in_sync() {
+        let (host, joiner) = connected_pair();
+        let bindings = key_bindings::KeyBindings::default_bindings();
+        let mut sides = [NetVersus::new(host, 42, 0, bindings.clone()), NetVersus::new(joiner, 42, 1, bindings)];
+        sides[0].local_player().handle_action(Action::MoveLeft);
+        sides[1].local_player().handle_action(Action::HardDrop);
+        play(&mut sides, 120);
+        assert!(!sides[0].desynced && !sides[1].desynced);
+        assert_eq!(sides[0].versus.players[0].state_hash(), sides[1].versus.players[0].state_hash());
+        assert_eq!(sides[0].versus.players[1].state_hash(), sides[1].versus.players[1].state_hash());
+    }
+
+    #[test]
+    fn survives_packet_loss() {
+        let (mut host, mut joiner) = connected_pair();
+        host.loss = 0.3;
+        joiner.loss = 0.3;
+        let bindings = key_bindings::KeyBindings::default_bindings();
+        let mut sides = [NetVersus::new(host, 7, 0, bindings.clone()), NetVersus::new(joiner, 7, 1, bindings)];
+        play(&mut sides, 120);
+        assert!(!sides[0].desynced && !sides[1].desynced);
+    }
+
+    //Answers one join the way a host would, after a pause so the joiner is left waiting first
+    fn answer_join(listener: TcpListener, answer: &'static str) -> std::thread::JoinHandle<String> {
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut line = Vec::new();
+            let mut byte = [0u8; 1];
+            while stream.read(&mut byte).unwrap() == 1 && byte[0] != b'\n' {
+                line.push(byte[0]);
+            }
+            std::thread::sleep(Duration::from_millis(100));
+            stream.write_all(answer.as_bytes()).unwrap();
+            String::from_utf8(line).unwrap()
+        })
+    }
+
+    fn wait_for(joining: &mut Joining) -> Result<(Connection, u64, usize)> {
+        let start = std::time::Instant::now();
+        loop {
+            if let Some(joined) = joining.update()? {
+                return Ok(joined);
+            }
+            assert!(start.elapsed() < Duration::from_secs(10), "never heard back");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn joins_without_blocking() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let host = answer_join(listener, "seed 99\n");
+        let mut joining = Joining::start(addr, "rollback", key_bindings::KeyBindings::default_bindings());
+        //The host hasn't answered yet, the caller carries on
+        assert!(joining.update().unwrap().is_none());
+        let (_, seed, local) = wait_for(&mut joining).unwrap();
+        assert_eq!((seed, local), (99, 1));
+        assert_eq!(host.join().unwrap(), format!("join {}", piece_set::active().fingerprint()));
+    }
+
+    #[test]
+    fn refused_joins_are_errors() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let host = answer_join(listener, "refused different pieces\n");
+        let mut joining = Joining::start(addr, "lockstep", key_bindings::KeyBindings::default_bindings());
+        let e = wait_for(&mut joining).err().unwrap();
+        assert!(e.to_string().contains("different pieces"));
+        host.join().unwrap();
+    }
+
+    #[test]
+    fn spots_a_desync() {
+        let (host, joiner) = connected_pair();
+        let bindings = key_bindings::KeyBindings::default_bindings();
+        let mut sides = [NetVersus::new(host, 3, 0, bindings.clone()), NetVersus::new(joiner, 4, 1, bindings)];
+        //Different seeds, so each side's idea of the other's board is wrong from the first piece
+        let start = std::time::Instant::now();
+        while !(sides[0].desynced && sides[1].desynced) && start.elapsed() < Duration::from_secs(10) {
+            for side in sides.iter_mut() {
+                side.update();
+            }
+        }
+        assert!(sides[0].desynced && sides[1].desynced);
+    }
+}
@@ -0,0 +1,426 @@
+use crate::game;
+use crate::tetromino;
+use crate::tetris_board;
+use crate::game_options;
+use crate::r_backend;
+use crate::piece_set;
+
+use std::collections::HashSet;
+
+/*
+Looks for placements of the current piece, hold and visible queue that empty the board within a
+line limit. The bottom MAX_LINES rows are one u64 bitboard, ten bits a row with row 0 at the
+bottom and bit 0 of a row being board x 1, so placing, clearing and hashing a position is a few
+shifts. Only hard drops are tried: everything sits below the limit, so the rows above are empty
+and any drop can be lined up by shifting before the piece comes down.
+*/
+
+const WIDTH: usize = game_options::BOARD_WIDTH - 2;
+pub const MAX_LINES: usize = 6;
+const ROW: u64 = (1 << WIDTH) - 1;
+//Positions looked at before giving up, keeps a hopeless queue from stalling the game
+const MAX_NODES: u32 = 400_000;
+
+#[derive(Debug, Clone)]
+pub struct Step {
+    //Where the piece ends up, in board coordinates at the time it is placed
+    pub piece: tetromino::Tetromino,
+    pub hold: bool,
+    //The bitboard once this piece has locked and its lines have cleared
+    pub board: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Solution {
+    pub lines: usize,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Clone)]
+struct Shape {
+    piece: tetromino::Tetromino,
+    //Filled cells relative to the piece's x and y
+    cells: Vec<(i8, i8)>,
+}
+
+//Each rotation of each kind, rotated in the middle of an empty board so no kick moves it
+fn shapes() -> Vec<Vec<Shape>> {
+
+    let empty = tetris_board::TetrisBoard::new();
+    (0..piece_set::active().len() as u8).map(|kind| {
+        (0..4).map(|rotation| {
+            let mut piece = tetromino::Tetromino::new_piece(kind);
+            piece.x = 6;
+            piece.y = 10;
+            for _ in 0..rotation {
+                piece.rotate_piece(1, &empty);
+            }
+            let cells = piece.cells().iter().map(|(x, y)| (*x as i8 - piece.x as i8, *y as i8 - piece.y as i8)).collect();
+            Shape { piece, cells }
+        }).collect()
+    }).collect()
+}
+
+//None if anything sits above the rows the solver looks at. Rows waiting to clear are left out.
+pub fn bitboard(board: &tetris_board::TetrisBoard) -> Option<u64> {
+
+    let mut bits = 0;
+    let mut rows = 0;
+    for y in 1..(game_options::BOARD_HEIGHT - 1) {
+        let mut row = 0;
+        for x in 0..WIDTH {
+            if board.cell(x + 1, y) > 0 {
+                row |= 1 << x;
+            }
+        }
+        if row == ROW {
+            continue;
+        }
+        if row != 0 && rows >= MAX_LINES {
+            return None;
+        }
+        if rows < MAX_LINES {
+            bits |= row << (rows * WIDTH);
+        }
+        rows += 1;
+    }
+    Some(bits)
+}
+
+fn clear_lines(board: u64) -> (u64, usize) {
+
+    let mut kept = 0;
+    let mut cleared = 0;
+    let mut out = 0;
+    for r in 0..MAX_LINES {
+        let row = (board >> (r * WIDTH)) & ROW;
+        if row == ROW {
+            cleared += 1;
+        }
+        else {
+            out |= row << (kept * WIDTH);
+            kept += 1;
+        }
+    }
+    (out, cleared)
+}
+
+fn column_mask(column: usize) -> u64 {
+    (0..MAX_LINES).fold(0, |mask, r| mask | 1 << (r * WIDTH + column))
+}
+
+//Every pocket of empty cells under the ceiling has to be filled by whole pieces
+fn regions_fillable(board: u64, ceiling: usize, piece_size: u32) -> bool {
+
+    let not_left = !column_mask(0);
+    let not_right = !column_mask(WIDTH - 1);
+    let mut free = !board & ((1u64 << (ceiling * WIDTH)) - 1);
+    while free != 0 {
+        let mut region = free & free.wrapping_neg();
+        loop {
+            let grown = (region | region << WIDTH | region >> WIDTH | (region << 1 & not_left) | (region >> 1 & not_right)) & free;
+            if grown == region {
+                break;
+            }
+            region = grown;
+        }
+        if !region.count_ones().is_multiple_of(piece_size) {
+            return false;
+        }
+        free &= !region;
+    }
+    true
+}
+
+struct Search {
+    shapes: Vec<Vec<Shape>>,
+    piece_size: usize,
+    queue: Vec<u8>,
+    failed: HashSet<(u64, usize, usize, u8, Option<u8>)>,
+    nodes: u32,
+    steps: Vec<Step>,
+}
+
+impl Search {
+
+    //Hard drops of one kind that stay under the ceiling, each set of cells once
+    fn placements(&self, board: u64, ceiling: usize, kind: u8) -> Vec<(u64, tetromino::Tetromino)> {
+
+        let fits = |cells: &[(i8, i8)], x: i8, y: i8| {
+            cells.iter().all(|(dx, dy)| {
+                let column = x + dx - 1;
+                let row = y + dy - 1;
+                column >= 0 && column < WIDTH as i8 && row >= 0 && (row >= ceiling as i8 || board & 1 << (row as usize * WIDTH + column as usize) == 0)
+            })
+        };
+
+        let mut found: Vec<(u64, tetromino::Tetromino)> = Vec::new();
+        for shape in self.shapes[kind as usize].iter() {
+            let bottom = shape.cells.iter().map(|c| c.1).min().unwrap_or(0);
+            let top = shape.cells.iter().map(|c| c.1).max().unwrap_or(0);
+            for x in 0..(WIDTH + piece_set::MAX_SIZE) as i8 {
+                //Drop from just above the ceiling, where the rows are known to be empty
+                let mut y = ceiling as i8 + 1 - bottom;
+                if !fits(&shape.cells, x, y) {
+                    continue;
+                }
+                while fits(&shape.cells, x, y - 1) {
+                    y -= 1;
+                }
+                if y + top > ceiling as i8 {
+                    continue;
+                }
+                let mask = shape.cells.iter().fold(0u64, |m, (dx, dy)| m | 1 << ((y + dy - 1) as usize * WIDTH + (x + dx - 1) as usize));
+                if found.iter().any(|(m, _)| *m == mask) {
+                    continue;
+                }
+                let mut piece = shape.piece.clone();
+                piece.x = x as u8;
+                piece.y = y as u8;
+                found.push((mask, piece));
+            }
+        }
+        found
+    }
+
+    fn search(&mut self, board: u64, ceiling: usize, current: Option<u8>, hold: Option<u8>, next: usize, can_hold: bool) -> bool {
+
+        self.nodes += 1;
+        if self.nodes > MAX_NODES {
+            return false;
+        }
+        let current = match current {
+            Some(c) => c,
+            None => return false,
+        };
+
+        let empty = (ceiling * WIDTH) as u32 - board.count_ones();
+        let pieces_left = 1 + hold.is_some() as usize + self.queue.len() - next;
+        if empty as usize > pieces_left * self.piece_size || !regions_fillable(board, ceiling, self.piece_size as u32) {
+            return false;
+        }
+        let key = (board, ceiling, next, current, hold);
+        if self.failed.contains(&key) {
+            return false;
+        }
+
+        //(kind placed, held first, piece after it, hold after it, queue position after it)
+        let mut options = vec![(current, false, self.queue.get(next).copied(), hold, next + 1)];
+        if can_hold {
+            match hold {
+                Some(h) if h != current => options.push((h, true, self.queue.get(next).copied(), Some(current), next + 1)),
+                None => if let Some(n) = self.queue.get(next).copied() {
+                    options.push((n, true, self.queue.get(next + 1).copied(), Some(current), next + 2));
+                },
+                _ => (),
+            }
+        }
+
+        for (kind, held, after, hold_after, next_after) in options {
+            for (mask, piece) in self.placements(board, ceiling, kind) {
+                let (cleared_board, cleared) = clear_lines(board | mask);
+                self.steps.push(Step { piece, hold: held, board: cleared_board });
+                if cleared_board == 0 {
+                    return true;
+                }
+                if self.search(cleared_board, ceiling - cleared, after, hold_after, next_after.min(self.queue.len()), true) {
+                    return true;
+                }
+                self.steps.pop();
+            }
+        }
+
+        if self.nodes <= MAX_NODES {
+            self.failed.insert(key);
+        }
+        false
+    }
+}
+
+//Tries fewer lines first, a perfect clear is a perfect clear
+pub fn solve(game: &game::Game, max_lines: usize) -> Option<Solution> {
+
+    let piece_size = match piece_set::active().uniform_size() {
+        Some(size) => size,
+        None => {
+            println!("Perfect clears can only be searched for when every piece is the same size");
+            return None;
+        }
+    };
+    let board = bitboard(game.board())?;
+    let filled = board.count_ones() as usize;
+    let height = (0..MAX_LINES).filter(|r| (board >> (r * WIDTH)) & ROW != 0).map(|r| r + 1).max().unwrap_or(1);
+    let available = 1 + game.hold_piece().is_some() as usize + game.next_pieces().len();
+
+    let mut search = Search { shapes: shapes(), piece_size, queue: game.next_pieces().to_vec(), failed: HashSet::new(), nodes: 0, steps: Vec::new() };
+    for lines in height..=max_lines.min(MAX_LINES) {
+        let empty = lines * WIDTH - filled;
+        if !empty.is_multiple_of(piece_size) || empty / piece_size > available {
+            continue;
+        }
+        search.failed.clear();
+        search.steps.clear();
+        if search.search(board, lines, Some(game.piece().kind), game.hold_piece(), 0, game.can_hold()) {
+            return Some(Solution { lines, steps: search.steps });
+        }
+        if search.nodes > MAX_NODES {
+            println!("Perfect clear search gave up after {} positions", MAX_NODES);
+            return None;
+        }
+    }
+    None
+}
+
+//Shows the next placement of a solution and moves on as the player follows it
+#[derive(Clone)]
+pub struct Trainer {
+    pub max_lines: usize,
+    solution: Option<Solution>,
+    step: usize,
+    start: u64,
+    checked_for: Option<(u32, bool)>,
+}
+
+impl Trainer {
+
+    pub fn new(max_lines: usize) -> Trainer {
+        Trainer { max_lines, solution: None, step: 0, start: 0, checked_for: None }
+    }
+
+    //Checked whenever a new piece spawns or hold swaps it, once the board has settled. The game's
+    //events belong to the play mode, so a pending clear is read off the board.
+    pub fn update(&mut self, game: &game::Game) {
+
+        let key = (game.pieces_placed(), game.can_hold());
+        if self.checked_for == Some(key) || game.board().did_zero {
+            return;
+        }
+        self.checked_for = Some(key);
+
+        let board = bitboard(game.board());
+        if let Some(solution) = &self.solution {
+            if self.step < solution.steps.len() && board == Some(solution.steps[self.step].board) {
+                self.step += 1;
+                if self.step == solution.steps.len() {
+                    println!("Perfect clear!");
+                }
+            }
+            if self.step < solution.steps.len() && board == Some(self.board_before()) && self.on_track(game) {
+                return;
+            }
+        }
+
+        self.solution = solve(game, self.max_lines);
+        self.step = 0;
+        self.start = board.unwrap_or(0);
+        match &self.solution {
+            Some(s) => println!("Perfect clear in {} lines with {} pieces", s.lines, s.steps.len()),
+            None => println!("No perfect clear within {} lines", self.max_lines),
+        }
+    }
+
+    fn board_before(&self) -> u64 {
+        match (&self.solution, self.step) {
+            (Some(s), step) if step > 0 => s.steps[step - 1].board,
+            _ => self.start,
+        }
+    }
+
+    //The piece in play is the one the step needs, or the player has yet to hold for it
+    fn on_track(&self, game: &game::Game) -> bool {
+
+        let step = match &self.solution {
+            Some(s) => &s.steps[self.step],
+            None => return false,
+        };
+        if !step.hold {
+            return game.can_hold() && game.piece().kind == step.piece.kind;
+        }
+        if game.can_hold() {
+            return game.hold_piece().unwrap_or(game.next_pieces()[0]) == step.piece.kind;
+        }
+        game.piece().kind == step.piece.kind
+    }
+
+    pub fn render(&self, r: &mut r_backend::Renderer, origin: [f32; 2], scale: f32) {
+
+        if let Some(solution) = &self.solution {
+            if let Some(step) = solution.steps.get(self.step) {
+                step.piece.render_outline(r, origin, scale);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Standard set kinds
+    const I: u8 = 0;
+    const O: u8 = 3;
+
+    fn search(queue: Vec<u8>) -> Search {
+        Search { shapes: shapes(), piece_size: 4, queue, failed: HashSet::new(), nodes: 0, steps: Vec::new() }
+    }
+
+    #[test]
+    fn reads_the_bottom_rows() {
+        let mut board = tetris_board::TetrisBoard::new();
+        assert_eq!(bitboard(&board), Some(0));
+        board.add_garbage(2, 5);
+        let row = ROW & !(1 << 4);
+        assert_eq!(bitboard(&board), Some(row | row << WIDTH));
+        board.add_garbage(MAX_LINES - 2, 5);
+        assert!(bitboard(&board).is_some());
+        board.add_garbage(1, 5);
+        assert_eq!(bitboard(&board), None);
+    }
+
+    #[test]
+    fn clears_full_rows() {
+        let below = 1;
+        let above = 1 << 3;
+        let board = below | ROW << WIDTH | above << (2 * WIDTH);
+        assert_eq!(clear_lines(board), (below | above << WIDTH, 1));
+        assert_eq!(clear_lines(ROW | ROW << WIDTH), (0, 2));
+    }
+
+    #[test]
+    fn pockets_have_to_fit_whole_pieces() {
+        assert!(regions_fillable(0, 4, 4));
+        //Three cells walled off in the corner of an otherwise full row
+        let walled = ROW & !0b111;
+        assert!(!regions_fillable(walled, 1, 4));
+        assert!(regions_fillable(walled, 1, 3));
+    }
+
+    #[test]
+    fn finds_a_clear_with_enough_pieces() {
+        let mut s = search(vec![O; 4]);
+        assert!(s.search(0, 2, Some(O), None, 0, false));
+        assert_eq!(s.steps.len(), 5);
+        assert_eq!(s.steps.last().map(|step| step.board), Some(0));
+        assert!(s.steps.iter().all(|step| step.piece.kind == O && !step.hold));
+
+        let mut s = search(vec![I; 9]);
+        assert!(s.search(0, 4, Some(I), None, 0, true));
+        assert_eq!(s.steps.len(), 10);
+    }
+
+    #[test]
+    fn gives_up_without_enough_pieces() {
+        let mut s = search(vec![O; 3]);
+        assert!(!s.search(0, 2, Some(O), None, 0, false));
+        assert!(s.steps.is_empty());
+    }
+
+    #[test]
+    fn holds_to_reach_a_clear() {
+        //A lone I can't fill two rows around Os, holding it for the rest of the queue can
+        let mut s = search(vec![O, O, O, O, O]);
+        assert!(s.search(0, 2, Some(I), None, 0, true));
+        assert!(s.steps[0].hold);
+        assert!(s.steps.iter().all(|step| step.piece.kind == O));
+    }
+}
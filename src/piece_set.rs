@@ -0,0 +1,289 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::fs;
+use std::sync::{Arc, RwLock};
+
+/*
+Piece sets are json files like res/piece_sets/tetrominoes.json
+
+{
+    "kick_tables": { "name": [0, 0, 0, 0, ...] },
+    "pieces": [
+        { "name": "T", "shape": [".#.", "###", "..."], "spawn": [7, 20], "kicks": "jlstz", "sprite": 5 }
+    ]
+}
+
+Shapes are square and read the way they look on screen, # for a filled cell. The spawn is the
+board position of the top left cell of the shape, with x counting leftwards like the board.
+Kicks name one of the built in tables (jlstz, i, o, none) or one from kick_tables, which lists
+for each of the four rotation states the x and y offset of every test, x inverted like the
+tables on https://tetris.wiki/Super_Rotation_System#Wall_Kicks. sprite picks the colour from
+the piece sprite array, 0 to 6. Sprite 7 is the garbage tile.
+*/
+
+pub const MAX_SIZE: usize = 6;
+//Board cells hold sprite + 1, and 8 on the board is garbage
+const MAX_SPRITE: u8 = 6;
+
+//https://tetris.wiki/Super_Rotation_System#Wall_Kicks invert X
+const JLSTZ_KICKS: [i8; 40] = [
+    0, 0,   0, 0,    0, 0,  0, 0,   0, 0,
+    0, 0,   -1, 0,   -1, -1,  0, 2,   -1, 2,
+    0, 0,   0, 0,   0, 0,   0, 0,   0, 0,
+    0, 0,   1, 0,  1, -1,   0, 2,   1, 2,
+];
+
+const I_KICKS: [i8; 40] = [
+    0, 0,   1, 0,    -2, 0,  1, 0,   -2, 0,
+    1, 0,  0, 0,    0, 0,  0, 1,    0, -2,
+    1, 1,  -1, 1,   2, 1,  -1, 0,   2, 0,
+    0, 1,   0, 1,   0, 1,   0, -1,  0, 2
+];
+
+const O_KICKS: [i8; 8] = [
+    0, 0,
+    0, -1,
+    1, -1,
+    1, 0
+];
+
+const NO_KICKS: [i8; 8] = [0; 8];
+
+//The standard seven, the same as res/piece_sets/tetrominoes.json
+const STANDARD: &str = r####"{
+    "pieces": [
+        { "name": "I", "shape": [".....", ".....", ".####", ".....", "....."], "spawn": [8, 22], "kicks": "i", "sprite": 0 },
+        { "name": "J", "shape": ["#..", "###", "..."], "spawn": [7, 20], "kicks": "jlstz", "sprite": 1 },
+        { "name": "L", "shape": ["..#", "###", "..."], "spawn": [7, 20], "kicks": "jlstz", "sprite": 2 },
+        { "name": "O", "shape": [".##", ".##", "..."], "spawn": [7, 20], "kicks": "o", "sprite": 3 },
+        { "name": "S", "shape": [".##", "##.", "..."], "spawn": [7, 20], "kicks": "jlstz", "sprite": 4 },
+        { "name": "T", "shape": [".#.", "###", "..."], "spawn": [7, 20], "kicks": "jlstz", "sprite": 5 },
+        { "name": "Z", "shape": ["##.", ".##", "..."], "spawn": [7, 20], "kicks": "jlstz", "sprite": 6 }
+    ]
+}"####;
+
+#[derive(Deserialize)]
+struct PieceFile {
+    #[serde(default)]
+    kick_tables: BTreeMap<String, Vec<i8>>,
+    pieces: Vec<PieceEntry>,
+}
+
+#[derive(Deserialize)]
+struct PieceEntry {
+    name: String,
+    shape: Vec<String>,
+    spawn: (u8, u8),
+    kicks: String,
+    sprite: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub struct PieceDef {
+    pub name: String,
+    //Row by row from the top, sprite + 1 in filled cells so they can go straight on the board
+    pub data: Vec<u8>,
+    pub spawn: (u8, u8),
+    pub kicks: Vec<i8>,
+    pub sprite: u8,
+}
+
+impl PieceDef {
+
+    pub fn size(&self) -> usize {
+        self.data.iter().filter(|c| **c > 0).count()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub struct PieceSet {
+    pub pieces: Vec<PieceDef>,
+}
+
+pub fn kick_table(name: &str) -> Option<Vec<i8>> {
+    match name {
+        "jlstz" => Some(JLSTZ_KICKS.to_vec()),
+        "i" => Some(I_KICKS.to_vec()),
+        "o" => Some(O_KICKS.to_vec()),
+        "none" => Some(NO_KICKS.to_vec()),
+        _ => None,
+    }
+}
+
+static ACTIVE: RwLock<Option<Arc<PieceSet>>> = RwLock::new(None);
+
+//The set new pieces come from, the standard one until another is made active
+pub fn active() -> Arc<PieceSet> {
+    if let Some(set) = ACTIVE.read().ok().and_then(|s| s.clone()) {
+        return set;
+    }
+    let set = Arc::new(PieceSet::standard());
+    if let Ok(mut s) = ACTIVE.write() {
+        *s = Some(set.clone());
+    }
+    set
+}
+
+pub fn set_active(set: PieceSet) {
+    if let Ok(mut s) = ACTIVE.write() {
+        *s = Some(Arc::new(set));
+    }
+}
+
+impl PieceSet {
+
+    pub fn standard() -> PieceSet {
+        PieceSet::parse(STANDARD).expect("built in piece set")
+    }
+
+    pub fn load(path: &str) -> Result<PieceSet> {
+        let contents = fs::read_to_string(path).with_context(|| format!("Couldn't read piece set {}", path))?;
+        PieceSet::parse(&contents).with_context(|| format!("Bad piece set {}", path))
+    }
+
+    pub fn parse(contents: &str) -> Result<PieceSet> {
+
+        let file: PieceFile = serde_json::from_str(contents)?;
+        if file.pieces.is_empty() {
+            bail!("No pieces in the set");
+        }
+        if file.pieces.len() > u8::MAX as usize {
+            bail!("Too many pieces, at most {}", u8::MAX);
+        }
+
+        let mut pieces = Vec::new();
+        for entry in file.pieces {
+            let size = entry.shape.len();
+            if size == 0 || size > MAX_SIZE {
+                bail!("{} has to be between 1 and {} rows", entry.name, MAX_SIZE);
+            }
+            if entry.sprite > MAX_SPRITE {
+                bail!("{} uses sprite {}, the last one is {}", entry.name, entry.sprite, MAX_SPRITE);
+            }
+
+            let mut data = Vec::new();
+            for row in entry.shape.iter() {
+                if row.chars().count() != size {
+                    bail!("{} isn't square, row {:?} should be {} wide", entry.name, row, size);
+                }
+                data.extend(row.chars().map(|c| if c == '#' { entry.sprite + 1 } else { 0 }));
+            }
+            if data.iter().all(|c| *c == 0) {
+                bail!("{} has no filled cells", entry.name);
+            }
+
+            let kicks = match file.kick_tables.get(&entry.kicks).cloned().or_else(|| kick_table(&entry.kicks)) {
+                Some(table) => table,
+                None => bail!("{} uses unknown kick table {}", entry.name, entry.kicks),
+            };
+            if kicks.is_empty() || kicks.len() % 8 != 0 {
+                bail!("Kick table {} needs an x and y for each test in each of the four rotations", entry.kicks);
+            }
+
+            pieces.push(PieceDef { name: entry.name, data, spawn: entry.spawn, kicks, sprite: entry.sprite });
+        }
+
+        Ok(PieceSet { pieces })
+    }
+
+    pub fn len(&self) -> usize {
+        self.pieces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pieces.is_empty()
+    }
+
+    pub fn piece(&self, kind: u8) -> &PieceDef {
+        &self.pieces[kind as usize % self.pieces.len()]
+    }
+
+    //Both sides of an online game have to play with the same pieces, this is what they compare
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    //The seven tetrominoes in their usual order, which is all some outside tools understand
+    pub fn is_standard(&self) -> bool {
+        *self == PieceSet::standard()
+    }
+
+    //Every piece covers the same number of cells, like the standard set
+    pub fn uniform_size(&self) -> Option<usize> {
+        let size = self.pieces[0].size();
+        if self.pieces.iter().all(|p| p.size() == size) { Some(size) } else { None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standard_matches_the_bundled_file() {
+        let standard = PieceSet::standard();
+        assert_eq!(standard.len(), 7);
+        assert!(standard.is_standard());
+        assert_eq!(standard.uniform_size(), Some(4));
+        assert_eq!(PieceSet::load("res/piece_sets/tetrominoes.json").unwrap(), standard);
+    }
+
+    #[test]
+    fn bundled_sets_load() {
+        let trominoes = PieceSet::load("res/piece_sets/trominoes.json").unwrap();
+        assert_eq!(trominoes.uniform_size(), Some(3));
+        assert!(!trominoes.is_standard());
+        let pentominoes = PieceSet::load("res/piece_sets/pentominoes.json").unwrap();
+        assert_eq!(pentominoes.uniform_size(), Some(5));
+    }
+
+    #[test]
+    fn cells_hold_the_sprite() {
+        let t = PieceSet::standard().pieces.into_iter().find(|p| p.name == "T").unwrap();
+        assert_eq!(t.data, vec![0, 6, 0, 6, 6, 6, 0, 0, 0]);
+        assert_eq!(t.size(), 4);
+        assert_eq!(t.kicks, JLSTZ_KICKS.to_vec());
+    }
+
+    #[test]
+    fn fingerprint_follows_the_pieces() {
+        let standard = PieceSet::standard();
+        assert_eq!(standard.fingerprint(), PieceSet::standard().fingerprint());
+        let mut changed = PieceSet::standard();
+        changed.pieces[0].sprite = 3;
+        assert_ne!(standard.fingerprint(), changed.fingerprint());
+        let mut reordered = PieceSet::standard();
+        reordered.pieces.swap(0, 1);
+        assert_ne!(standard.fingerprint(), reordered.fingerprint());
+        assert!(!reordered.is_standard());
+    }
+
+    #[test]
+    fn custom_kick_tables() {
+        let set = PieceSet::parse(r####"{
+            "kick_tables": { "still": [0, 0, 0, 0, 0, 0, 0, 0] },
+            "pieces": [{ "name": "D", "shape": ["##", "##"], "spawn": [6, 20], "kicks": "still", "sprite": 3 }]
+        }"####).unwrap();
+        assert_eq!(set.pieces[0].kicks, vec![0; 8]);
+    }
+
+    #[test]
+    fn rejects_bad_sets() {
+        let piece = |shape: &str, kicks: &str, sprite: u8| format!(r####"{{ "pieces": [{{ "name": "X", "shape": {}, "spawn": [6, 20], "kicks": "{}", "sprite": {} }}] }}"####, shape, kicks, sprite);
+        assert!(PieceSet::parse(r####"{ "pieces": [] }"####).is_err());
+        assert!(PieceSet::parse(&piece(r####"["##", "#"]"####, "jlstz", 0)).is_err());
+        assert!(PieceSet::parse(&piece(r####"["..", ".."]"####, "jlstz", 0)).is_err());
+        assert!(PieceSet::parse(&piece(r####"["##", "#."]"####, "sideways", 0)).is_err());
+        assert!(PieceSet::parse(&piece(r####"["##", "#."]"####, "jlstz", 9)).is_err());
+        assert!(PieceSet::parse(&piece(r####"["##", "#."]"####, "jlstz", 7)).is_err());
+        assert!(PieceSet::parse(&piece(r####"["##", "#."]"####, "jlstz", 6)).is_ok());
+        let too_big = vec!["\".......\""; 7].join(", ");
+        assert!(PieceSet::parse(&piece(&format!("[{}]", too_big), "jlstz", 0)).is_err());
+        assert!(PieceSet::parse(&piece(r####"["##", "#."]"####, "jlstz", 0)).is_ok());
+    }
+}
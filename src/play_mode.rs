@@ -0,0 +1,203 @@
+use crate::net;
+use crate::game;
+use crate::camera;
+use crate::versus;
+use crate::rollback;
+use crate::lobby;
+use crate::r_backend;
+use crate::key_bindings;
+use crate::events::GameEvent;
+
+use winit::event::*;
+
+pub enum PlayMode {
+    Single(game::Game),
+    Local(versus::Versus),
+    Lockstep(net::NetVersus),
+    Rollback(rollback::RollbackVersus),
+    Browser(lobby::LobbyBrowser),
+    //Waiting for someone to join, becomes Lockstep or Rollback when they do
+    Hosting(net::Host),
+    //Connecting to a lobby picked in the browser, back to the browser if that fails
+    Joining(net::Joining),
+}
+
+impl PlayMode {
+
+    //mode is "rollback" or "lockstep", the same names lobbies are advertised with
+    pub fn online(connection: net::Connection, seed: u64, local: usize, mode: &str, bindings: key_bindings::KeyBindings) -> PlayMode {
+        if mode == "rollback" {
+            PlayMode::Rollback(rollback::RollbackVersus::new(connection, seed, local, bindings))
+        }
+        else {
+            PlayMode::Lockstep(net::NetVersus::new(connection, seed, local, bindings))
+        }
+    }
+
+    pub fn input(&mut self, event: &WindowEvent) {
+        match self {
+            PlayMode::Single(g) => g.input(event),
+            PlayMode::Local(v) => v.input(event),
+            PlayMode::Lockstep(n) => n.input(event),
+            PlayMode::Rollback(n) => n.input(event),
+            PlayMode::Browser(b) => b.input(event),
+            PlayMode::Hosting(_) | PlayMode::Joining(_) => (),
+        }
+    }
+
+    pub fn process_inputs(&mut self, w_di: [u32; 2]) {
+        match self {
+            PlayMode::Single(g) => g.process_inputs(w_di),
+            PlayMode::Local(v) => v.process_inputs(w_di),
+            PlayMode::Lockstep(n) => n.process_inputs(w_di),
+            PlayMode::Rollback(n) => n.process_inputs(w_di),
+            PlayMode::Browser(b) => b.process_inputs(),
+            PlayMode::Hosting(_) | PlayMode::Joining(_) => (),
+        }
+    }
+
+    pub fn update(&mut self) {
+        match self {
+            //Topping out is picked up by the scene, which shows the results
+            PlayMode::Single(g) => g.update(),
+            PlayMode::Local(v) => v.update(),
+            PlayMode::Lockstep(n) => n.update(),
+            PlayMode::Rollback(n) => n.update(),
+            PlayMode::Browser(b) => {
+                b.update();
+                if let Some((addr, mode)) = b.take_join() {
+                    *self = PlayMode::Joining(net::Joining::start(addr, &mode, b.bindings.clone()));
+                }
+            },
+            PlayMode::Hosting(h) => match h.update() {
+                Ok(Some((connection, seed, local))) => {
+                    let mode = h.info().mode.clone();
+                    *self = PlayMode::online(connection, seed, local, &mode, h.bindings.clone());
+                },
+                Ok(None) => (),
+                Err(e) => println!("Couldn't accept opponent: {:?}", e),
+            },
+            PlayMode::Joining(j) => match j.update() {
+                Ok(Some((connection, seed, local))) => {
+                    let mode = j.mode.clone();
+                    *self = PlayMode::online(connection, seed, local, &mode, j.bindings.clone());
+                },
+                Ok(None) => (),
+                Err(e) => {
+                    println!("Couldn't join {}: {:?}", j.addr, e);
+                    match lobby::LobbyBrowser::new(j.bindings.clone()) {
+                        Ok(b) => *self = PlayMode::Browser(b),
+                        Err(e) => println!("{:?}", e),
+                    }
+                },
+            },
+        }
+    }
+
+    pub fn render(&mut self, r: &mut r_backend::Renderer, camera: &mut camera::Camera) {
+        match self {
+            PlayMode::Single(g) => g.render(r, camera),
+            PlayMode::Local(v) => v.render(r, camera),
+            PlayMode::Lockstep(n) => n.render(r, camera),
+            PlayMode::Rollback(n) => n.render(r, camera),
+            PlayMode::Browser(b) => b.render(r),
+            PlayMode::Hosting(h) => h.render(r),
+            PlayMode::Joining(j) => j.render(r),
+        }
+    }
+
+    pub fn release_keys(&mut self) {
+        match self {
+            PlayMode::Single(g) => g.release_keys(),
+            PlayMode::Local(v) => v.players.iter_mut().for_each(|p| p.release_keys()),
+            PlayMode::Lockstep(n) => n.local_player().release_keys(),
+            PlayMode::Rollback(n) => n.local_player().release_keys(),
+            PlayMode::Browser(_) => (),
+            PlayMode::Hosting(_) | PlayMode::Joining(_) => (),
+        }
+    }
+
+    //Any player at this keyboard can pause, bots can't
+    pub fn is_pause_key(&mut self, key: VirtualKeyCode) -> bool {
+        let pauses = |g: &game::Game| g.bot.is_none() && g.bindings.action_for(key) == Some(key_bindings::Action::Pause);
+        match self {
+            PlayMode::Single(g) => pauses(g),
+            PlayMode::Local(v) => v.players.iter().any(pauses),
+            PlayMode::Lockstep(n) => pauses(n.local_player()),
+            PlayMode::Rollback(n) => pauses(n.local_player()),
+            PlayMode::Browser(_) => false,
+            PlayMode::Hosting(_) | PlayMode::Joining(_) => false,
+        }
+    }
+
+    //The first local player's bindings, the ones the controls menu edits as player 1
+    pub fn bindings_mut(&mut self) -> &mut key_bindings::KeyBindings {
+        match self {
+            PlayMode::Single(g) => &mut g.bindings,
+            PlayMode::Local(v) => &mut v.players[0].bindings,
+            PlayMode::Lockstep(n) => &mut n.local_player().bindings,
+            PlayMode::Rollback(n) => &mut n.local_player().bindings,
+            PlayMode::Browser(b) => &mut b.bindings,
+            PlayMode::Hosting(h) => &mut h.bindings,
+            PlayMode::Joining(j) => &mut j.bindings,
+        }
+    }
+
+    //Whether a second person is playing at this keyboard, so player 2's bindings are live
+    pub fn has_second_player(&self) -> bool {
+        match self {
+            PlayMode::Local(v) => v.players[1].bot.is_none(),
+            _ => false,
+        }
+    }
+
+    //Both local players' bindings, only when has_second_player
+    pub fn both_bindings_mut(&mut self) -> Option<[&mut key_bindings::KeyBindings; 2]> {
+        match self {
+            PlayMode::Local(v) if v.players[1].bot.is_none() => {
+                let [one, two] = &mut v.players;
+                Some([&mut one.bindings, &mut two.bindings])
+            },
+            _ => None,
+        }
+    }
+
+    //The player a bot takes over, the right hand side in local versus so a person can play it
+    pub fn bot_player(&mut self) -> Option<&mut game::Game> {
+        match self {
+            PlayMode::Single(g) => Some(g),
+            PlayMode::Local(v) => Some(&mut v.players[1]),
+            PlayMode::Lockstep(n) => Some(n.local_player()),
+            PlayMode::Rollback(n) => Some(n.local_player()),
+            PlayMode::Browser(_) => None,
+            PlayMode::Hosting(_) | PlayMode::Joining(_) => None,
+        }
+    }
+
+    //Events from the players at this keyboard, a computer opponent's stay on its side of the screen
+    pub fn take_events(&mut self) -> Vec<GameEvent> {
+        match self {
+            PlayMode::Single(g) => g.take_events(),
+            PlayMode::Local(v) => {
+                let events = v.take_events();
+                events.into_iter().filter(|(i, _)| v.players[*i].bot.is_none()).map(|(_, e)| e).collect()
+            },
+            PlayMode::Lockstep(n) => n.take_local_events(),
+            PlayMode::Rollback(n) => n.take_local_events(),
+            PlayMode::Browser(_) => Vec::new(),
+            PlayMode::Hosting(_) | PlayMode::Joining(_) => Vec::new(),
+        }
+    }
+
+    //The first local player, the one hints are shown for
+    pub fn human_player(&mut self) -> Option<&mut game::Game> {
+        match self {
+            PlayMode::Single(g) => Some(g),
+            PlayMode::Local(v) => Some(&mut v.players[0]),
+            PlayMode::Lockstep(n) => Some(n.local_player()),
+            PlayMode::Rollback(n) => Some(n.local_player()),
+            PlayMode::Browser(_) => None,
+            PlayMode::Hosting(_) | PlayMode::Joining(_) => None,
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use crate::piece_set;
+use crate::game_options;
+
+use anyhow::{bail, Result};
+use std::collections::BTreeSet;
+
+/*
+Builds piece sets out of every polyomino of a size instead of a file. Shapes grow one cell at a
+time from the single square and are kept once per equivalence class:
+fixed        every orientation is its own piece
+one_sided    rotations are the same piece, mirror images aren't, the usual Pentris set
+free         rotations and mirror images are the same piece
+Each shape sits in a square as big as its longer side and turns inside that square, the same
+bounding box rule the standard pieces use, with the jlstz kicks.
+*/
+
+pub const MAX_CELLS: usize = piece_set::MAX_SIZE;
+//Sprites 7 and 8 are garbage grey and the line clear white
+const COLOURS: u8 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Equivalence {
+    Fixed,
+    OneSided,
+    Free,
+}
+
+impl Equivalence {
+
+    pub fn from_name(name: &str) -> Option<Equivalence> {
+        match name {
+            "fixed" => Some(Equivalence::Fixed),
+            "one_sided" => Some(Equivalence::OneSided),
+            "free" => Some(Equivalence::Free),
+            _ => None,
+        }
+    }
+}
+
+type Shape = Vec<(i32, i32)>;
+
+//Moved so the smallest x and y are 0, and sorted so equal shapes compare equal
+fn normalise(cells: &[(i32, i32)]) -> Shape {
+    let min_x = cells.iter().map(|c| c.0).min().unwrap_or(0);
+    let min_y = cells.iter().map(|c| c.1).min().unwrap_or(0);
+    let mut shape: Shape = cells.iter().map(|(x, y)| (x - min_x, y - min_y)).collect();
+    shape.sort();
+    shape
+}
+
+fn rotate(shape: &[(i32, i32)]) -> Shape {
+    normalise(&shape.iter().map(|(x, y)| (*y, -*x)).collect::<Vec<_>>())
+}
+
+fn mirror(shape: &[(i32, i32)]) -> Shape {
+    normalise(&shape.iter().map(|(x, y)| (-*x, *y)).collect::<Vec<_>>())
+}
+
+//The smallest of the shapes this one counts as the same as
+fn canonical(shape: &[(i32, i32)], equivalence: Equivalence) -> Shape {
+
+    let mut forms = vec![normalise(shape)];
+    if equivalence != Equivalence::Fixed {
+        for i in 0..3 {
+            let next = rotate(&forms[i]);
+            forms.push(next);
+        }
+    }
+    if equivalence == Equivalence::Free {
+        let mirrored: Vec<Shape> = forms.iter().map(|f| mirror(f)).collect();
+        forms.extend(mirrored);
+    }
+    forms.into_iter().min().unwrap_or_default()
+}
+
+//Every fixed polyomino of the size, then one of each class
+pub fn generate(cells: usize, equivalence: Equivalence) -> Vec<Shape> {
+
+    let mut fixed: BTreeSet<Shape> = BTreeSet::new();
+    fixed.insert(vec![(0, 0)]);
+    for _ in 1..cells {
+        let mut grown = BTreeSet::new();
+        for shape in fixed.iter() {
+            for (x, y) in shape.iter() {
+                for (dx, dy) in [(1, 0), (-1, 0), (0, 1), (0, -1)].iter() {
+                    let cell = (x + dx, y + dy);
+                    if shape.contains(&cell) {
+                        continue;
+                    }
+                    let mut bigger = shape.clone();
+                    bigger.push(cell);
+                    grown.insert(normalise(&bigger));
+                }
+            }
+        }
+        fixed = grown;
+    }
+
+    let classes: BTreeSet<Shape> = fixed.iter().map(|s| canonical(s, equivalence)).collect();
+    classes.into_iter().collect()
+}
+
+fn piece_def(name: String, shape: &[(i32, i32)], sprite: u8) -> piece_set::PieceDef {
+
+    let width = shape.iter().map(|c| c.0).max().unwrap_or(0) + 1;
+    let height = shape.iter().map(|c| c.1).max().unwrap_or(0) + 1;
+    let size = width.max(height);
+    //Centred in its square, rounding up and left
+    let left = (size - width) / 2;
+    let top = (size - height) / 2;
+
+    let mut data = vec![0; (size * size) as usize];
+    for (x, y) in shape.iter() {
+        data[((top + y) * size + left + x) as usize] = sprite + 1;
+    }
+
+    //Centred on the board, with the top filled row on the highest playable row
+    let spawn_x = (game_options::BOARD_WIDTH as i32 + size) / 2;
+    let spawn_y = game_options::BOARD_HEIGHT as i32 - 2 + top;
+    let kicks = piece_set::kick_table("jlstz").unwrap_or_default();
+    piece_set::PieceDef { name, data, spawn: (spawn_x as u8, spawn_y as u8), kicks, sprite }
+}
+
+//Pieces for every size from min_cells to max_cells, coloured in turn
+pub fn piece_set(min_cells: usize, max_cells: usize, equivalence: Equivalence) -> Result<piece_set::PieceSet> {
+
+    if min_cells < 1 || max_cells > MAX_CELLS || min_cells > max_cells {
+        bail!("Polyomino sizes go from 1 to {}", MAX_CELLS);
+    }
+
+    let mut pieces = Vec::new();
+    for cells in min_cells..=max_cells {
+        for (i, shape) in generate(cells, equivalence).iter().enumerate() {
+            let sprite = (pieces.len() % COLOURS as usize) as u8;
+            pieces.push(piece_def(format!("{}-{}", cells, i + 1), shape, sprite));
+        }
+    }
+    if pieces.len() > u8::MAX as usize {
+        bail!("{} pieces is more than the {} a set can hold", pieces.len(), u8::MAX);
+    }
+    Ok(piece_set::PieceSet { pieces })
+}
+
+//Parses sizes like 5 or 3-5, then optionally a comma and fixed, one_sided or free
+pub fn from_spec(spec: &str) -> Result<piece_set::PieceSet> {
+
+    let mut parts = spec.splitn(2, ',');
+    let sizes = parts.next().unwrap_or("");
+    let equivalence = match parts.next() {
+        Some(name) => match Equivalence::from_name(name) {
+            Some(e) => e,
+            None => bail!("Unknown polyomino kind {}, try fixed, one_sided or free", name),
+        },
+        None => Equivalence::OneSided,
+    };
+    let (min_cells, max_cells) = match sizes.find('-') {
+        Some(i) => (sizes[..i].parse()?, sizes[i + 1..].parse()?),
+        None => {
+            let n = sizes.parse()?;
+            (n, n)
+        }
+    };
+    piece_set(min_cells, max_cells, equivalence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_match_the_known_sequences() {
+        let fixed = [1, 2, 6, 19, 63, 216];
+        let one_sided = [1, 1, 2, 7, 18, 60];
+        let free = [1, 1, 2, 5, 12, 35];
+        for cells in 1..=MAX_CELLS {
+            assert_eq!(generate(cells, Equivalence::Fixed).len(), fixed[cells - 1], "fixed {}", cells);
+            assert_eq!(generate(cells, Equivalence::OneSided).len(), one_sided[cells - 1], "one sided {}", cells);
+            assert_eq!(generate(cells, Equivalence::Free).len(), free[cells - 1], "free {}", cells);
+        }
+    }
+
+    #[test]
+    fn rotation_and_mirroring() {
+        let l = vec![(0, 0), (0, 1), (0, 2), (1, 2)];
+        let mut turned = l.clone();
+        for _ in 0..4 {
+            turned = rotate(&turned);
+        }
+        assert_eq!(turned, normalise(&l));
+        assert_eq!(mirror(&mirror(&l)), normalise(&l));
+        assert_ne!(canonical(&l, Equivalence::OneSided), canonical(&mirror(&l), Equivalence::OneSided));
+        assert_eq!(canonical(&l, Equivalence::Free), canonical(&mirror(&l), Equivalence::Free));
+    }
+
+    #[test]
+    fn pieces_sit_in_their_square() {
+        let set = piece_set(4, 4, Equivalence::OneSided).unwrap();
+        assert_eq!(set.len(), 7);
+        assert_eq!(set.uniform_size(), Some(4));
+        for (i, piece) in set.pieces.iter().enumerate() {
+            let side = (piece.data.len() as f32).sqrt() as usize;
+            assert_eq!(side * side, piece.data.len());
+            assert_eq!(piece.sprite, (i % COLOURS as usize) as u8);
+        }
+    }
+
+    #[test]
+    fn specs() {
+        assert_eq!(from_spec("5").unwrap().len(), 18);
+        assert_eq!(from_spec("3-4,free").unwrap().len(), 2 + 5);
+        assert_eq!(from_spec("4,fixed").unwrap().len(), 19);
+        assert!(from_spec("4,twisted").is_err());
+        assert!(from_spec("0").is_err());
+        assert!(from_spec("5-3").is_err());
+        assert!(from_spec("7").is_err());
+        assert!(from_spec("six").is_err());
+    }
+}
@@ -2,6 +2,7 @@ use wgpu::util::DeviceExt;
 use std::collections::HashMap;
 
 use crate::texture;
+use crate::text;
 use glob::glob;
 
 #[repr(C)]
@@ -171,7 +172,13 @@ impl ArrayTextureVerticesInfo {
 
     pub fn new_sprite_array(device: &wgpu::Device, queue: &wgpu::Queue, path: &str) -> ArrayTextureVerticesInfo {
 
-        let mut verts: Vec<ArrayVertex> = Vec::new();
+        let texture = texture::Texture::from_array_to_image(device, queue, path).unwrap();
+        ArrayTextureVerticesInfo::from_texture(device, texture)
+    }
+
+    pub fn from_texture(device: &wgpu::Device, texture: texture::Texture) -> ArrayTextureVerticesInfo {
+
+        let verts: Vec<ArrayVertex> = Vec::new();
 
         let buf = device.create_buffer_init(
             &wgpu::util::BufferInitDescriptor {
@@ -180,8 +187,6 @@ impl ArrayTextureVerticesInfo {
                 usage: wgpu::BufferUsage::VERTEX,
             }
         );
-        
-        let texture = texture::Texture::from_array_to_image(device, queue, path).unwrap();
 
         let texture_bind_group_layout = texture::Texture::get_array_bind_group_layout(device);
 
@@ -219,6 +224,7 @@ pub struct Renderer {
 
     pub render_info: HashMap<String, TextureVerticesInfo>,
     pub render_array_info: HashMap<String, ArrayTextureVerticesInfo>,
+    pub fonts: HashMap<String, text::Font>,
 }
 
 impl Renderer {
@@ -227,8 +233,9 @@ impl Renderer {
 
         let render_info = HashMap::new();
         let render_array_info = HashMap::new();
+        let fonts = HashMap::new();
 
-        Renderer { render_info, render_array_info }
+        Renderer { render_info, render_array_info, fonts }
     }
 
     pub fn load_sprite(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, path: &std::path::Path, name: String) {
@@ -272,6 +279,27 @@ impl Renderer {
         }
     }
 
+    //Loads over any array already under the name, so a skin can be swapped while running
+    pub fn replace_sprite_array(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, path: &str, name: String) {
+        println!("Loading texture: {}", name);
+        self.render_array_info.insert(name, ArrayTextureVerticesInfo::new_sprite_array(device, queue, path));
+    }
+
+    //The font's glyphs go in a sprite array under the same name
+    pub fn load_font(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, path: &str, name: String) -> anyhow::Result<()> {
+
+        if self.fonts.contains_key(&name) {
+            println!("Already loaded font: {}", name);
+            return Ok(());
+        }
+        println!("Loading font: {}", name);
+        let (font, layers) = text::Font::load(path)?;
+        let texture = texture::Texture::from_images(device, queue, &layers, path)?;
+        self.render_array_info.insert(name.clone(), ArrayTextureVerticesInfo::from_texture(device, texture));
+        self.fonts.insert(name, font);
+        Ok(())
+    }
+
     pub fn render_sprite(&mut self, pos: [f32; 2], size: [f32; 2], depth: f32, name: String) {
 
         match self.render_info.get_mut(&name) {
@@ -370,6 +398,47 @@ impl Renderer {
         }
     }
 
+    //Part of an array image from its top left, uv is how much of it across and down, tinted by colour
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_sprite_array_part(&mut self, pos: [f32; 2], size: [f32; 2], depth: f32, name: String, image_index: i32, uv: [f32; 2], colour: [f32; 4]) {
+
+        match self.render_array_info.get_mut(&name) {
+            Some(texture_vertices) => {
+
+                texture_vertices.verts.push(ArrayVertex { pos: [pos[0],           pos[1],           depth], uv: [0.0,   uv[1], image_index as f32], colour });
+                texture_vertices.verts.push(ArrayVertex { pos: [pos[0],           pos[1] + size[1], depth], uv: [0.0,   0.0,   image_index as f32], colour });
+                texture_vertices.verts.push(ArrayVertex { pos: [pos[0] + size[0], pos[1] + size[1], depth], uv: [uv[0], 0.0,   image_index as f32], colour });
+
+                texture_vertices.verts.push(ArrayVertex { pos: [pos[0],           pos[1],           depth], uv: [0.0,   uv[1], image_index as f32], colour });
+                texture_vertices.verts.push(ArrayVertex { pos: [pos[0] + size[0], pos[1] + size[1], depth], uv: [uv[0], 0.0,   image_index as f32], colour });
+                texture_vertices.verts.push(ArrayVertex { pos: [pos[0] + size[0], pos[1],           depth], uv: [uv[0], uv[1], image_index as f32], colour });
+            },
+            None => {
+                println!("Texture not found {}", name);
+            }
+        }
+    }
+
+    //pos is the top of the first line, size the height of a line
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_text(&mut self, text: &str, pos: [f32; 2], size: f32, align: text::Align, colour: [f32; 4], depth: f32, name: String) {
+
+        let quads = match self.fonts.get(&name) {
+            Some(font) => font.layout(text, pos, size, align),
+            None => {
+                println!("Font not found {}", name);
+                return;
+            }
+        };
+        for q in quads {
+            self.render_sprite_array_part(q.pos, q.size, depth, name.clone(), q.layer, q.uv, colour);
+        }
+    }
+
+    pub fn text_width(&self, text: &str, size: f32, name: &str) -> f32 {
+        self.fonts.get(name).map_or(0.0, |f| f.measure(text, size))
+    }
+
     pub fn update_buffers(&mut self, device: &wgpu::Device) {
 
         for texture_vertices in self.render_info.values_mut() {
@@ -0,0 +1,178 @@
+use crate::key_bindings;
+use crate::high_scores;
+use crate::stats;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/*
+A solo run kept as its seed and every action with the game tick it came in on. Everything else
+in a game follows from those, so a game started the same way and given the same actions on the
+same ticks plays out identically. Saved as json in REPLAY_DIR when the run makes a high score
+table, along with the stats it finished on, and played back with --replay <file>.
+*/
+
+pub const REPLAY_DIR: &str = "./replays";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replay {
+    pub seed: u64,
+    pub goal: String,
+    //Fingerprint of the piece set, a replay only makes sense with the same pieces
+    pub pieces: u64,
+    //The tick each action came after, and the action by its bindings file name
+    pub actions: Vec<(u32, String)>,
+    //How the run finished, filled in as it's saved. Playing it back counts them again.
+    #[serde(default)]
+    pub stats: Option<stats::Stats>,
+}
+
+impl Replay {
+
+    pub fn new(seed: u64, goal: &str, pieces: u64) -> Replay {
+        Replay { seed, goal: goal.to_string(), pieces, actions: Vec::new(), stats: None }
+    }
+
+    pub fn record(&mut self, tick: u32, action: key_bindings::Action) {
+        self.actions.push((tick, action.name().to_string()));
+    }
+
+    pub fn load(path: &str) -> Result<Replay> {
+        let contents = fs::read_to_string(path).with_context(|| format!("Couldn't read replay {}", path))?;
+        serde_json::from_str(&contents).with_context(|| format!("Bad replay {}", path))
+    }
+
+    //Named for the goal and when it was saved, returns where it went
+    pub fn save(&self) -> Result<String> {
+        fs::create_dir_all(REPLAY_DIR).with_context(|| format!("Couldn't make {}", REPLAY_DIR))?;
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+        let path = format!("{}/{}-{}-{}.json", REPLAY_DIR, self.goal, high_scores::today(), millis % 100000);
+        let contents = serde_json::to_string(self)?;
+        fs::write(&path, contents).with_context(|| format!("Couldn't write replay {}", path))?;
+        Ok(path)
+    }
+
+    pub fn decode_actions(&self) -> Result<Vec<(u32, key_bindings::Action)>> {
+        let mut actions = Vec::new();
+        for (tick, name) in self.actions.iter() {
+            match key_bindings::Action::from_name(name) {
+                Some(action) => actions.push((*tick, action)),
+                None => bail!("Unknown action {} in replay", name),
+            }
+        }
+        Ok(actions)
+    }
+}
+
+//Feeds a replay's actions back into a game on the ticks they were made
+#[derive(Debug, Clone)]
+pub struct Playback {
+    actions: Vec<(u32, key_bindings::Action)>,
+    next: usize,
+}
+
+impl Playback {
+
+    pub fn new(actions: Vec<(u32, key_bindings::Action)>) -> Playback {
+        Playback { actions, next: 0 }
+    }
+
+    //Everything recorded on this tick, in the order it happened
+    pub fn take(&mut self, tick: u32) -> Vec<key_bindings::Action> {
+        let mut due = Vec::new();
+        while let Some((t, action)) = self.actions.get(self.next) {
+            if *t > tick {
+                break;
+            }
+            due.push(*action);
+            self.next += 1;
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game;
+    use crate::piece_set;
+    use key_bindings::Action;
+
+    const SCRIPT: [Action; 6] = [Action::MoveLeft, Action::RotateCw, Action::HardDrop, Action::MoveRight, Action::Hold, Action::HardDrop];
+
+    fn new_game() -> game::Game {
+        game::Game::new_player(key_bindings::KeyBindings::default_bindings(), [0.0, 0.0], 0)
+    }
+
+    //Plays a few hundred ticks of inputs on a recorded game
+    fn recorded() -> game::Game {
+        let mut g = new_game();
+        g.restart_seeded(21);
+        g.recording = Some(Replay::new(21, "endless", piece_set::active().fingerprint()));
+        for tick in 0..400 {
+            if tick % 7 == 0 {
+                g.apply_action(SCRIPT[(tick / 7) % SCRIPT.len()]);
+            }
+            g.update();
+        }
+        g
+    }
+
+    #[test]
+    fn playback_gives_actions_on_their_tick() {
+        let mut playback = Playback::new(vec![(0, Action::MoveLeft), (0, Action::HardDrop), (3, Action::Hold)]);
+        assert_eq!(playback.take(0), vec![Action::MoveLeft, Action::HardDrop]);
+        assert!(playback.take(2).is_empty());
+        assert_eq!(playback.take(5), vec![Action::Hold]);
+        assert!(playback.take(6).is_empty());
+    }
+
+    #[test]
+    fn unknown_actions_are_refused() {
+        let mut replay = Replay::new(1, "sprint", 0);
+        replay.record(4, Action::SoftDrop);
+        assert_eq!(replay.decode_actions().unwrap(), vec![(4, Action::SoftDrop)]);
+        replay.actions.push((5, "teleport".to_string()));
+        assert!(replay.decode_actions().is_err());
+    }
+
+    #[test]
+    fn played_back_runs_end_the_same() {
+        let live = recorded();
+        let replay = live.recording.clone().unwrap();
+        assert!(!replay.actions.is_empty());
+        let json = serde_json::to_string(&replay).unwrap();
+        let mut watched = new_game();
+        watched.play_back(&serde_json::from_str(&json).unwrap()).unwrap();
+        assert!(watched.is_playback());
+        for _ in 0..400 {
+            watched.update();
+        }
+        assert_eq!(watched.state_hash(), live.state_hash());
+        assert_eq!(watched.stats.summary(), live.stats.summary());
+    }
+
+    #[test]
+    fn different_pieces_are_refused() {
+        let mut replay = Replay::new(1, "endless", piece_set::active().fingerprint() ^ 1);
+        assert!(new_game().play_back(&replay).is_err());
+        replay.pieces ^= 1;
+        replay.goal = "forever".to_string();
+        assert!(new_game().play_back(&replay).is_err());
+    }
+
+    #[test]
+    fn replays_keep_their_stats() {
+        let live = recorded();
+        let replay = Replay { stats: Some(live.stats.clone()), ..live.recording.clone().unwrap() };
+        let loaded: Replay = serde_json::from_str(&serde_json::to_string(&replay).unwrap()).unwrap();
+        let stats = loaded.stats.unwrap();
+        assert!(stats.pieces > 0);
+        assert_eq!(stats.summary(), live.stats.summary());
+        //Replays from before they were kept still load
+        let old = r#"{"seed":1,"goal":"endless","pieces":2,"actions":[[3,"hard_drop"]]}"#;
+        assert!(serde_json::from_str::<Replay>(old).unwrap().stats.is_none());
+    }
+}
@@ -0,0 +1,372 @@
+use crate::net;
+use crate::game;
+use crate::camera;
+use crate::versus;
+use crate::r_backend;
+use crate::key_bindings;
+use crate::events::GameEvent;
+
+use winit::event::*;
+use std::collections::{BTreeMap, VecDeque};
+
+//How far the local simulation may run ahead of the last tick with known remote inputs
+pub const MAX_ROLLBACK: u32 = 15;
+//Recent local inputs are resent every tick so a dropped packet is covered by the next one.
+//The opponent can't be waiting on anything older than two rollback windows back.
+const REDUNDANT_TICKS: u32 = MAX_ROLLBACK * 2;
+
+/*
+Same protocol as lockstep but local inputs apply on the tick they're made. Remote inputs
+that haven't arrived are predicted as nothing, and when they turn out to be something the
+game is restored from the snapshot at that tick and simulated forward again.
+*/
+
+//A board's state hash and the garbage it has sent, as the check messages carry them
+type Check = (u64, u32);
+
+pub struct RollbackVersus {
+    versus: versus::Versus,
+    local: usize,
+    connection: net::Connection,
+    tick: u32,
+    snapshots: VecDeque<(u32, versus::Versus)>,
+    local_inputs: BTreeMap<u32, Vec<key_bindings::Action>>,
+    remote_inputs: BTreeMap<u32, Vec<key_bindings::Action>>,
+    confirmed: u32,
+    //Both players' checks for each simulated tick, local then remote, until the tick is confirmed
+    checks: BTreeMap<u32, (Check, Check)>,
+    checks_sent: u32,
+    expected_checks: BTreeMap<u32, Check>,
+    remote_checks: BTreeMap<u32, Check>,
+    pub desynced: bool,
+    pub rollbacks: u32,
+    announced: bool,
+    //The local player's events for each tick not yet confirmed, as they were handed on. When a
+    //tick is simulated again only the ones that weren't there the first time go out.
+    events_sent: BTreeMap<u32, Vec<GameEvent>>,
+    events: Vec<GameEvent>,
+}
+
+impl RollbackVersus {
+
+    pub fn new(connection: net::Connection, seed: u64, local: usize, bindings: key_bindings::KeyBindings) -> RollbackVersus {
+
+        let mut versus = versus::Versus::new_seeded([bindings.clone(), bindings], seed);
+        versus.players[0].queue_actions = true;
+        versus.players[1].queue_actions = true;
+        //A predicted top out can be rolled back, so the winner is only announced once confirmed
+        versus.announce_winner = false;
+
+        RollbackVersus {
+            versus,
+            local,
+            connection,
+            tick: 0,
+            snapshots: VecDeque::new(),
+            local_inputs: BTreeMap::new(),
+            remote_inputs: BTreeMap::new(),
+            confirmed: 0,
+            checks: BTreeMap::new(),
+            checks_sent: 0,
+            expected_checks: BTreeMap::new(),
+            remote_checks: BTreeMap::new(),
+            desynced: false,
+            rollbacks: 0,
+            announced: false,
+            events_sent: BTreeMap::new(),
+            events: Vec::new(),
+        }
+    }
+
+    pub fn local_player(&mut self) -> &mut game::Game {
+        &mut self.versus.players[self.local]
+    }
+
+    //Only what happened to the player here, the other board is someone else's to hear
+    pub fn take_local_events(&mut self) -> Vec<GameEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    pub fn input(&mut self, event: &WindowEvent) {
+        self.versus.players[self.local].input(event);
+    }
+
+    pub fn process_inputs(&mut self, w_di: [u32; 2]) {
+        self.versus.players[self.local].process_inputs(w_di);
+    }
+
+    pub fn update(&mut self) {
+
+        if self.desynced || self.connection.disconnected {
+            return;
+        }
+
+        let mut rollback_to: Option<u32> = None;
+        for message in self.connection.poll() {
+            match message {
+                net::Message::Input(tick, actions) => {
+                    if tick < self.confirmed || self.remote_inputs.contains_key(&tick) {
+                        continue;
+                    }
+                    //Anything already simulated was predicted as no input
+                    if tick < self.tick && !actions.is_empty() {
+                        rollback_to = Some(rollback_to.map_or(tick, |t| t.min(tick)));
+                    }
+                    self.remote_inputs.insert(tick, actions);
+                },
+                net::Message::Check(tick, hash, garbage) => {
+                    self.remote_checks.insert(tick, (hash, garbage));
+                },
+            }
+        }
+
+        if let Some(tick) = rollback_to {
+            self.rollback(tick);
+        }
+
+        while self.remote_inputs.contains_key(&self.confirmed) {
+            self.confirmed += 1;
+        }
+
+        let mut lines = Vec::new();
+
+        //Only ticks simulated with the real inputs from both sides are worth checking
+        let final_tick = self.confirmed.min(self.tick);
+        while self.checks_sent < final_tick {
+            let t = self.checks_sent;
+            if let Some((local_check, remote_check)) = self.checks.remove(&t) {
+                lines.push(format!("c {} {} {}", t, local_check.0, local_check.1));
+                self.expected_checks.insert(t, remote_check);
+            }
+            self.checks_sent += 1;
+        }
+        self.compare_checks();
+
+        if self.versus.outcome.is_some() && !self.announced && self.confirmed >= self.tick {
+            println!("{}", self.versus.outcome_text());
+            self.announced = true;
+        }
+
+        if self.tick < self.confirmed + MAX_ROLLBACK {
+            let queued = self.versus.players[self.local].take_queued_actions();
+            self.local_inputs.insert(self.tick, queued);
+            self.simulate_tick();
+        }
+
+        //Resent even while stalled, otherwise a lost packet could leave both sides waiting
+        for t in self.tick.saturating_sub(REDUNDANT_TICKS)..self.tick {
+            if let Some(actions) = self.local_inputs.get(&t) {
+                lines.push(format!("i {} {}", t, net::encode_actions(actions)));
+            }
+        }
+        if !lines.is_empty() {
+            self.connection.send(&lines);
+        }
+
+        self.prune();
+    }
+
+    fn simulate_tick(&mut self) {
+
+        self.snapshots.push_back((self.tick, self.versus.clone()));
+
+        let remote = 1 - self.local;
+        let mut actions = [Vec::new(), Vec::new()];
+        actions[self.local] = self.local_inputs.get(&self.tick).cloned().unwrap_or_default();
+        actions[remote] = self.remote_inputs.get(&self.tick).cloned().unwrap_or_default();
+        for (player, actions) in self.versus.players.iter_mut().zip(actions.iter()) {
+            for action in actions.iter() {
+                player.apply_action(*action);
+            }
+        }
+        self.versus.update();
+        self.forward_events();
+
+        let local_check = (self.versus.players[self.local].state_hash(), self.versus.last_sent[self.local]);
+        let remote_check = (self.versus.players[remote].state_hash(), self.versus.last_sent[remote]);
+        self.checks.insert(self.tick, (local_check, remote_check));
+
+        self.tick += 1;
+    }
+
+    //A tick played again can turn out differently, a line clear that was predicted might not
+    //happen or one that wasn't might. What already went out can't be taken back, but anything
+    //new is passed on.
+    fn forward_events(&mut self) {
+        let local = self.local;
+        let mut already = self.events_sent.remove(&self.tick).unwrap_or_default();
+        let mut sent = Vec::new();
+        for (_, event) in self.versus.take_events().into_iter().filter(|(i, _)| *i == local) {
+            match already.iter().position(|e| *e == event) {
+                Some(i) => {
+                    already.remove(i);
+                },
+                None => self.events.push(event.clone()),
+            }
+            sent.push(event);
+        }
+        self.events_sent.insert(self.tick, sent);
+    }
+
+    fn rollback(&mut self, tick: u32) {
+
+        let target = self.tick;
+        while let Some((t, _)) = self.snapshots.back() {
+            if *t > tick {
+                self.snapshots.pop_back();
+            }
+            else {
+                break;
+            }
+        }
+        let (t, saved) = match self.snapshots.pop_back() {
+            Some(s) => s,
+            None => {
+                println!("No snapshot to roll back to tick {}", tick);
+                self.desynced = true;
+                return;
+            }
+        };
+
+        self.versus.load_state(&saved);
+        self.tick = t;
+        while self.tick < target {
+            self.simulate_tick();
+        }
+        self.rollbacks += 1;
+    }
+
+    fn compare_checks(&mut self) {
+
+        let ready: Vec<u32> = self.remote_checks.keys().copied().filter(|t| self.expected_checks.contains_key(t)).collect();
+        for t in ready {
+            let remote = self.remote_checks.remove(&t).unwrap();
+            let expected = self.expected_checks.remove(&t).unwrap();
+            if remote != expected {
+                println!("Desync at tick {}: opponent reported hash {} garbage {}, expected hash {} garbage {}", t, remote.0, remote.1, expected.0, expected.1);
+                self.desynced = true;
+            }
+        }
+    }
+
+    fn prune(&mut self) {
+
+        //Nothing before the confirmed tick can be rolled back to again. The opponent can be
+        //ahead, so inputs confirmed for ticks not yet simulated here are still needed.
+        let settled = self.confirmed.min(self.tick);
+        while let Some((t, _)) = self.snapshots.front() {
+            if *t < settled {
+                self.snapshots.pop_front();
+            }
+            else {
+                break;
+            }
+        }
+        let oldest_resend = self.tick.saturating_sub(REDUNDANT_TICKS);
+        self.remote_inputs = self.remote_inputs.split_off(&settled);
+        self.events_sent = self.events_sent.split_off(&settled);
+        self.local_inputs = self.local_inputs.split_off(&settled.min(oldest_resend));
+        //Checks the opponent never sent, because of packet loss, would otherwise pile up
+        self.expected_checks = self.expected_checks.split_off(&self.confirmed.saturating_sub(600));
+        self.remote_checks = self.remote_checks.split_off(&self.confirmed.saturating_sub(600));
+    }
+
+    pub fn render(&mut self, r: &mut r_backend::Renderer, camera: &mut camera::Camera) {
+        self.versus.render(r, camera);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use key_bindings::Action;
+    use std::time::{Duration, Instant};
+
+    const ACTIONS: [Action; 6] = [Action::MoveLeft, Action::RotateCw, Action::HardDrop, Action::MoveRight, Action::SoftDrop, Action::Hold];
+
+    fn sides(seed: u64, latency: Duration, loss: f32) -> [RollbackVersus; 2] {
+        let (mut host, mut joiner) = net::tests::connected_pair();
+        for connection in [&mut host, &mut joiner].iter_mut() {
+            connection.latency = latency;
+            connection.loss = loss;
+        }
+        let bindings = key_bindings::KeyBindings::default_bindings();
+        [RollbackVersus::new(host, seed, 0, bindings.clone()), RollbackVersus::new(joiner, seed, 1, bindings)]
+    }
+
+    //Each side presses something every few ticks up to inputs_until, on different ticks to
+    //the other, until both have heard every input up to until
+    fn play(sides: &mut [RollbackVersus; 2], inputs_until: u32, until: u32) {
+        let start = Instant::now();
+        let mut acted = [None, None];
+        while sides.iter().any(|s| s.confirmed < until) {
+            for (i, side) in sides.iter_mut().enumerate() {
+                let t = side.tick;
+                if t < inputs_until && t % 9 == i as u32 * 4 && acted[i] != Some(t) {
+                    side.local_player().handle_action(ACTIONS[(t / 9) as usize % ACTIONS.len()]);
+                    acted[i] = Some(t);
+                }
+                side.update();
+            }
+            assert!(start.elapsed() < Duration::from_secs(20), "stalled at ticks {} {}", sides[0].tick, sides[1].tick);
+        }
+    }
+
+    //Both boards as each side has them at the earliest tick neither can roll back past any more
+    fn settled_hashes(sides: &mut [RollbackVersus; 2]) -> [[u64; 2]; 2] {
+        let start = Instant::now();
+        loop {
+            let tick = sides[0].confirmed.max(sides[1].confirmed);
+            let saved: Vec<Option<&versus::Versus>> = sides.iter().map(|s| s.snapshots.iter().find(|(t, _)| *t == tick).map(|(_, v)| v)).collect();
+            if let (Some(a), Some(b)) = (saved[0], saved[1]) {
+                return [[a.players[0].state_hash(), a.players[1].state_hash()], [b.players[0].state_hash(), b.players[1].state_hash()]];
+            }
+            for side in sides.iter_mut() {
+                side.update();
+            }
+            assert!(start.elapsed() < Duration::from_secs(10), "no tick both sides have kept");
+        }
+    }
+
+    #[test]
+    fn rolls_back_late_inputs_and_stays_in_sync() {
+        let mut sides = sides(42, Duration::from_millis(40), 0.0);
+        play(&mut sides, 200, 260);
+        assert!(!sides[0].desynced && !sides[1].desynced);
+        assert!(sides[0].rollbacks > 0 && sides[1].rollbacks > 0);
+        let [a, b] = settled_hashes(&mut sides);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn survives_latency_and_packet_loss() {
+        let mut sides = sides(7, Duration::from_millis(25), 0.25);
+        play(&mut sides, 200, 260);
+        assert!(!sides[0].desynced && !sides[1].desynced);
+        assert!(sides[0].rollbacks > 0 && sides[1].rollbacks > 0);
+        let [a, b] = settled_hashes(&mut sides);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn spots_a_desync() {
+        let mut sides = sides(3, Duration::from_millis(10), 0.0);
+        play(&mut sides, 60, 60);
+        assert!(!sides[0].desynced && !sides[1].desynced);
+
+        //Garbage for the host that only the joiner knows about, in every state it could roll back
+        //to as well, so the host's reports stop matching what the joiner expects
+        sides[1].versus.players[0].receive_garbage(4);
+        for (_, saved) in sides[1].snapshots.iter_mut() {
+            saved.players[0].receive_garbage(4);
+        }
+        let start = Instant::now();
+        while !sides[1].desynced && start.elapsed() < Duration::from_secs(10) {
+            for side in sides.iter_mut() {
+                side.local_player().handle_action(Action::HardDrop);
+                side.update();
+            }
+        }
+        assert!(sides[1].desynced);
+    }
+}
@@ -0,0 +1,845 @@
+use crate::bot;
+use crate::game;
+use crate::input;
+use crate::camera;
+use crate::versus;
+use crate::lobby;
+use crate::r_backend;
+use crate::key_bindings;
+use crate::play_mode;
+use crate::high_scores;
+use crate::text;
+use crate::events::GameEvent;
+use crate::settings;
+use crate::music;
+use crate::goals;
+
+use winit::event::*;
+
+/*
+Screens are kept on a stack and only the top one gets input, updates and draws. Menus push what
+they open and pop back, starting a game replaces the mode select so leaving a game lands on the
+title, and a finished game replaces itself with its results. Escape backs out of anything.
+
+Title       Play, High scores, Settings, Quit
+ModeSelect  the local modes, online ones still start from the command line
+Scores      the high score table for each solo goal, Left and Right switch goal
+Settings    skin, volumes and controls
+Playing     a play mode, counting down before it carries on after a pause
+Paused      over a game, hiding it so the board can't be studied. Local games stop, online games
+            keep running underneath since the other side won't stop
+Results     the end of a solo game with its stats and where it placed on its goal's table
+*/
+
+//Middle of the default view
+const CENTRE_X: f32 = -1.0;
+const TITLE_Y: f32 = 7.4;
+const TITLE_SIZE: f32 = 0.8;
+const ITEM_SIZE: f32 = 0.4;
+const ITEM_SPACING: f32 = 0.6;
+const LINE_SIZE: f32 = 0.28;
+const DEPTH: f32 = -0.5;
+//About a second and a half, shown as 3 2 1
+const COUNTDOWN_TICKS: u32 = 90;
+const COUNTDOWN_STEPS: u32 = 3;
+//Left and Right on a volume move it this much
+const VOLUME_STEP: f32 = 0.1;
+
+//What a menu choice asks for
+pub enum Transition {
+    Stay,
+    Push(Scene),
+    Pop,
+    Replace(Scene),
+    //Back down to the title
+    Reset,
+    //Off the pause menu, back into the game under it
+    Resume,
+    Restart,
+    Quit,
+    //These need the window or renderer, so main sees to them
+    CycleSkin,
+    OpenControls,
+    //Volumes are changed on the stack's settings, main passes them on to the audio and saves them
+    AdjustVolume(f32),
+    AdjustEffects(f32),
+    AdjustMusic(f32),
+    SettingsChanged,
+}
+
+//A mode that can be started again from the results
+#[derive(Debug, Clone, Copy)]
+pub enum ModeChoice {
+    Solo(goals::Goal),
+    Versus,
+    Cpu(bot::Difficulty),
+    Browse,
+}
+
+impl ModeChoice {
+
+    pub fn start(&self) -> anyhow::Result<play_mode::PlayMode> {
+        let bindings = || key_bindings::KeyBindings::load(key_bindings::BINDINGS_PATH, key_bindings::KeyBindings::default_bindings());
+        Ok(match self {
+            ModeChoice::Solo(goal) => {
+                let mut g = game::Game::new();
+                g.set_goal(*goal);
+                play_mode::PlayMode::Single(g)
+            },
+            ModeChoice::Versus => play_mode::PlayMode::Local(versus::Versus::new()),
+            ModeChoice::Cpu(d) => play_mode::PlayMode::Local(versus::Versus::against_cpu(bindings(), *d)),
+            ModeChoice::Browse => play_mode::PlayMode::Browser(lobby::LobbyBrowser::new(bindings())?),
+        })
+    }
+}
+
+//A list of choices, Up and Down move between them
+pub struct Menu {
+    inputs: input::Inputs,
+    pub title: String,
+    pub items: Vec<String>,
+    pub selected: usize,
+}
+
+impl Menu {
+
+    pub fn new(title: &str, items: &[&str]) -> Menu {
+        let inputs = input::Inputs::new();
+        let title = title.to_string();
+        let items = items.iter().map(|i| i.to_string()).collect();
+        let selected = 0;
+        Menu { inputs, title, items, selected }
+    }
+
+    pub fn input(&mut self, event: &WindowEvent) {
+        self.inputs.input(event);
+    }
+
+    //Any key press other than moving the selection
+    pub fn process_inputs(&mut self) -> Option<VirtualKeyCode> {
+        match self.inputs.take_last_pressed()? {
+            VirtualKeyCode::Up => {
+                self.selected = (self.selected + self.items.len() - 1) % self.items.len();
+                None
+            },
+            VirtualKeyCode::Down => {
+                self.selected = (self.selected + 1) % self.items.len();
+                None
+            },
+            key => Some(key),
+        }
+    }
+
+    //Items start at y and go down the screen
+    pub fn render_items(&self, r: &mut r_backend::Renderer, y: f32) {
+        for (i, item) in self.items.iter().enumerate() {
+            let (label, colour) = if i == self.selected {
+                (format!("> {} <", item), [1.0, 1.0, 1.0, 1.0])
+            }
+            else {
+                (item.clone(), [0.55, 0.55, 0.55, 1.0])
+            };
+            r.render_text(&label, [CENTRE_X, y - i as f32 * ITEM_SPACING], ITEM_SIZE, text::Align::Centre, colour, DEPTH, text::FONT.to_string());
+        }
+    }
+
+    pub fn render(&self, r: &mut r_backend::Renderer) {
+        r.render_text(&self.title, [CENTRE_X, TITLE_Y], TITLE_SIZE, text::Align::Centre, [1.0, 1.0, 1.0, 1.0], DEPTH, text::FONT.to_string());
+        self.render_items(r, TITLE_Y - 1.6);
+    }
+}
+
+pub struct ModeSelect {
+    pub menu: Menu,
+    pub difficulty: bot::Difficulty,
+    pub goal: goals::Goal,
+}
+
+impl ModeSelect {
+
+    pub fn new() -> ModeSelect {
+        let menu = Menu::new("Play", &["", "Versus", "", "Find LAN games", "Back"]);
+        let mut select = ModeSelect { menu, difficulty: bot::Difficulty::Medium, goal: goals::Goal::Endless };
+        select.label();
+        select
+    }
+
+    fn label(&mut self) {
+        self.menu.items[0] = format!("Solo: {}", self.goal.title());
+        self.menu.items[2] = format!("Versus CPU: {}", self.difficulty.name());
+    }
+}
+
+impl Default for ModeSelect {
+    fn default() -> ModeSelect {
+        ModeSelect::new()
+    }
+}
+
+pub struct Playing {
+    pub mode: play_mode::PlayMode,
+    //None when it came from the command line and can't be set up again
+    pub choice: Option<ModeChoice>,
+    //Ticks left before play carries on
+    pub countdown: u32,
+}
+
+impl Playing {
+
+    //Online games can't wait for a pause menu, the other player is still going
+    fn runs_underneath(&self) -> bool {
+        matches!(self.mode, play_mode::PlayMode::Lockstep(_) | play_mode::PlayMode::Rollback(_) | play_mode::PlayMode::Browser(_) | play_mode::PlayMode::Hosting(_) | play_mode::PlayMode::Joining(_))
+    }
+
+    //A game joined or hosted online can't be set up again from here, starting over would drop
+    //the other player, even when the choice it came from was the lobby browser
+    fn can_restart(&self) -> bool {
+        match self.mode {
+            play_mode::PlayMode::Lockstep(_) | play_mode::PlayMode::Rollback(_) | play_mode::PlayMode::Hosting(_) => false,
+            play_mode::PlayMode::Single(_) => true,
+            _ => self.choice.is_some(),
+        }
+    }
+
+    fn restart(&mut self) {
+        if !self.can_restart() {
+            return;
+        }
+        match (self.choice, &mut self.mode) {
+            (Some(choice), _) => match choice.start() {
+                Ok(mode) => self.mode = mode,
+                Err(e) => println!("Couldn't restart: {:?}", e),
+            },
+            (None, play_mode::PlayMode::Single(g)) => g.restart(),
+            _ => return,
+        }
+        self.countdown = COUNTDOWN_TICKS;
+    }
+}
+
+pub struct Results {
+    pub menu: Menu,
+    pub lines: Vec<String>,
+    pub choice: Option<ModeChoice>,
+}
+
+impl Results {
+
+    //Prints and records the finished game as well, on the table for its goal
+    pub fn solo(g: &game::Game, choice: Option<ModeChoice>) -> Results {
+
+        println!("{}", g.stats.summary());
+        println!("{}", g.finesse.summary());
+        let goal = g.goal;
+        let result = goal.result(g);
+        //Watching a replay shouldn't put it on the table a second time
+        let place = match result {
+            Some(value) if !g.is_playback() => high_scores::record(goal.name(), high_scores::Entry::new(value, &g.stats), g.recording.as_ref()),
+            _ => None,
+        };
+
+        let headline = match (goal, result) {
+            (goals::Goal::Endless, _) => format!("{} lines  {} pieces", g.stats.lines, g.stats.pieces),
+            (goals::Goal::Marathon, _) | (goals::Goal::Ultra, _) => format!("Score {}  {} lines", g.stats.score, g.stats.lines),
+            (_, Some(ticks)) => format!("Time {}", high_scores::format_value(goal.name(), ticks)),
+            (_, None) => format!("{} not finished", goal.title()),
+        };
+        let mut lines = vec![
+            headline,
+            format!("PPS {:.2}  APM {:.1}  KPP {:.2}", g.stats.pps(), g.stats.apm(), g.stats.kpp()),
+            format!("Finesse {:.1}%", g.finesse.accuracy() * 100.0),
+        ];
+        lines.push(match place {
+            Some(p) => format!("New high score, #{}", p),
+            None => "No high score".to_string(),
+        });
+
+        let items: &[&str] = if choice.is_some() { &["Play again", "Title"] } else { &["Title"] };
+        let title = if g.finished { goal.title() } else { "Game over" };
+        Results { menu: Menu::new(title, items), lines, choice }
+    }
+}
+
+//The saved tables, read again whenever the goal shown changes
+pub struct Scores {
+    pub menu: Menu,
+    pub goal: goals::Goal,
+    pub lines: Vec<String>,
+}
+
+impl Scores {
+
+    pub fn new() -> Scores {
+        let mut scores = Scores { menu: Menu::new("", &["Back"]), goal: goals::Goal::Endless, lines: Vec::new() };
+        scores.show(goals::Goal::Endless);
+        scores
+    }
+
+    fn show(&mut self, goal: goals::Goal) {
+        self.goal = goal;
+        self.menu.title = format!("< {} >", goal.title());
+        let scores = high_scores::HighScores::load(high_scores::HIGH_SCORES_PATH);
+        let table = scores.table(goal.name());
+        self.lines = table.iter().enumerate().map(|(i, e)| {
+            format!("{:2}. {:<12} {:>8}  {}{}", i + 1, e.name, high_scores::format_value(goal.name(), e.value), e.date, if e.stats.assisted { "  assisted" } else { "" })
+        }).collect();
+        if self.lines.is_empty() {
+            self.lines.push("No runs yet".to_string());
+        }
+    }
+}
+
+impl Default for Scores {
+    fn default() -> Scores {
+        Scores::new()
+    }
+}
+
+pub enum Scene {
+    Title(Menu),
+    ModeSelect(ModeSelect),
+    Settings(Menu),
+    //Boxed, it holds whole games and would size every other scene to match
+    Playing(Box<Playing>),
+    Paused(Menu),
+    Results(Results),
+    Scores(Scores),
+}
+
+impl Scene {
+
+    pub fn title() -> Scene {
+        Scene::Title(Menu::new("Tetris", &["Play", "High scores", "Settings", "Quit"]))
+    }
+
+    //The skin and volumes are filled in by the stack
+    pub fn settings() -> Scene {
+        Scene::Settings(Menu::new("Settings", &["Skin", "Volume", "Effects", "Music", "Controls", "Back"]))
+    }
+
+    pub fn paused(can_restart: bool) -> Scene {
+        let items: &[&str] = if can_restart { &["Resume", "Restart", "Quit to title"] } else { &["Resume", "Quit to title"] };
+        Scene::Paused(Menu::new("Paused", items))
+    }
+
+    pub fn playing(mode: play_mode::PlayMode, choice: Option<ModeChoice>) -> Scene {
+        Scene::Playing(Box::new(Playing { mode, choice, countdown: 0 }))
+    }
+
+    fn start(choice: ModeChoice) -> Transition {
+        match choice.start() {
+            Ok(mode) => Transition::Replace(Scene::playing(mode, Some(choice))),
+            Err(e) => {
+                println!("Couldn't start game: {:?}", e);
+                Transition::Stay
+            },
+        }
+    }
+
+    pub fn input(&mut self, event: &WindowEvent) {
+        match self {
+            Scene::Title(m) | Scene::Settings(m) | Scene::Paused(m) => m.input(event),
+            Scene::ModeSelect(s) => s.menu.input(event),
+            //Nothing gets to the game during the countdown but letting go of keys
+            Scene::Playing(p) if p.countdown > 0 => {
+                if let WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Released, .. }, .. } = event {
+                    p.mode.input(event);
+                }
+            },
+            Scene::Playing(p) => p.mode.input(event),
+            Scene::Results(r) => r.menu.input(event),
+            Scene::Scores(s) => s.menu.input(event),
+        }
+    }
+
+    pub fn process_inputs(&mut self, w_di: [u32; 2]) -> Transition {
+        match self {
+            Scene::Title(m) => match m.process_inputs() {
+                Some(VirtualKeyCode::Return) => match m.selected {
+                    0 => Transition::Push(Scene::ModeSelect(ModeSelect::new())),
+                    1 => Transition::Push(Scene::Scores(Scores::new())),
+                    2 => Transition::Push(Scene::settings()),
+                    _ => Transition::Quit,
+                },
+                Some(VirtualKeyCode::Escape) => Transition::Quit,
+                _ => Transition::Stay,
+            },
+            Scene::ModeSelect(s) => match s.menu.process_inputs() {
+                Some(VirtualKeyCode::Left) | Some(VirtualKeyCode::Right) if s.menu.selected == 0 => {
+                    s.goal = s.goal.next();
+                    s.label();
+                    Transition::Stay
+                },
+                Some(VirtualKeyCode::Left) | Some(VirtualKeyCode::Right) if s.menu.selected == 2 => {
+                    s.difficulty = s.difficulty.next();
+                    s.label();
+                    Transition::Stay
+                },
+                Some(VirtualKeyCode::Return) => match s.menu.selected {
+                    0 => Scene::start(ModeChoice::Solo(s.goal)),
+                    1 => Scene::start(ModeChoice::Versus),
+                    2 => Scene::start(ModeChoice::Cpu(s.difficulty)),
+                    3 => Scene::start(ModeChoice::Browse),
+                    _ => Transition::Pop,
+                },
+                Some(VirtualKeyCode::Escape) => Transition::Pop,
+                _ => Transition::Stay,
+            },
+            Scene::Settings(m) => match m.process_inputs() {
+                Some(VirtualKeyCode::Return) | Some(VirtualKeyCode::Left) | Some(VirtualKeyCode::Right) if m.selected == 0 => Transition::CycleSkin,
+                Some(VirtualKeyCode::Left) if m.selected == 1 => Transition::AdjustVolume(-VOLUME_STEP),
+                Some(VirtualKeyCode::Right) if m.selected == 1 => Transition::AdjustVolume(VOLUME_STEP),
+                Some(VirtualKeyCode::Left) if m.selected == 2 => Transition::AdjustEffects(-VOLUME_STEP),
+                Some(VirtualKeyCode::Right) if m.selected == 2 => Transition::AdjustEffects(VOLUME_STEP),
+                Some(VirtualKeyCode::Left) if m.selected == 3 => Transition::AdjustMusic(-VOLUME_STEP),
+                Some(VirtualKeyCode::Right) if m.selected == 3 => Transition::AdjustMusic(VOLUME_STEP),
+                Some(VirtualKeyCode::Return) => match m.selected {
+                    1..=3 => Transition::Stay,
+                    4 => Transition::OpenControls,
+                    _ => Transition::Pop,
+                },
+                Some(VirtualKeyCode::Escape) => Transition::Pop,
+                _ => Transition::Stay,
+            },
+            Scene::Playing(p) => {
+                if p.countdown == 0 {
+                    p.mode.process_inputs(w_di);
+                }
+                Transition::Stay
+            },
+            Scene::Paused(m) => match m.process_inputs() {
+                Some(VirtualKeyCode::Return) => match m.items[m.selected].as_str() {
+                    "Resume" => Transition::Resume,
+                    "Restart" => Transition::Restart,
+                    _ => Transition::Reset,
+                },
+                Some(VirtualKeyCode::Escape) => Transition::Resume,
+                _ => Transition::Stay,
+            },
+            Scene::Results(r) => match r.menu.process_inputs() {
+                Some(VirtualKeyCode::Return) => match (r.menu.selected, r.choice) {
+                    (0, Some(choice)) => Scene::start(choice),
+                    _ => Transition::Reset,
+                },
+                Some(VirtualKeyCode::Escape) => Transition::Reset,
+                _ => Transition::Stay,
+            },
+            Scene::Scores(s) => match s.menu.process_inputs() {
+                Some(VirtualKeyCode::Left) | Some(VirtualKeyCode::Right) => {
+                    let goal = s.goal.next();
+                    s.show(goal);
+                    Transition::Stay
+                },
+                Some(VirtualKeyCode::Return) | Some(VirtualKeyCode::Escape) => Transition::Pop,
+                _ => Transition::Stay,
+            },
+        }
+    }
+
+    //Events from the game go in events
+    pub fn update(&mut self, events: &mut Vec<GameEvent>) -> Transition {
+        if let Scene::Playing(p) = self {
+            if p.countdown > 0 {
+                p.countdown -= 1;
+                return Transition::Stay;
+            }
+            p.mode.update();
+            let taken = p.mode.take_events();
+            let over = taken.iter().any(|e| *e == GameEvent::TopOut || *e == GameEvent::Finished);
+            events.extend(taken);
+            if let play_mode::PlayMode::Single(g) = &p.mode {
+                if over {
+                    return Transition::Replace(Scene::Results(Results::solo(g, p.choice)));
+                }
+            }
+        }
+        Transition::Stay
+    }
+
+    pub fn render(&mut self, r: &mut r_backend::Renderer, camera: &mut camera::Camera) {
+        match self {
+            Scene::Title(m) | Scene::Settings(m) | Scene::Paused(m) => m.render(r),
+            Scene::ModeSelect(s) => s.menu.render(r),
+            Scene::Playing(p) => {
+                p.mode.render(r, camera);
+                if p.countdown > 0 {
+                    let step = (p.countdown * COUNTDOWN_STEPS).div_ceil(COUNTDOWN_TICKS);
+                    r.render_text(&step.to_string(), [CENTRE_X, 5.5], TITLE_SIZE * 2.0, text::Align::Centre, [1.0, 1.0, 1.0, 1.0], DEPTH, text::FONT.to_string());
+                }
+            },
+            Scene::Results(res) => {
+                r.render_text(&res.menu.title, [CENTRE_X, TITLE_Y], TITLE_SIZE, text::Align::Centre, [1.0, 1.0, 1.0, 1.0], DEPTH, text::FONT.to_string());
+                r.render_text(&res.lines.join("\n"), [CENTRE_X, TITLE_Y - 1.4], LINE_SIZE * 1.4, text::Align::Centre, [0.8, 0.8, 0.8, 1.0], DEPTH, text::FONT.to_string());
+                res.menu.render_items(r, TITLE_Y - 1.6 - res.lines.len() as f32 * LINE_SIZE * 1.4 - 0.6);
+            },
+            Scene::Scores(s) => {
+                r.render_text(&s.menu.title, [CENTRE_X, TITLE_Y], TITLE_SIZE, text::Align::Centre, [1.0, 1.0, 1.0, 1.0], DEPTH, text::FONT.to_string());
+                r.render_text(&s.lines.join("\n"), [CENTRE_X, TITLE_Y - 1.4], LINE_SIZE * 1.4, text::Align::Centre, [0.8, 0.8, 0.8, 1.0], DEPTH, text::FONT.to_string());
+                s.menu.render_items(r, TITLE_Y - 1.6 - s.lines.len() as f32 * LINE_SIZE * 1.4 - 0.6);
+            },
+        }
+    }
+
+    fn runs_underneath(&self) -> bool {
+        match self {
+            Scene::Playing(p) => p.runs_underneath(),
+            _ => false,
+        }
+    }
+}
+
+pub struct SceneStack {
+    pub scenes: Vec<Scene>,
+    pub settings: settings::Settings,
+    //From the game being played, main hands them on every tick
+    pub events: Vec<GameEvent>,
+    //For the controls menu when no game is running, or no second player is in it
+    pub bindings: [key_bindings::KeyBindings; 2],
+}
+
+impl SceneStack {
+
+    pub fn new(settings: settings::Settings) -> SceneStack {
+        let scenes = vec![Scene::title()];
+        let events = Vec::new();
+        let bindings = [key_bindings::KeyBindings::load_player(0), key_bindings::KeyBindings::load_player(1)];
+        SceneStack { scenes, settings, events, bindings }
+    }
+
+    pub fn top(&mut self) -> &mut Scene {
+        self.scenes.last_mut().expect("scene stack is never empty")
+    }
+
+    //Stack transitions happen here, the rest go back to the caller
+    fn apply(&mut self, transition: Transition) -> Transition {
+        match transition {
+            Transition::Push(scene) => self.push(scene),
+            Transition::Pop => {
+                if self.scenes.len() > 1 {
+                    self.scenes.pop();
+                }
+            },
+            Transition::Replace(scene) => {
+                self.scenes.pop();
+                self.push(scene);
+            },
+            Transition::Reset => self.scenes.truncate(1),
+            Transition::AdjustVolume(step) => {
+                self.settings.master_volume = (self.settings.master_volume + step).clamp(0.0, 1.0);
+                self.label_settings();
+                return Transition::SettingsChanged;
+            },
+            Transition::AdjustEffects(step) => {
+                self.settings.effects_volume = (self.settings.effects_volume + step).clamp(0.0, 1.0);
+                self.label_settings();
+                return Transition::SettingsChanged;
+            },
+            Transition::AdjustMusic(step) => {
+                self.settings.music_volume = (self.settings.music_volume + step).clamp(0.0, 1.0);
+                self.label_settings();
+                return Transition::SettingsChanged;
+            },
+            Transition::Resume | Transition::Restart => {
+                if let Some(Scene::Paused(_)) = self.scenes.last() {
+                    self.scenes.pop();
+                }
+                if let Scene::Playing(p) = self.top() {
+                    match transition {
+                        Transition::Restart => p.restart(),
+                        _ if !p.runs_underneath() => p.countdown = COUNTDOWN_TICKS,
+                        _ => (),
+                    }
+                }
+            },
+            other => return other,
+        }
+        Transition::Stay
+    }
+
+    pub fn push(&mut self, scene: Scene) {
+        self.scenes.push(scene);
+        self.label_settings();
+    }
+
+    pub fn set_skin(&mut self, skin: &str) {
+        self.settings.skin = skin.to_string();
+        self.label_settings();
+    }
+
+    //Keeps the settings menu showing what's in use
+    fn label_settings(&mut self) {
+        let settings = &self.settings;
+        for scene in self.scenes.iter_mut() {
+            if let Scene::Settings(m) = scene {
+                m.items[0] = format!("Skin: {}", settings.skin);
+                m.items[1] = format!("Volume: {}%", (settings.master_volume * 100.0).round());
+                m.items[2] = format!("Effects: {}%", (settings.effects_volume * 100.0).round());
+                m.items[3] = format!("Music: {}%", (settings.music_volume * 100.0).round());
+            }
+        }
+    }
+
+    //The window lost focus, so no key is held any more as far as we'll ever hear
+    pub fn focus_lost(&mut self) {
+        self.release_keys();
+        self.pause();
+    }
+
+    //Forgets every held key in any game on the stack, for when its releases won't reach it
+    pub fn release_keys(&mut self) {
+        for scene in self.scenes.iter_mut() {
+            if let Scene::Playing(p) = scene {
+                p.mode.release_keys();
+            }
+        }
+    }
+
+    //Puts the pause menu over a game, does nothing anywhere else
+    pub fn pause(&mut self) {
+        if let Scene::Playing(p) = self.top() {
+            let can_restart = p.can_restart();
+            self.push(Scene::paused(can_restart));
+        }
+    }
+
+    pub fn input(&mut self, event: &WindowEvent) {
+        //Escape or a pause key pauses a game, every other scene treats Escape as back
+        if let WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. }, .. } = event {
+            if let Scene::Playing(p) = self.top() {
+                if *key == VirtualKeyCode::Escape || p.mode.is_pause_key(*key) {
+                    self.pause();
+                    return;
+                }
+            }
+        }
+        //Releases go to every scene so nothing underneath is left thinking a key is held
+        if let WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Released, .. }, .. } = event {
+            for scene in self.scenes.iter_mut() {
+                scene.input(event);
+            }
+            return;
+        }
+        self.top().input(event);
+    }
+
+    pub fn process_inputs(&mut self, w_di: [u32; 2]) -> Transition {
+        let transition = self.top().process_inputs(w_di);
+        self.apply(transition)
+    }
+
+    pub fn update(&mut self) -> Transition {
+        let n = self.scenes.len();
+        if n >= 2 && matches!(self.scenes[n - 1], Scene::Paused(_)) && self.scenes[n - 2].runs_underneath() {
+            let transition = self.scenes[n - 2].update(&mut self.events);
+            //Whatever the game turned into goes under the pause menu
+            if let Transition::Replace(scene) = transition {
+                self.scenes[n - 2] = scene;
+            }
+            return Transition::Stay;
+        }
+        let events = &mut self.events;
+        let transition = self.scenes.last_mut().expect("scene stack is never empty").update(events);
+        self.apply(transition)
+    }
+
+    pub fn render(&mut self, r: &mut r_backend::Renderer, camera: &mut camera::Camera) {
+        self.top().render(r, camera);
+    }
+
+    pub fn take_events(&mut self) -> Vec<GameEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    //What should be playing, following the first player at this keyboard. Nothing outside a game.
+    pub fn music(&mut self) -> Option<music::Cue> {
+        let n = self.scenes.len();
+        let paused = n >= 2 && matches!(self.scenes[n - 1], Scene::Paused(_));
+        let p = match &mut self.scenes[if paused { n - 2 } else { n - 1 }] {
+            Scene::Playing(p) => p,
+            _ => return None,
+        };
+        let track = match p.mode {
+            play_mode::PlayMode::Single(_) => music::ENDLESS_TRACK,
+            _ => music::VERSUS_TRACK,
+        };
+        let waiting = p.countdown > 0;
+        let player = p.mode.human_player()?;
+        Some(music::Cue { track, level: player.level(), stack_height: player.board().stack_height(), paused: paused || waiting })
+    }
+
+    //The mode on top, if a game is what's showing
+    pub fn playing(&mut self) -> Option<&mut play_mode::PlayMode> {
+        match self.top() {
+            Scene::Playing(p) => Some(&mut p.mode),
+            _ => None,
+        }
+    }
+
+    //Both players' bindings for the controls menu, the running game's where it has them
+    pub fn bindings_mut(&mut self) -> [&mut key_bindings::KeyBindings; 2] {
+        let [one, two] = &mut self.bindings;
+        let mode = self.scenes.iter_mut().rev().find_map(|s| match s {
+            Scene::Playing(p) => Some(&mut p.mode),
+            _ => None,
+        });
+        match mode {
+            Some(m) if m.has_second_player() => m.both_bindings_mut().expect("second player is local"),
+            Some(m) => [m.bindings_mut(), two],
+            None => [one, two],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[allow(deprecated)]
+    fn key(key: VirtualKeyCode, state: ElementState) -> WindowEvent<'static> {
+        WindowEvent::KeyboardInput {
+            device_id: unsafe { DeviceId::dummy() },
+            input: KeyboardInput { scancode: 0, state, virtual_keycode: Some(key), modifiers: ModifiersState::empty() },
+            is_synthetic: false,
+        }
+    }
+
+    //A press and its release, then the stack's response to it
+    fn tap(stack: &mut SceneStack, k: VirtualKeyCode) -> Transition {
+        stack.input(&key(k, ElementState::Pressed));
+        stack.input(&key(k, ElementState::Released));
+        stack.process_inputs([800, 600])
+    }
+
+    fn stack() -> SceneStack {
+        SceneStack::new(settings::Settings::default_settings())
+    }
+
+    fn with_game() -> SceneStack {
+        let mut stack = stack();
+        let g = game::Game::new_player(key_bindings::KeyBindings::default_bindings(), [0.0, 0.0], 5);
+        stack.push(Scene::playing(play_mode::PlayMode::Single(g), None));
+        stack
+    }
+
+    fn ticks(stack: &mut SceneStack) -> u32 {
+        match stack.playing() {
+            Some(play_mode::PlayMode::Single(g)) => g.ticks(),
+            _ => panic!("no game on top"),
+        }
+    }
+
+    fn top_menu(stack: &mut SceneStack) -> &mut Menu {
+        match stack.top() {
+            Scene::Title(m) | Scene::Settings(m) | Scene::Paused(m) => m,
+            Scene::ModeSelect(s) => &mut s.menu,
+            Scene::Results(r) => &mut r.menu,
+            Scene::Scores(s) => &mut s.menu,
+            Scene::Playing(_) => panic!("a game is on top"),
+        }
+    }
+
+    #[test]
+    fn menus_wrap_around() {
+        let mut stack = stack();
+        tap(&mut stack, VirtualKeyCode::Up);
+        assert_eq!(top_menu(&mut stack).selected, 3);
+        tap(&mut stack, VirtualKeyCode::Down);
+        assert_eq!(top_menu(&mut stack).selected, 0);
+    }
+
+    #[test]
+    fn menus_push_and_back_out() {
+        let mut stack = stack();
+        tap(&mut stack, VirtualKeyCode::Return);
+        assert!(matches!(stack.top(), Scene::ModeSelect(_)));
+        assert_eq!(top_menu(&mut stack).items[0], "Solo: Endless");
+        //Left and Right on the first item change the goal
+        tap(&mut stack, VirtualKeyCode::Right);
+        assert_eq!(top_menu(&mut stack).items[0], "Solo: Marathon");
+        tap(&mut stack, VirtualKeyCode::Escape);
+        assert!(matches!(stack.top(), Scene::Title(_)));
+        assert!(matches!(tap(&mut stack, VirtualKeyCode::Escape), Transition::Quit));
+        assert_eq!(stack.scenes.len(), 1);
+    }
+
+    #[test]
+    fn settings_show_and_clamp_volumes() {
+        let mut stack = stack();
+        tap(&mut stack, VirtualKeyCode::Down);
+        tap(&mut stack, VirtualKeyCode::Down);
+        tap(&mut stack, VirtualKeyCode::Return);
+        assert_eq!(top_menu(&mut stack).items[0], "Skin: default");
+        assert_eq!(top_menu(&mut stack).items[1], "Volume: 80%");
+        tap(&mut stack, VirtualKeyCode::Down);
+        assert!(matches!(tap(&mut stack, VirtualKeyCode::Right), Transition::SettingsChanged));
+        tap(&mut stack, VirtualKeyCode::Right);
+        tap(&mut stack, VirtualKeyCode::Right);
+        assert_eq!(stack.settings.master_volume, 1.0);
+        assert_eq!(top_menu(&mut stack).items[1], "Volume: 100%");
+        assert!(matches!(tap(&mut stack, VirtualKeyCode::Return), Transition::Stay));
+        tap(&mut stack, VirtualKeyCode::Escape);
+        assert!(matches!(stack.top(), Scene::Title(_)));
+    }
+
+    #[test]
+    fn scores_switch_goal() {
+        let mut stack = stack();
+        tap(&mut stack, VirtualKeyCode::Down);
+        tap(&mut stack, VirtualKeyCode::Return);
+        assert_eq!(top_menu(&mut stack).title, "< Endless >");
+        tap(&mut stack, VirtualKeyCode::Right);
+        assert_eq!(top_menu(&mut stack).title, "< Marathon >");
+        match stack.top() {
+            Scene::Scores(s) => assert!(!s.lines.is_empty()),
+            _ => panic!("not the scores"),
+        }
+        tap(&mut stack, VirtualKeyCode::Return);
+        assert!(matches!(stack.top(), Scene::Title(_)));
+    }
+
+    #[test]
+    fn escape_pauses_a_game_and_stops_it() {
+        let mut stack = with_game();
+        stack.update();
+        assert_eq!(ticks(&mut stack), 1);
+        stack.input(&key(VirtualKeyCode::Escape, ElementState::Pressed));
+        assert!(matches!(stack.top(), Scene::Paused(_)));
+        assert_eq!(top_menu(&mut stack).items, vec!["Resume", "Restart", "Quit to title"]);
+        for _ in 0..10 {
+            stack.update();
+        }
+        stack.scenes.pop();
+        assert_eq!(ticks(&mut stack), 1);
+    }
+
+    #[test]
+    fn resuming_counts_down_first() {
+        let mut stack = with_game();
+        stack.pause();
+        assert!(matches!(tap(&mut stack, VirtualKeyCode::Return), Transition::Stay));
+        assert!(matches!(stack.top(), Scene::Playing(p) if p.countdown == COUNTDOWN_TICKS));
+        for _ in 0..COUNTDOWN_TICKS {
+            stack.update();
+        }
+        assert_eq!(ticks(&mut stack), 0);
+        stack.update();
+        assert_eq!(ticks(&mut stack), 1);
+    }
+
+    #[test]
+    fn losing_focus_pauses_only_games() {
+        let mut stack = stack();
+        stack.focus_lost();
+        assert_eq!(stack.scenes.len(), 1);
+        let mut stack = with_game();
+        stack.focus_lost();
+        assert!(matches!(stack.top(), Scene::Paused(_)));
+        //Pausing again doesn't stack another menu
+        stack.focus_lost();
+        assert_eq!(stack.scenes.len(), 3);
+        tap(&mut stack, VirtualKeyCode::Down);
+        tap(&mut stack, VirtualKeyCode::Down);
+        tap(&mut stack, VirtualKeyCode::Return);
+        assert_eq!(stack.scenes.len(), 1);
+    }
+}
@@ -0,0 +1,54 @@
+use std::fs;
+
+pub const SETTINGS_PATH: &str = "./settings.cfg";
+
+//Player preferences kept between runs, one name=value a line
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub skin: String,
+    //0 to 1, effects and music are scaled by the master volume as well
+    pub master_volume: f32,
+    pub effects_volume: f32,
+    pub music_volume: f32,
+}
+
+impl Settings {
+
+    pub fn default_settings() -> Settings {
+        Settings { skin: "default".to_string(), master_volume: 0.8, effects_volume: 1.0, music_volume: 0.6 }
+    }
+
+    pub fn load(path: &str) -> Settings {
+
+        let mut settings = Settings::default_settings();
+
+        let contents = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(_) => return settings,
+        };
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(2, '=');
+            let name = parts.next().unwrap_or("").trim();
+            let value = parts.next().map(|v| v.trim().to_string());
+            let volume = value.as_ref().and_then(|v| v.parse::<f32>().ok()).map(|v| v.clamp(0.0, 1.0));
+            match (name, value, volume) {
+                ("skin", Some(v), _) => settings.skin = v,
+                ("master_volume", _, Some(v)) => settings.master_volume = v,
+                ("effects_volume", _, Some(v)) => settings.effects_volume = v,
+                ("music_volume", _, Some(v)) => settings.music_volume = v,
+                _ => println!("Bad line in settings file: {}", line),
+            }
+        }
+
+        settings
+    }
+
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        fs::write(path, format!("skin={}\nmaster_volume={}\neffects_volume={}\nmusic_volume={}\n", self.skin, self.master_volume, self.effects_volume, self.music_volume))
+    }
+}
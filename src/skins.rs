@@ -0,0 +1,173 @@
+use crate::r_backend;
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::Path;
+
+/*
+Every folder in res/pieces is a skin. A skin has to hold exactly the tiles 1.png to 9.png, all the
+same size: the seven piece colours in kind order, then garbage grey and the line clear white.
+Whichever skin is picked is loaded under SPRITE_ARRAY so the render code never needs its name.
+*/
+
+pub const SKINS_DIR: &str = "./res/pieces";
+pub const SPRITE_ARRAY: &str = "pieces";
+pub const DEFAULT_SKIN: &str = "default";
+const TILE_COUNT: usize = 9;
+
+//Skin folder names, sorted
+pub fn discover() -> Vec<String> {
+    let mut names: Vec<String> = match fs::read_dir(SKINS_DIR) {
+        Ok(entries) => entries.filter_map(|e| e.ok())
+            .filter(|e| e.path().is_dir())
+            .filter_map(|e| e.file_name().to_str().map(|n| n.to_string()))
+            .collect(),
+        Err(e) => {
+            println!("Couldn't look for skins in {}: {:?}", SKINS_DIR, e);
+            Vec::new()
+        }
+    };
+    names.sort();
+    names
+}
+
+//Checks the tiles are all there and match before anything is sent to the GPU
+pub fn validate(name: &str) -> Result<()> {
+    validate_dir(&Path::new(SKINS_DIR).join(name), name)
+}
+
+fn validate_dir(dir: &Path, name: &str) -> Result<()> {
+
+    let mut size = None;
+    for i in 1..=TILE_COUNT {
+        let file = dir.join(format!("{}.png", i));
+        let dimensions = image::image_dimensions(&file).with_context(|| format!("Skin {} is missing or can't read {}", name, file.display()))?;
+        match size {
+            None => size = Some(dimensions),
+            Some(s) if s != dimensions => bail!("Skin {} tile {}.png is {}x{} but 1.png is {}x{}", name, i, dimensions.0, dimensions.1, s.0, s.1),
+            _ => (),
+        }
+    }
+
+    //Tiles are loaded in file name order, anything extra would shift the colours
+    let pngs = fs::read_dir(dir)?.filter_map(|e| e.ok())
+        .filter(|e| e.path().extension().is_some_and(|x| x == "png"))
+        .count();
+    if pngs != TILE_COUNT {
+        bail!("Skin {} has {} tile images, it should only have 1.png to {}.png", name, pngs, TILE_COUNT);
+    }
+    Ok(())
+}
+
+pub struct Skins {
+    pub names: Vec<String>,
+    pub current: String,
+}
+
+impl Skins {
+
+    pub fn new() -> Skins {
+        Skins { names: discover(), current: String::new() }
+    }
+
+    pub fn load(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, r: &mut r_backend::Renderer, name: &str) -> Result<()> {
+        validate(name)?;
+        let pattern = format!("{}/{}/*.png", SKINS_DIR, name);
+        r.replace_sprite_array(device, queue, &pattern, SPRITE_ARRAY.to_string());
+        self.current = name.to_string();
+        Ok(())
+    }
+
+    //The saved skin, or the default one if that can't be used
+    pub fn load_or_default(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, r: &mut r_backend::Renderer, name: &str) {
+        if let Err(e) = self.load(device, queue, r, name) {
+            println!("{:#}, using the {} skin", e, DEFAULT_SKIN);
+            if let Err(e) = self.load(device, queue, r, DEFAULT_SKIN) {
+                println!("{:#}", e);
+            }
+        }
+    }
+
+    //Moves on to the next skin that loads, the list is read again so new folders show up
+    pub fn cycle(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, r: &mut r_backend::Renderer) -> Option<String> {
+
+        self.names = discover();
+        let start = self.names.iter().position(|n| *n == self.current).map_or(0, |i| i + 1);
+        for i in 0..self.names.len() {
+            let name = self.names[(start + i) % self.names.len()].clone();
+            if name == self.current {
+                break;
+            }
+            match self.load(device, queue, r, &name) {
+                Ok(_) => {
+                    println!("Skin {}", name);
+                    return Some(name);
+                },
+                Err(e) => println!("{:#}", e),
+            }
+        }
+        None
+    }
+}
+
+impl Default for Skins {
+    fn default() -> Skins {
+        Skins::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //A skin folder of its own for each test, with tiles of the given sizes named 1.png up
+    fn skin(name: &str, sizes: &[u32]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("tetris-skin-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (i, size) in sizes.iter().enumerate() {
+            image::RgbaImage::new(*size, *size).save(dir.join(format!("{}.png", i + 1))).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn the_bundled_skin_is_found_and_valid() {
+        assert!(discover().contains(&DEFAULT_SKIN.to_string()));
+        validate(DEFAULT_SKIN).unwrap();
+        assert!(validate("no such skin").is_err());
+    }
+
+    #[test]
+    fn a_full_set_of_tiles_is_valid() {
+        let dir = skin("full", &[8; TILE_COUNT]);
+        validate_dir(&dir, "full").unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_tiles_are_refused() {
+        let dir = skin("missing", &[8; TILE_COUNT - 1]);
+        let e = validate_dir(&dir, "missing").unwrap_err();
+        assert!(format!("{:#}", e).contains("9.png"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tiles_of_different_sizes_are_refused() {
+        let mut sizes = [8; TILE_COUNT];
+        sizes[4] = 16;
+        let dir = skin("sizes", &sizes);
+        let e = validate_dir(&dir, "sizes").unwrap_err();
+        assert!(e.to_string().contains("tile 5.png is 16x16"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn extra_tiles_are_refused() {
+        let dir = skin("extra", &[8; TILE_COUNT + 1]);
+        let e = validate_dir(&dir, "extra").unwrap_err();
+        assert!(e.to_string().contains("has 10 tile images"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
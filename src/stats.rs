@@ -0,0 +1,167 @@
+use crate::game_options;
+use crate::events::GameEvent;
+
+use serde::{Deserialize, Serialize};
+
+//Counts for one run, rates are worked out from them whenever they're asked for
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Stats {
+    pub ticks: u32,
+    pub pieces: u32,
+    //Every action applied, including hard drops and holds
+    pub keys: u32,
+    pub attack: u32,
+    pub lines: u32,
+    //Lines and spins score the usual way at the level they were made on, drops don't score
+    #[serde(default)]
+    pub score: u32,
+    //Hints or the perfect clear trainer were on at some point
+    pub assisted: bool,
+}
+
+//Singles to tetrises, then the same for spins
+const LINE_POINTS: [u32; 5] = [0, 100, 300, 500, 800];
+const SPIN_POINTS: [u32; 5] = [400, 800, 1200, 1600, 2000];
+
+fn line_points(lines: usize, spin: bool) -> u32 {
+    let table = if spin { &SPIN_POINTS } else { &LINE_POINTS };
+    table[lines.min(4)]
+}
+
+impl Stats {
+
+    pub fn new() -> Stats {
+        Stats::default()
+    }
+
+    //Pieces, lines and attack are counted from the game's events, ticks and keys by the game itself
+    pub fn record(&mut self, event: &GameEvent) {
+        match event {
+            GameEvent::Locked { .. } => self.pieces += 1,
+            GameEvent::LinesCleared { rows, spin, combo } => {
+                let level = self.lines / 10 + 1;
+                self.score += (line_points(rows.len(), *spin) + 50 * combo) * level;
+                self.lines += rows.len() as u32;
+            },
+            GameEvent::GarbageSent { lines } => self.attack += lines,
+            _ => (),
+        }
+    }
+
+    pub fn seconds(&self) -> f32 {
+        self.ticks as f32 / game_options::TICKS_PER_SECOND
+    }
+
+    fn per_second(&self, count: u32) -> f32 {
+        let seconds = self.seconds();
+        if seconds > 0.0 { count as f32 / seconds } else { 0.0 }
+    }
+
+    pub fn pps(&self) -> f32 {
+        self.per_second(self.pieces)
+    }
+
+    pub fn apm(&self) -> f32 {
+        self.per_second(self.attack) * 60.0
+    }
+
+    pub fn lines_per_minute(&self) -> f32 {
+        self.per_second(self.lines) * 60.0
+    }
+
+    pub fn kpp(&self) -> f32 {
+        if self.pieces > 0 { self.keys as f32 / self.pieces as f32 } else { 0.0 }
+    }
+
+    //Attack per piece
+    pub fn app(&self) -> f32 {
+        if self.pieces > 0 { self.attack as f32 / self.pieces as f32 } else { 0.0 }
+    }
+
+    //Short enough for the window title
+    pub fn hud(&self) -> String {
+        format!("PPS {:.2}  APM {:.1}  KPP {:.2}  LPM {:.1}{}", self.pps(), self.apm(), self.kpp(), self.lines_per_minute(), if self.assisted { "  (assisted)" } else { "" })
+    }
+
+    pub fn summary(&self) -> String {
+        let seconds = self.seconds();
+        format!(
+            "{}:{:04.1}  {} pieces  {} lines  {} attack  PPS {:.2}  APM {:.1}  APP {:.2}  KPP {:.2}  LPM {:.1}{}",
+            (seconds / 60.0) as u32, seconds % 60.0, self.pieces, self.lines, self.attack,
+            self.pps(), self.apm(), self.app(), self.kpp(), self.lines_per_minute(),
+            if self.assisted { "  assisted" } else { "" },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Two seconds of play
+    const TICKS: u32 = 125;
+
+    #[test]
+    fn rates_from_counts() {
+        let stats = Stats { ticks: TICKS, pieces: 5, keys: 20, attack: 4, lines: 6, ..Stats::default() };
+        assert_eq!(stats.seconds(), 2.0);
+        assert_eq!(stats.pps(), 2.5);
+        assert_eq!(stats.apm(), 120.0);
+        assert_eq!(stats.lines_per_minute(), 180.0);
+        assert_eq!(stats.kpp(), 4.0);
+        assert_eq!(stats.app(), 0.8);
+    }
+
+    #[test]
+    fn nothing_to_divide_by() {
+        let stats = Stats::new();
+        assert_eq!((stats.pps(), stats.apm(), stats.lines_per_minute(), stats.kpp(), stats.app()), (0.0, 0.0, 0.0, 0.0, 0.0));
+        //Keys before the first piece locks, or pieces on the first tick
+        let stats = Stats { keys: 3, ..Stats::default() };
+        assert_eq!(stats.kpp(), 0.0);
+        let stats = Stats { pieces: 1, attack: 2, lines: 2, ..Stats::default() };
+        assert_eq!((stats.pps(), stats.apm(), stats.lines_per_minute()), (0.0, 0.0, 0.0));
+        assert!(stats.summary().starts_with("0:00.0  1 pieces"));
+    }
+
+    #[test]
+    fn counts_from_events() {
+        let locked = GameEvent::Locked { kind: 0, cells: Vec::new(), hard_drop: true, spin: false };
+        let events = vec![
+            GameEvent::PieceSpawned { kind: 0 },
+            GameEvent::Moved { dx: 1, dy: 0 },
+            locked.clone(),
+            locked.clone(),
+            GameEvent::LinesCleared { rows: vec![1, 2], spin: false, combo: 0 },
+            GameEvent::GarbageSent { lines: 1 },
+            locked.clone(),
+            locked,
+            GameEvent::LinesCleared { rows: vec![1, 2, 3, 4], spin: false, combo: 1 },
+            GameEvent::GarbageSent { lines: 4 },
+            GameEvent::TopOut,
+        ];
+        let mut stats = Stats::new();
+        for event in &events {
+            stats.record(event);
+        }
+        stats.ticks = TICKS;
+        stats.keys = 10;
+        assert_eq!((stats.pieces, stats.lines, stats.attack), (4, 6, 5));
+        assert_eq!(stats.pps(), 2.0);
+        assert_eq!(stats.apm(), 150.0);
+        assert_eq!(stats.kpp(), 2.5);
+        assert_eq!(stats.lines_per_minute(), 180.0);
+    }
+
+    #[test]
+    fn clears_score_at_their_level() {
+        let mut stats = Stats::new();
+        stats.record(&GameEvent::LinesCleared { rows: vec![1, 2, 3, 4], spin: false, combo: 0 });
+        assert_eq!(stats.score, 800);
+        stats.record(&GameEvent::LinesCleared { rows: vec![1, 2], spin: true, combo: 1 });
+        assert_eq!(stats.score, 800 + 1200 + 50);
+        stats.lines = 10;
+        stats.record(&GameEvent::LinesCleared { rows: vec![1], spin: false, combo: 0 });
+        assert_eq!(stats.score, 800 + 1250 + 200);
+    }
+}
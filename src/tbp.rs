@@ -0,0 +1,488 @@
+use crate::bot;
+use crate::game;
+use crate::movegen;
+use crate::tetromino;
+use crate::key_bindings;
+use crate::piece_set;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::collections::VecDeque;
+
+/*
+Tetris Bot Protocol, one JSON message per line over the bot's stdin and stdout.
+https://github.com/tetris-bot-protocol/tbp-spec
+Coordinates are the spec's, x from the left and y from the bottom, so both are flipped
+around from the board's own layout when converting.
+*/
+
+//Piece letters in the order of tetromino kinds
+const PIECE_NAMES: [&str; 7] = ["I", "J", "L", "O", "S", "T", "Z"];
+const ORIENTATIONS: [&str; 4] = ["north", "east", "south", "west"];
+//Cells of each piece facing north, relative to its centre
+const NORTH_CELLS: [[(i32, i32); 4]; 7] = [
+    [(-1, 0), (0, 0), (1, 0), (2, 0)],
+    [(-1, 0), (0, 0), (1, 0), (-1, 1)],
+    [(-1, 0), (0, 0), (1, 0), (1, 1)],
+    [(0, 0), (1, 0), (0, 1), (1, 1)],
+    [(-1, 0), (0, 0), (0, 1), (1, 1)],
+    [(-1, 0), (0, 0), (1, 0), (0, 1)],
+    [(-1, 1), (0, 1), (0, 0), (1, 0)],
+];
+const TBP_BOARD_HEIGHT: usize = 40;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Location {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub orientation: String,
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TbpMove {
+    pub location: Location,
+    pub spin: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FrontendMessage {
+    Rules,
+    Start {
+        hold: Option<String>,
+        queue: Vec<String>,
+        combo: u32,
+        back_to_back: bool,
+        board: Vec<Vec<Option<String>>>,
+    },
+    NewPiece { piece: String },
+    Suggest,
+    Play {
+        #[serde(rename = "move")]
+        mv: TbpMove,
+    },
+    Stop,
+    Quit,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BotMessage {
+    Info {
+        name: String,
+        version: String,
+        author: String,
+        #[serde(default)]
+        features: Vec<String>,
+    },
+    Ready,
+    Error { reason: String },
+    Suggestion { moves: Vec<TbpMove> },
+    //Anything newer than this frontend knows about
+    #[serde(other)]
+    Unknown,
+}
+
+//Only the standard set is ever played over TBP, launch refuses any other
+pub fn piece_name(kind: u8) -> String {
+    PIECE_NAMES[kind as usize % PIECE_NAMES.len()].to_string()
+}
+
+pub fn piece_kind(name: &str) -> Option<u8> {
+    PIECE_NAMES.iter().position(|n| *n == name).map(|i| i as u8)
+}
+
+//Board cells a location covers, sorted the same way as Tetromino::cells
+pub fn location_cells(location: &Location) -> Option<Vec<(u8, u8)>> {
+
+    let kind = piece_kind(&location.kind)?;
+    let orientation = ORIENTATIONS.iter().position(|o| *o == location.orientation)?;
+    let mut cells = Vec::new();
+    for (dx, dy) in NORTH_CELLS[kind as usize].iter() {
+        let (dx, dy) = match orientation {
+            0 => (*dx, *dy),
+            1 => (*dy, -*dx),
+            2 => (-*dx, -*dy),
+            _ => (-*dy, *dx),
+        };
+        let column = location.x + dx;
+        let row = location.y + dy;
+        let board_x = 10 - column;
+        let board_y = row + 1;
+        if !(1..=10).contains(&board_x) || board_y < 1 {
+            return None;
+        }
+        cells.push((board_x as u8, board_y as u8));
+    }
+    cells.sort();
+    Some(cells)
+}
+
+fn board_message(game: &game::Game) -> Vec<Vec<Option<String>>> {
+
+    let mut rows = Vec::new();
+    for row in 0..TBP_BOARD_HEIGHT {
+        let mut cells = Vec::new();
+        for column in 0..10 {
+            let cell = if row < bot::FIELD_HEIGHT { game.board().cell(10 - column, row + 1) } else { 0 };
+            cells.push(match cell {
+                0 => None,
+                1..=7 => Some(piece_name(cell - 1)),
+                _ => Some("G".to_string()),
+            });
+        }
+        rows.push(cells);
+    }
+    rows
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    WaitingInfo,
+    WaitingReady,
+    Starting,
+    Thinking,
+    Playing,
+    Failed,
+}
+
+pub struct TbpBot {
+    child: Child,
+    stdin: ChildStdin,
+    messages: Receiver<BotMessage>,
+    state: State,
+    pub name: String,
+    plan: VecDeque<key_bindings::Action>,
+    placed_at: u32,
+    //What the bot has been told, checked against the game after every piece
+    queue: Vec<u8>,
+    hold: Option<u8>,
+    expected: bot::Field,
+    resync: bool,
+}
+
+impl TbpBot {
+
+    //The command is split on spaces, the first part is the program
+    pub fn launch(command: &str) -> Result<TbpBot> {
+
+        if !piece_set::active().is_standard() {
+            bail!("TBP bots only know the seven standard tetrominoes, not this piece set");
+        }
+
+        let mut parts = command.split_whitespace();
+        let program = parts.next().context("No bot command given")?;
+        let mut child = Command::new(program)
+            .args(parts)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Unable to start bot {}", program))?;
+
+        let stdin = child.stdin.take().context("Bot has no stdin")?;
+        let stdout = child.stdout.take().context("Bot has no stdout")?;
+
+        //Read on a thread so a slow bot never holds up a frame
+        let (sender, messages) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let line = match line {
+                    Ok(l) => l,
+                    Err(_) => break,
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str::<BotMessage>(&line) {
+                    Ok(m) => {
+                        if sender.send(m).is_err() {
+                            break;
+                        }
+                    },
+                    Err(e) => println!("Bad message from bot: {} ({})", line, e),
+                }
+            }
+        });
+
+        Ok(TbpBot {
+            child,
+            stdin,
+            messages,
+            state: State::WaitingInfo,
+            name: program.to_string(),
+            plan: VecDeque::new(),
+            placed_at: 0,
+            queue: Vec::new(),
+            hold: None,
+            expected: bot::Field { cells: [[false; bot::FIELD_WIDTH]; bot::FIELD_HEIGHT] },
+            resync: false,
+        })
+    }
+
+    fn send(&mut self, message: &FrontendMessage) {
+
+        let line = match serde_json::to_string(message) {
+            Ok(l) => l,
+            Err(e) => {
+                println!("Couldn't encode bot message: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = writeln!(self.stdin, "{}", line).and_then(|_| self.stdin.flush()) {
+            println!("Lost bot {}: {}", self.name, e);
+            self.state = State::Failed;
+        }
+    }
+
+    pub fn failed(&self) -> bool {
+        self.state == State::Failed
+    }
+
+    pub fn update(&mut self, game: &mut game::Game) {
+
+        loop {
+            let message = match self.messages.try_recv() {
+                Ok(m) => m,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    if self.state != State::Failed {
+                        println!("Bot {} exited", self.name);
+                        self.state = State::Failed;
+                    }
+                    break;
+                },
+            };
+            match (self.state, message) {
+                (State::WaitingInfo, BotMessage::Info { name, version, author, .. }) => {
+                    println!("Bot {} {} by {}", name, version, author);
+                    self.name = name;
+                    self.send(&FrontendMessage::Rules);
+                    self.state = State::WaitingReady;
+                },
+                (State::WaitingReady, BotMessage::Ready) => self.state = State::Starting,
+                (_, BotMessage::Error { reason }) => {
+                    println!("Bot {} refused to play: {}", self.name, reason);
+                    self.state = State::Failed;
+                },
+                (State::Thinking, BotMessage::Suggestion { moves }) => self.play(game, &moves),
+                _ => (),
+            }
+        }
+
+        if game.topped_out {
+            return;
+        }
+
+        match self.state {
+            State::Starting if !game.board().did_zero => self.start(game),
+            State::Playing => {
+                if !self.plan.is_empty() {
+                    if let Some(action) = bot::next_planned_action(&mut self.plan, game) {
+                        game.handle_action(action);
+                    }
+                }
+                else if game.pieces_placed() != self.placed_at && !game.board().did_zero {
+                    self.next_piece(game);
+                }
+            },
+            _ => (),
+        }
+    }
+
+    fn game_queue(game: &game::Game) -> Vec<u8> {
+        let mut queue = vec![game.piece().kind];
+        queue.extend_from_slice(game.next_pieces());
+        queue
+    }
+
+    fn start(&mut self, game: &game::Game) {
+
+        self.queue = TbpBot::game_queue(game);
+        self.hold = game.hold_piece();
+        self.expected = bot::Field::from_board(game.board());
+        self.resync = false;
+
+        let message = FrontendMessage::Start {
+            hold: self.hold.map(piece_name),
+            queue: self.queue.iter().map(|k| piece_name(*k)).collect(),
+            combo: 0,
+            back_to_back: false,
+            board: board_message(game),
+        };
+        self.send(&message);
+        self.send(&FrontendMessage::Suggest);
+        self.state = State::Thinking;
+    }
+
+    //After a piece locks, tell the bot about the pieces that came into view, or start over
+    //if the game went somewhere the bot didn't expect, like garbage coming in
+    fn next_piece(&mut self, game: &game::Game) {
+
+        let game_queue = TbpBot::game_queue(game);
+        let in_sync = !self.resync
+            && self.hold == game.hold_piece()
+            && game_queue.starts_with(&self.queue)
+            && self.expected == bot::Field::from_board(game.board());
+
+        if !in_sync {
+            self.send(&FrontendMessage::Stop);
+            self.start(game);
+            return;
+        }
+
+        for kind in game_queue[self.queue.len()..].iter().copied() {
+            self.send(&FrontendMessage::NewPiece { piece: piece_name(kind) });
+            self.queue.push(kind);
+        }
+        self.send(&FrontendMessage::Suggest);
+        self.state = State::Thinking;
+    }
+
+    //Which piece a location is for and whether holding is needed to get it
+    fn piece_for(game: &game::Game, kind: u8) -> Option<(tetromino::Tetromino, bool)> {
+        if game.piece().kind == kind {
+            return Some((game.piece().clone(), false));
+        }
+        let hold_kind = game.hold_piece().unwrap_or(game.next_pieces()[0]);
+        if game.can_hold() && hold_kind == kind {
+            return Some((tetromino::Tetromino::new_piece(kind), true));
+        }
+        None
+    }
+
+    fn find_move(game: &game::Game, mv: &TbpMove) -> Option<(movegen::Move, bool)> {
+        let kind = piece_kind(&mv.location.kind)?;
+        let cells = location_cells(&mv.location)?;
+        let (piece, hold) = TbpBot::piece_for(game, kind)?;
+        movegen::reachable_placements(&piece, game.board()).into_iter().find(|m| m.piece.cells() == cells).map(|m| (m, hold))
+    }
+
+    fn play(&mut self, game: &game::Game, moves: &[TbpMove]) {
+
+        self.placed_at = game.pieces_placed();
+        self.state = State::Playing;
+
+        let found = moves.iter().find_map(|mv| TbpBot::find_move(game, mv).map(|(m, hold)| (mv.clone(), m, hold)));
+        let (mv, m, hold) = match found {
+            Some(f) => f,
+            None => {
+                //Keep the game going with our own pick and start the bot over on the next piece
+                println!("Bot {} suggested no reachable move, playing one for it", self.name);
+                self.plan = bot::best_placement(game, &bot::BotWeights::default_weights()).map(|p| p.path.into_iter().collect()).unwrap_or_default();
+                self.resync = true;
+                return;
+            }
+        };
+
+        if hold {
+            self.plan.push_back(key_bindings::Action::Hold);
+            if self.hold.is_none() {
+                self.hold = Some(self.queue.remove(0));
+            }
+            else {
+                self.hold = Some(self.queue[0]);
+            }
+        }
+        self.queue.remove(0);
+        self.plan.extend(m.path.iter().copied());
+        self.expected.place(&m.piece);
+        self.expected.clear_lines();
+
+        self.send(&FrontendMessage::Play { mv });
+    }
+}
+
+impl Drop for TbpBot {
+    fn drop(&mut self) {
+        if self.state != State::Failed {
+            self.send(&FrontendMessage::Quit);
+        }
+        //Give it a moment to exit on its own before it's killed
+        for _ in 0..50 {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tetris_board;
+    use crate::goals;
+
+    #[test]
+    fn piece_names() {
+        for kind in 0..7 {
+            assert_eq!(piece_kind(&piece_name(kind)), Some(kind));
+        }
+        assert_eq!(piece_kind("G"), None);
+    }
+
+    #[test]
+    fn locations_are_flipped_onto_the_board() {
+        let t = Location { kind: "T".to_string(), orientation: "north".to_string(), x: 4, y: 0 };
+        assert_eq!(location_cells(&t), Some(vec![(5, 1), (6, 1), (6, 2), (7, 1)]));
+        let off_board = Location { kind: "I".to_string(), orientation: "north".to_string(), x: 8, y: 0 };
+        assert_eq!(location_cells(&off_board), None);
+        let unknown = Location { kind: "T".to_string(), orientation: "up".to_string(), x: 4, y: 0 };
+        assert_eq!(location_cells(&unknown), None);
+    }
+
+    #[test]
+    fn every_drop_has_a_location() {
+        let board = tetris_board::TetrisBoard::new();
+        for kind in 0..7u8 {
+            for m in movegen::reachable_placements(&tetromino::Tetromino::new_piece(kind), &board) {
+                let found = ORIENTATIONS.iter().any(|o| (-2..12).any(|x| (0..4).any(|y| {
+                    location_cells(&Location { kind: piece_name(kind), orientation: o.to_string(), x, y }) == Some(m.piece.cells())
+                })));
+                assert!(found, "{} at {:?}", piece_name(kind), m.piece.cells());
+            }
+        }
+    }
+
+    #[test]
+    fn messages_match_the_spec() {
+        assert_eq!(serde_json::to_string(&FrontendMessage::Suggest).unwrap(), r#"{"type":"suggest"}"#);
+        let play = FrontendMessage::Play { mv: TbpMove { location: Location { kind: "O".to_string(), orientation: "north".to_string(), x: 4, y: 0 }, spin: "none".to_string() } };
+        let value: serde_json::Value = serde_json::to_value(&play).unwrap();
+        assert_eq!(value["type"], "play");
+        assert_eq!(value["move"]["location"]["type"], "O");
+
+        match serde_json::from_str(r#"{"type":"info","name":"Bot","version":"1","author":"me"}"#).unwrap() {
+            BotMessage::Info { name, features, .. } => {
+                assert_eq!(name, "Bot");
+                assert!(features.is_empty());
+            },
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(serde_json::from_str(r#"{"type":"something_new"}"#).unwrap(), BotMessage::Unknown));
+        match serde_json::from_str(r#"{"type":"suggestion","moves":[{"location":{"type":"I","orientation":"east","x":0,"y":1},"spin":"none"}]}"#).unwrap() {
+            BotMessage::Suggestion { moves } => assert_eq!(moves[0].location.orientation, "east"),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn board_is_sent_bottom_up() {
+        let mut game = game::Game::new();
+        game.set_goal(goals::Goal::Dig);
+        let rows = board_message(&game);
+        assert_eq!(rows.len(), TBP_BOARD_HEIGHT);
+        for row in rows[..goals::DIG_ROWS as usize].iter() {
+            assert_eq!(row.iter().filter(|c| c.is_none()).count(), 1);
+            assert!(row.iter().flatten().all(|c| c == "G"));
+        }
+        assert!(rows[goals::DIG_ROWS as usize..].iter().all(|row| row.iter().all(|c| c.is_none())));
+    }
+}
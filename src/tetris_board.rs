@@ -1,10 +1,12 @@
 use crate::r_backend;
 use crate::tetromino;
 use crate::game_options;
+use crate::skins;
 
 use std::iter::*;
 use std::collections::BTreeSet;
 
+#[derive(Clone, Hash)]
 pub struct TetrisBoard {
     board: [u8; game_options::BOARD_WIDTH * game_options::BOARD_HEIGHT],
     pub did_zero: bool,
@@ -27,28 +29,37 @@ impl TetrisBoard {
         TetrisBoard { board, did_zero, empty_lines }
     }
 
-    pub fn render(&self, r: &mut r_backend::Renderer) {
+    pub fn render(&self, r: &mut r_backend::Renderer, origin: [f32; 2], scale: f32) {
 
         for x in 0..game_options::BOARD_WIDTH {
             for y in 0..game_options::BOARD_HEIGHT {
                 if self.board[y * game_options::BOARD_WIDTH + x] > 0 {
-                    r.render_sprite_array([x as f32 * -game_options::SCALE, y as f32 * game_options::SCALE], [1.0 * game_options::SCALE, 1.0 * game_options::SCALE], -1.0, "default_pieces".to_string(), self.board[y * game_options::BOARD_WIDTH + x] as i32 - 1);
+                    r.render_sprite_array([origin[0] + x as f32 * -scale, origin[1] + y as f32 * scale], [1.0 * scale, 1.0 * scale], -1.0, skins::SPRITE_ARRAY.to_string(), self.board[y * game_options::BOARD_WIDTH + x] as i32 - 1);
                 }
             }
         }
     }
 
+    pub fn cell(&self, x: usize, y: usize) -> u8 {
+        self.board[y * game_options::BOARD_WIDTH + x]
+    }
+
     pub fn check_piece_fits(&self, piece: &tetromino::Tetromino) -> bool {
 
         for x in 0..piece.rotation_constant {
             for y in 0..piece.rotation_constant {
-                let board_x = (piece.x - x) as usize;
-                let board_y = (piece.y - y) as usize;
+                let board_x = piece.x.wrapping_sub(x) as usize;
+                let board_y = piece.y.wrapping_sub(y) as usize;
                 let piece_index = y as usize * piece.rotation_constant as usize + x as usize;
                 let board_index = board_y * game_options::BOARD_WIDTH as usize + board_x;
-                if board_index >= game_options::BOARD_WIDTH as usize * game_options::BOARD_HEIGHT as usize && piece.piece_data[piece_index] > 0 {
+                //Empty cells of the piece's grid can hang off the board, filled ones can't
+                let off_board = board_x >= game_options::BOARD_WIDTH || board_index >= game_options::BOARD_WIDTH * game_options::BOARD_HEIGHT;
+                if off_board && piece.piece_data[piece_index] > 0 {
                     return false;
                 }
+                if off_board {
+                    continue;
+                }
                 if piece.piece_data[piece_index] > 0 && self.board[board_index] > 0 {
                     return false;
                 }
@@ -62,8 +73,8 @@ impl TetrisBoard {
 
         for x in 0..piece.rotation_constant {
             for y in 0..piece.rotation_constant {
-                let board_x = (piece.x - x) as usize;
-                let board_y = (piece.y - y) as usize;
+                let board_x = piece.x.wrapping_sub(x) as usize;
+                let board_y = piece.y.wrapping_sub(y) as usize;
                 if piece.piece_data[y as usize * piece.rotation_constant as usize + x as usize] > 0 {
 
                     self.board[board_y * game_options::BOARD_WIDTH as usize + board_x] = piece.piece_data[y as usize * piece.rotation_constant as usize + x as usize];
@@ -72,15 +83,22 @@ impl TetrisBoard {
         }
     }
 
+    //Puts back the cells a piece filled, for looking at the board as it was before it locked
+    pub fn remove_piece_from_board(&mut self, piece: &tetromino::Tetromino) {
+        for (x, y) in piece.cells() {
+            self.board[y as usize * game_options::BOARD_WIDTH + x as usize] = 0;
+        }
+    }
+
     pub fn check_line(&mut self, rotation_constant: u8, piece_y: u8) {
 
         for y in 0..rotation_constant {
             let mut should_zero = true;
             for x in 1..(game_options::BOARD_WIDTH - 1) {
             
-                let board_y = (piece_y - y) as usize;
+                let board_y = piece_y.wrapping_sub(y) as usize;
                 
-                if piece_y < y || board_y == 0 || board_y == game_options::BOARD_HEIGHT - 1 {
+                if piece_y < y || board_y == 0 || board_y >= game_options::BOARD_HEIGHT - 1 {
                     continue;
                 }
 
@@ -96,9 +114,9 @@ impl TetrisBoard {
                 self.did_zero = true;
                 for x in 1..(game_options::BOARD_WIDTH - 1) {
 
-                    let board_y = (piece_y - y) as usize;
+                    let board_y = piece_y.wrapping_sub(y) as usize;
 
-                    if piece_y < y || board_y == 0 || board_y == game_options::BOARD_HEIGHT - 1 {
+                    if piece_y < y || board_y == 0 || board_y >= game_options::BOARD_HEIGHT - 1 {
                         continue;
                     }
 
@@ -110,8 +128,29 @@ impl TetrisBoard {
         }
     }
 
-    pub fn clear_lines(&mut self) {
+    //Rows from the floor up to the highest filled cell, not counting the walls
+    pub fn stack_height(&self) -> usize {
+        for y in (1..(game_options::BOARD_HEIGHT - 1)).rev() {
+            if (1..(game_options::BOARD_WIDTH - 1)).any(|x| self.cell(x, y) > 0) {
+                return y;
+            }
+        }
+        0
+    }
+
+    //Rows with any grey garbage left in them
+    pub fn garbage_rows(&self) -> usize {
+        (1..(game_options::BOARD_HEIGHT - 1)).filter(|y| (1..(game_options::BOARD_WIDTH - 1)).any(|x| self.cell(x, *y) == 8)).count()
+    }
 
+    //Full rows waiting for clear_lines, bottom first
+    pub fn rows_to_clear(&self) -> Vec<usize> {
+        self.empty_lines.iter().copied().collect()
+    }
+
+    pub fn clear_lines(&mut self) -> usize {
+
+        let cleared = self.empty_lines.len();
         if self.did_zero {
             for i in self.empty_lines.iter().rev() {
                 for x in 1..(game_options::BOARD_WIDTH - 1) {
@@ -124,5 +163,27 @@ impl TetrisBoard {
             }
             self.empty_lines.clear();
         }
+        cleared
+    }
+
+    //Pushes the stack up and fills the bottom rows with grey, leaving one hole column open.
+    //False if that pushed filled cells off the top, which tops the player out.
+    pub fn add_garbage(&mut self, lines: usize, hole: usize) -> bool {
+
+        let top = game_options::BOARD_HEIGHT - 2;
+        let lines = lines.min(top);
+        let kept = ((top + 1 - lines)..=top).all(|y| (1..(game_options::BOARD_WIDTH - 1)).all(|x| self.cell(x, y) == 0));
+        for y in (1..(game_options::BOARD_HEIGHT - 1)).rev() {
+            for x in 1..(game_options::BOARD_WIDTH - 1) {
+                let board_index = y * game_options::BOARD_WIDTH + x;
+                if y > lines {
+                    self.board[board_index] = self.board[(y - lines) * game_options::BOARD_WIDTH + x];
+                }
+                else {
+                    self.board[board_index] = if x == hole { 0 } else { 8 };
+                }
+            }
+        }
+        kept
     }
 }
\ No newline at end of file
@@ -1,72 +1,8 @@
 use crate::r_backend;
 use crate::game_options;
 use crate::tetris_board;
-
-const CYAN_DATA: [u8; 25] = [
-    0, 0, 0, 0, 0,
-    0, 0, 0, 0, 0,
-    0, 1, 1, 1, 1,
-    0, 0, 0, 0, 0,
-    0, 0, 0, 0, 0
-];
-
-const BLUE_DATA: [u8; 9] = [
-    2, 0, 0,
-    2, 2, 2,
-    0, 0, 0
-];
-
-const ORANGE_DATA: [u8; 9] = [
-    0, 0, 3,
-    3, 3, 3,
-    0, 0, 0
-];
-
-const YELLOW_DATA: [u8; 9] = [
-    0, 4, 4,
-    0, 4, 4,
-    0, 0, 0
-];
-
-const GREEN_DATA: [u8; 9] = [
-    0, 5, 5,
-    5, 5, 0,
-    0, 0, 0
-];
-
-const PURPLE_DATA: [u8; 9] = [
-    0, 6, 0,
-    6, 6, 6,
-    0, 0, 0
-];
-
-const RED_DATA: [u8; 9] = [
-    7, 7, 0,
-    0, 7, 7,
-    0, 0, 0
-];
-
-//https://tetris.wiki/Super_Rotation_System#Wall_Kicks invert X
-const OTHER_OFFSETS: [i8; 40] = [
-    0, 0,   0, 0,    0, 0,  0, 0,   0, 0,
-    0, 0,   -1, 0,   -1, -1,  0, 2,   -1, 2,
-    0, 0,   0, 0,   0, 0,   0, 0,   0, 0,
-    0, 0,   1, 0,  1, -1,   0, 2,   1, 2,
-];
-
-const CYAN_OFFSETS: [i8; 40] = [
-    0, 0,   1, 0,    -2, 0,  1, 0,   -2, 0,
-    1, 0,  0, 0,    0, 0,  0, 1,    0, -2,
-    1, 1,  -1, 1,   2, 1,  -1, 0,   2, 0,
-    0, 1,   0, 1,   0, 1,   0, -1,  0, 2
-];
-
-const YELLOW_OFFSETS: [i8; 8] = [
-    0, 0,
-    0, -1,
-    1, -1,
-    1, 0
-];
+use crate::piece_set;
+use crate::skins;
 
 /*
 0 1 2
@@ -74,8 +10,9 @@ const YELLOW_OFFSETS: [i8; 8] = [
 6 7 8
 */
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Hash)]
 pub struct Tetromino {
+    pub kind: u8,
     pub piece_data: Vec<u8>,
     static_piece_data: Vec<u8>,
     piece_offsets: Vec<i8>,
@@ -90,16 +27,13 @@ pub struct Tetromino {
 
 impl Tetromino {
 
+    //piece indexes the active piece set
     pub fn new_piece(piece: u8) -> Tetromino {
-        let (x, y, piece_data, piece_offsets) = match piece {
-            0 => (8, 22, CYAN_DATA.to_vec(), CYAN_OFFSETS.to_vec()),
-            1 => (7, 20, BLUE_DATA.to_vec(), OTHER_OFFSETS.to_vec()),
-            2 => (7, 20, ORANGE_DATA.to_vec(), OTHER_OFFSETS.to_vec()),
-            3 => (7, 20, YELLOW_DATA.to_vec(), YELLOW_OFFSETS.to_vec()),
-            4 => (7, 20, GREEN_DATA.to_vec(), OTHER_OFFSETS.to_vec()),
-            5 => (7, 20, PURPLE_DATA.to_vec(), OTHER_OFFSETS.to_vec()),
-            _ => (7, 20, RED_DATA.to_vec(), OTHER_OFFSETS.to_vec()),
-        };
+        let set = piece_set::active();
+        let def = set.piece(piece);
+        let (x, y) = def.spawn;
+        let piece_data = def.data.clone();
+        let piece_offsets = def.kicks.clone();
         let static_piece_data = piece_data.clone();
         let piece_data_len = piece_data.len() as i32;
         let rotation_constant = (piece_data_len as f32).sqrt() as u8;
@@ -107,7 +41,8 @@ impl Tetromino {
         let rotation_180 = (piece_data_len - 1) as u8;
         let rotation_270 = (rotation_constant - 1);
         let rotation = 0;
-        Tetromino { piece_data, static_piece_data, piece_offsets, rotation_constant, rotation_90, rotation_180, rotation_270, rotation, x, y }
+        let kind = piece % set.len() as u8;
+        Tetromino { kind, piece_data, static_piece_data, piece_offsets, rotation_constant, rotation_90, rotation_180, rotation_270, rotation, x, y }
     }
 
     pub fn get_ghost(&mut self, board: &tetris_board::TetrisBoard) -> (u8, u8) {
@@ -165,7 +100,8 @@ impl Tetromino {
         }
     }
 
-    pub fn rotate_piece(&mut self, rotate: i8, board: &tetris_board::TetrisBoard) {
+    //Which kick test it fitted on, None if it couldn't turn
+    pub fn rotate_piece(&mut self, rotate: i8, board: &tetris_board::TetrisBoard) -> Option<usize> {
         let mut temp_rotate = self.rotation as i8 + rotate;
         let start_data = self.piece_data.clone();
         if temp_rotate < 0 {
@@ -196,14 +132,11 @@ impl Tetromino {
 
         let o_x = self.x;
         let o_y = self.y;
-        let mut cant_fit = true;
+        let mut kick = None;
 
-        let mut num_to_check = 5;
-        let mut offset: i8 = 10;
-        if self.piece_offsets.len() < 30 {
-            num_to_check = 1;
-            offset = 2;
-        }
+        //Kick tables hold the same number of tests for each of the four rotations
+        let num_to_check = self.piece_offsets.len() / 8;
+        let offset = (num_to_check * 2) as i8;
 
         for i in 0..num_to_check {
 
@@ -212,7 +145,7 @@ impl Tetromino {
 
             //println!("{} {}", self.piece_offsets[(self.rotation * 2) as usize] - self.piece_offsets[(temp_rotate * 2) as usize], self.piece_offsets[(self.rotation * 2) as usize + 1] - self.piece_offsets[(temp_rotate * 2) as usize + 1]);
             if board.check_piece_fits(&self) {
-                cant_fit = false;
+                kick = Some(i);
                 self.rotation = temp_rotate as u8;
                 break;
             }
@@ -230,18 +163,36 @@ impl Tetromino {
             self.rotation = temp_rotate as u8;
         }*/
 
-        if cant_fit {
+        if kick.is_none() {
             self.piece_data = start_data;
             self.x = o_x;
             self.y = o_y;
         }
+        kick
+    }
+
+    pub fn rotation(&self) -> u8 {
+        self.rotation
+    }
+
+    //Board positions of the filled cells, in the same order every time for a given placement
+    pub fn cells(&self) -> Vec<(u8, u8)> {
+        let rc = self.rotation_constant as usize;
+        let mut cells = Vec::new();
+        for i in 0..self.piece_data.len() {
+            if self.piece_data[i] > 0 {
+                cells.push((self.x.wrapping_sub((i % rc) as u8), self.y.wrapping_sub((i / rc) as u8)));
+            }
+        }
+        cells.sort();
+        cells
     }
 
     pub fn add_piece_to_board(&self, board: &mut tetris_board::TetrisBoard) {
         board.add_piece_to_board(&self);
     }
 
-    pub fn render(&mut self, r: &mut r_backend::Renderer, b: &tetris_board::TetrisBoard) {
+    pub fn render(&mut self, r: &mut r_backend::Renderer, b: &tetris_board::TetrisBoard, origin: [f32; 2], scale: f32) {
         //println!("{} {}", self.x, self.y);
         for i in 0..self.piece_data.len() {
             if self.piece_data[i] > 0 {
@@ -249,7 +200,7 @@ impl Tetromino {
                 let mut i_y: f32 = (i as u8 / self.rotation_constant) as f32;
                 i_y = self.y as f32 - i_y;
                 i_x = self.x as f32 - i_x;
-                r.render_sprite_array([i_x * -game_options::SCALE, i_y * game_options::SCALE], [1.0 * game_options::SCALE, 1.0 * game_options::SCALE], -1.0, "default_pieces".to_string(), self.piece_data[i] as i32 - 1);
+                r.render_sprite_array([origin[0] + i_x * -scale, origin[1] + i_y * scale], [1.0 * scale, 1.0 * scale], -1.0, skins::SPRITE_ARRAY.to_string(), self.piece_data[i] as i32 - 1);
             }
         }
 
@@ -260,12 +211,23 @@ impl Tetromino {
                 let mut i_y: f32 = (i as u8 / self.rotation_constant) as f32;
                 i_y = ghost_y as f32 - i_y;
                 i_x = ghost_x as f32 - i_x;
-                r.render_sprite_transparent([i_x * -game_options::SCALE, i_y * game_options::SCALE], [1.0 * game_options::SCALE, 1.0 * game_options::SCALE], -1.0, "default_pieces".to_string(), self.piece_data[i] as i32 - 1, 0.3);
+                r.render_sprite_transparent([origin[0] + i_x * -scale, origin[1] + i_y * scale], [1.0 * scale, 1.0 * scale], -1.0, skins::SPRITE_ARRAY.to_string(), self.piece_data[i] as i32 - 1, 0.3);
+            }
+        }
+    }
+
+    //Faint white copy of the piece where it is, drawn like the ghost for hints
+    pub fn render_outline(&self, r: &mut r_backend::Renderer, origin: [f32; 2], scale: f32) {
+        for i in 0..self.piece_data.len() {
+            if self.piece_data[i] > 0 {
+                let i_x = self.x as f32 - (i as u8 % self.rotation_constant) as f32;
+                let i_y = self.y as f32 - (i as u8 / self.rotation_constant) as f32;
+                r.render_sprite_transparent([origin[0] + i_x * -scale, origin[1] + i_y * scale], [1.0 * scale, 1.0 * scale], -1.0, skins::SPRITE_ARRAY.to_string(), 8, 0.45);
             }
         }
     }
 
-    pub fn render_force_position(&self, r: &mut r_backend::Renderer, x: f32, y: f32) {
+    pub fn render_force_position(&self, r: &mut r_backend::Renderer, x: f32, y: f32, origin: [f32; 2], scale: f32) {
         //println!("{} {}", self.x, self.y);
         for i in 0..self.piece_data.len() {
             if self.piece_data[i] > 0 {
@@ -273,7 +235,7 @@ impl Tetromino {
                 let mut i_y: f32 = (i as u8 / self.rotation_constant) as f32;
                 i_y = y as f32 - i_y;
                 i_x = x as f32 - i_x;
-                r.render_sprite_array([i_x * -game_options::SCALE, i_y * game_options::SCALE], [1.0 * game_options::SCALE, 1.0 * game_options::SCALE], -1.0, "default_pieces".to_string(), self.piece_data[i] as i32 - 1);
+                r.render_sprite_array([origin[0] + i_x * -scale, origin[1] + i_y * scale], [1.0 * scale, 1.0 * scale], -1.0, skins::SPRITE_ARRAY.to_string(), self.piece_data[i] as i32 - 1);
             }
         }
     }
@@ -0,0 +1,372 @@
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/*
+Bitmap fonts for the renderer. Two kinds of file load:
+.png    a fixed grid of 16 by 6 cells holding ASCII 32 to 126 in order, every glyph as wide as its cell
+.fnt    a BMFont text descriptor, https://www.angelcode.com/products/bmfont/doc/file_format.html,
+        with its single page image next to it
+Each glyph is cut out into its own layer of a sprite array, so a whole string goes out in the
+same batch as any other sprites. Metrics are kept in pixels of the font image and scaled so a
+line is as tall as the size asked for.
+*/
+
+pub const DEFAULT_FONT: &str = "./res/fonts/mono.png";
+pub const FONT: &str = "font";
+const GRID_COLUMNS: u32 = 16;
+const GRID_ROWS: u32 = 6;
+const GRID_FIRST: u32 = 32;
+//The most array layers every backend is guaranteed to allow
+const MAX_GLYPHS: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Align {
+    Left,
+    Centre,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Glyph {
+    pub layer: i32,
+    //Pixel size of the glyph, which sits in the top left of its layer
+    pub width: f32,
+    pub height: f32,
+    //From the pen position to the glyph's top left, y down from the top of the line
+    pub x_offset: f32,
+    pub y_offset: f32,
+    pub advance: f32,
+}
+
+//One glyph quad, position is the bottom left like the sprite calls
+#[derive(Debug, Clone, Copy)]
+pub struct Quad {
+    pub pos: [f32; 2],
+    pub size: [f32; 2],
+    pub layer: i32,
+    //How much of the layer the glyph covers
+    pub uv: [f32; 2],
+}
+
+#[derive(Debug, Clone)]
+pub struct Font {
+    pub line_height: f32,
+    //Size of every layer
+    pub cell: (u32, u32),
+    pub glyphs: HashMap<char, Glyph>,
+    pub kerning: HashMap<(char, char), f32>,
+}
+
+impl Font {
+
+    //The font and the layer images to load as its sprite array
+    pub fn load(path: &str) -> Result<(Font, Vec<image::RgbaImage>)> {
+        match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("png") => Font::load_grid(path),
+            Some("fnt") => Font::load_bmfont(path),
+            _ => bail!("Font {} should be a .png grid or a .fnt BMFont file", path),
+        }
+    }
+
+    pub fn load_grid(path: &str) -> Result<(Font, Vec<image::RgbaImage>)> {
+
+        let atlas = image::open(path).with_context(|| format!("Couldn't read font {}", path))?.to_rgba8();
+        if atlas.width() % GRID_COLUMNS != 0 || atlas.height() % GRID_ROWS != 0 {
+            bail!("Font {} is {}x{}, it has to split into {} by {} cells", path, atlas.width(), atlas.height(), GRID_COLUMNS, GRID_ROWS);
+        }
+        let cell = (atlas.width() / GRID_COLUMNS, atlas.height() / GRID_ROWS);
+
+        let mut glyphs = HashMap::new();
+        let mut layers = Vec::new();
+        for i in 0..(GRID_COLUMNS * GRID_ROWS - 1) {
+            let c = match std::char::from_u32(GRID_FIRST + i) {
+                Some(c) => c,
+                None => continue,
+            };
+            let x = (i % GRID_COLUMNS) * cell.0;
+            let y = (i / GRID_COLUMNS) * cell.1;
+            layers.push(image::imageops::crop_imm(&atlas, x, y, cell.0, cell.1).to_image());
+            glyphs.insert(c, Glyph {
+                layer: layers.len() as i32 - 1,
+                width: cell.0 as f32,
+                height: cell.1 as f32,
+                x_offset: 0.0,
+                y_offset: 0.0,
+                advance: cell.0 as f32,
+            });
+        }
+
+        Ok((Font { line_height: cell.1 as f32, cell, glyphs, kerning: HashMap::new() }, layers))
+    }
+
+    pub fn load_bmfont(path: &str) -> Result<(Font, Vec<image::RgbaImage>)> {
+
+        let contents = fs::read_to_string(path).with_context(|| format!("Couldn't read font {}", path))?;
+
+        let mut line_height = None;
+        let mut page = None;
+        let mut chars = Vec::new();
+        let mut kerning = HashMap::new();
+        for line in contents.lines() {
+            let mut words = line.split_whitespace();
+            let tag = words.next().unwrap_or("");
+            let values = attributes(line);
+            let number = |key: &str| -> Result<i32> {
+                match values.get(key) {
+                    Some(v) => v.parse().with_context(|| format!("Bad {} {:?} in {}", key, v, line)),
+                    None => bail!("{} is missing {}", line, key),
+                }
+            };
+            match tag {
+                "common" => {
+                    line_height = Some(number("lineHeight")? as f32);
+                    if number("pages")? != 1 {
+                        bail!("Font {} has more than one page, only single page fonts are supported", path);
+                    }
+                },
+                "page" => page = values.get("file").cloned(),
+                "char" => chars.push((
+                    number("id")? as u32,
+                    [number("x")?, number("y")?, number("width")?, number("height")?],
+                    [number("xoffset")? as f32, number("yoffset")? as f32, number("xadvance")? as f32],
+                )),
+                "kerning" => {
+                    let first = std::char::from_u32(number("first")? as u32);
+                    let second = std::char::from_u32(number("second")? as u32);
+                    if let (Some(a), Some(b)) = (first, second) {
+                        kerning.insert((a, b), number("amount")? as f32);
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        let line_height = match line_height {
+            Some(h) => h,
+            None => bail!("Font {} has no common line", path),
+        };
+        let page = match page {
+            Some(p) => Path::new(path).parent().unwrap_or(Path::new(".")).join(p),
+            None => bail!("Font {} has no page image", path),
+        };
+        if chars.is_empty() || chars.len() > MAX_GLYPHS {
+            bail!("Font {} has {} characters, it needs between 1 and {}", path, chars.len(), MAX_GLYPHS);
+        }
+        let atlas = image::open(&page).with_context(|| format!("Couldn't read font page {}", page.display()))?.to_rgba8();
+
+        //Layers all have to be the same size, so every glyph gets a cell as big as the biggest
+        let cell_width = chars.iter().map(|(_, r, _)| r[2]).max().unwrap_or(1).max(1) as u32;
+        let cell_height = chars.iter().map(|(_, r, _)| r[3]).max().unwrap_or(1).max(1) as u32;
+
+        let mut glyphs = HashMap::new();
+        let mut layers = Vec::new();
+        for (id, rect, metrics) in chars.iter() {
+            let c = match std::char::from_u32(*id) {
+                Some(c) => c,
+                None => continue,
+            };
+            let (x, y, w, h) = (rect[0] as u32, rect[1] as u32, rect[2] as u32, rect[3] as u32);
+            if x + w > atlas.width() || y + h > atlas.height() {
+                bail!("Character {} in font {} is outside its page", id, path);
+            }
+            let mut layer = image::RgbaImage::new(cell_width, cell_height);
+            image::imageops::replace(&mut layer, &image::imageops::crop_imm(&atlas, x, y, w, h).to_image(), 0, 0);
+            layers.push(layer);
+            glyphs.insert(c, Glyph {
+                layer: layers.len() as i32 - 1,
+                width: w as f32,
+                height: h as f32,
+                x_offset: metrics[0],
+                y_offset: metrics[1],
+                advance: metrics[2],
+            });
+        }
+
+        Ok((Font { line_height, cell: (cell_width, cell_height), glyphs, kerning }, layers))
+    }
+
+    //Unknown characters fall back to ?, then to nothing
+    fn glyph(&self, c: char) -> Option<&Glyph> {
+        self.glyphs.get(&c).or_else(|| self.glyphs.get(&'?'))
+    }
+
+    fn line_width(&self, line: &str) -> f32 {
+        let mut width = 0.0;
+        let mut last = None;
+        for c in line.chars() {
+            if let Some(g) = self.glyph(c) {
+                width += g.advance + last.and_then(|l| self.kerning.get(&(l, c))).unwrap_or(&0.0);
+            }
+            last = Some(c);
+        }
+        width
+    }
+
+    //Width of the widest line when each line is size tall
+    pub fn measure(&self, text: &str, size: f32) -> f32 {
+        let k = size / self.line_height;
+        text.lines().map(|l| self.line_width(l) * k).fold(0.0, f32::max)
+    }
+
+    //Quads for the text with pos at the top of the first line, on the left, centre or right
+    pub fn layout(&self, text: &str, pos: [f32; 2], size: f32, align: Align) -> Vec<Quad> {
+
+        let k = size / self.line_height;
+        let mut quads = Vec::new();
+        for (row, line) in text.lines().enumerate() {
+            let top = pos[1] - row as f32 * size;
+            let mut pen = match align {
+                Align::Left => pos[0],
+                Align::Centre => pos[0] - self.line_width(line) * k / 2.0,
+                Align::Right => pos[0] - self.line_width(line) * k,
+            };
+            let mut last = None;
+            for c in line.chars() {
+                if let Some(g) = self.glyph(c) {
+                    pen += last.and_then(|l| self.kerning.get(&(l, c))).unwrap_or(&0.0) * k;
+                    if g.width > 0.0 && g.height > 0.0 {
+                        quads.push(Quad {
+                            pos: [pen + g.x_offset * k, top - (g.y_offset + g.height) * k],
+                            size: [g.width * k, g.height * k],
+                            layer: g.layer,
+                            uv: [g.width / self.cell.0 as f32, g.height / self.cell.1 as f32],
+                        });
+                    }
+                    pen += g.advance * k;
+                }
+                last = Some(c);
+            }
+        }
+        quads
+    }
+}
+
+//key=value pairs on a BMFont line, values may be quoted
+fn attributes(line: &str) -> HashMap<String, String> {
+
+    let mut values = HashMap::new();
+    let mut rest = line;
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].rsplit(' ').next().unwrap_or("").to_string();
+        rest = &rest[eq + 1..];
+        let value = if rest.starts_with('"') {
+            let end = rest[1..].find('"').map_or(rest.len(), |i| i + 1);
+            let v = rest[1..end].to_string();
+            rest = &rest[(end + 1).min(rest.len())..];
+            v
+        } else {
+            let end = rest.find(' ').unwrap_or(rest.len());
+            let v = rest[..end].to_string();
+            rest = &rest[end..];
+            v
+        };
+        values.insert(key, value);
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Two glyphs and a kerning pair on a 16x8 page, in a folder of their own
+    const FNT: &str = r####"info face="Test" size=8
+common lineHeight=8 base=7 scaleW=16 scaleH=8 pages=1
+page id=0 file="test.png"
+chars count=2
+char id=65 x=0 y=0 width=6 height=7 xoffset=0 yoffset=1 xadvance=7 page=0
+char id=86 x=6 y=0 width=5 height=8 xoffset=1 yoffset=0 xadvance=6 page=0
+kernings count=1
+kerning first=65 second=86 amount=-2
+"####;
+
+    fn bmfont(name: &str, fnt: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("tetris-font-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        image::RgbaImage::new(16, 8).save(dir.join("test.png")).unwrap();
+        let path = dir.join("test.fnt");
+        fs::write(&path, fnt).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn grid_fonts_cover_printable_ascii() {
+        let (font, layers) = Font::load(DEFAULT_FONT).unwrap();
+        assert_eq!(layers.len(), 95);
+        assert_eq!(font.glyphs.len(), 95);
+        assert!(font.glyphs.contains_key(&' ') && font.glyphs.contains_key(&'~'));
+        assert_eq!(font.glyphs[&'A'].advance, font.cell.0 as f32);
+        assert!(layers.iter().all(|l| l.dimensions() == font.cell));
+    }
+
+    #[test]
+    fn bmfonts_load_with_kerning() {
+        let path = bmfont("load", FNT);
+        let (font, layers) = Font::load(&path).unwrap();
+        assert_eq!(font.line_height, 8.0);
+        //Every layer is as big as the biggest glyph
+        assert_eq!(font.cell, (6, 8));
+        assert_eq!(layers.len(), 2);
+        assert_eq!(font.glyphs[&'V'].x_offset, 1.0);
+        assert_eq!(font.kerning[&('A', 'V')], -2.0);
+        fs::remove_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn bad_fonts_are_refused() {
+        assert!(Font::load("font.ttf").is_err());
+        assert!(Font::load("./res/fonts/missing.png").is_err());
+        let path = bmfont("pages", &FNT.replace("pages=1", "pages=2"));
+        assert!(Font::load(&path).unwrap_err().to_string().contains("more than one page"));
+        fs::remove_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+        let path = bmfont("outside", &FNT.replace("x=6 y=0 width=5", "x=14 y=0 width=5"));
+        assert!(Font::load(&path).unwrap_err().to_string().contains("outside its page"));
+        fs::remove_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn layout_kerns_and_aligns() {
+        let path = bmfont("layout", FNT);
+        let (font, _) = Font::load(&path).unwrap();
+        //At twice the font's own size, A is 14 wide and pulls V 4 closer
+        assert_eq!(font.measure("AV", 16.0), 22.0);
+        assert_eq!(font.measure("A\nAVA", 8.0), 18.0);
+        let quads = font.layout("AV", [100.0, 50.0], 16.0, Align::Left);
+        assert_eq!(quads.len(), 2);
+        assert_eq!(quads[0].pos, [100.0, 50.0 - 16.0]);
+        assert_eq!(quads[1].pos, [100.0 + 14.0 - 4.0 + 2.0, 50.0 - 16.0]);
+        assert_eq!(quads[1].uv, [5.0 / 6.0, 1.0]);
+        let centred = font.layout("AV", [100.0, 50.0], 16.0, Align::Centre);
+        assert_eq!(centred[0].pos[0], 100.0 - 11.0);
+        let right = font.layout("AV", [100.0, 50.0], 16.0, Align::Right);
+        assert_eq!(right[0].pos[0], 100.0 - 22.0);
+        //Each line starts size further down
+        assert_eq!(font.layout("A\nA", [0.0, 0.0], 8.0, Align::Left)[1].pos[1], -8.0 - 8.0);
+        fs::remove_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn unknown_characters_fall_back() {
+        let (font, _) = Font::load(DEFAULT_FONT).unwrap();
+        let quads = font.layout("\u{e9}", [0.0, 0.0], 10.0, Align::Left);
+        assert_eq!(quads.len(), 1);
+        assert_eq!(quads[0].layer, font.glyphs[&'?'].layer);
+        //Without a ? they take up no room at all
+        let path = bmfont("unknown", FNT);
+        let (font, _) = Font::load(&path).unwrap();
+        assert!(font.layout("B", [0.0, 0.0], 8.0, Align::Left).is_empty());
+        assert_eq!(font.measure("ABA", 8.0), 14.0);
+        fs::remove_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn attributes_can_be_quoted() {
+        let values = attributes(r#"page id=0 file="my font.png" extra=1"#);
+        assert_eq!(values["id"], "0");
+        assert_eq!(values["file"], "my font.png");
+        assert_eq!(values["extra"], "1");
+        assert!(attributes("chars").is_empty());
+    }
+}
@@ -94,37 +94,42 @@ impl Texture {
         path: &str,
     ) -> Result<Self> {
 
-        //let path_copy = path.to_path_buf();
-        let label = path;
-
-
-        let mut images: Vec<u8> = Vec::new();
-
-        let mut counter: u32 = 0;
-        let mut dimensions: (u32, u32) = (0, 0);
+        let mut images: Vec<image::RgbaImage> = Vec::new();
 
         for entry in glob(path).expect("Failed to read glob pattern") {
             match entry {
                 Ok(path) => {
                     //println!("{:?}", path.display());
                     let img = image::open(path)?;
-                    let rgba = img.to_rgba8();
-                    counter += 1;
-                    dimensions = img.dimensions();
-                    for y in 0..(dimensions.1) {
-                        for x in 0..(dimensions.0) {
-                            let pix = rgba.get_pixel(x, y);
-                            images.push(pix[0]);
-                            images.push(pix[1]);
-                            images.push(pix[2]);
-                            images.push(pix[3]);
-                        }
-                    }
+                    images.push(img.to_rgba8());
                 }
                 Err(e) => println!("{:?}", e),
             }
         }
 
+        Self::from_images(device, queue, &images, path)
+    }
+
+    //One array layer per image, they all have to be the size of the first
+    pub fn from_images(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layers: &[image::RgbaImage],
+        label: &str,
+    ) -> Result<Self> {
+
+        let mut images: Vec<u8> = Vec::new();
+
+        let counter = layers.len() as u32;
+        let dimensions = layers.first().map_or((0, 0), |l| l.dimensions());
+
+        for rgba in layers.iter() {
+            if rgba.dimensions() != dimensions {
+                bail!("{} layers are different sizes", label);
+            }
+            images.extend_from_slice(rgba.as_raw());
+        }
+
         let dimension_x = core::num::NonZeroU32::new(4 * dimensions.0);
         let dimension_y = core::num::NonZeroU32::new(dimensions.1);
 
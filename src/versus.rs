@@ -0,0 +1,250 @@
+use crate::bot;
+use crate::game;
+use crate::camera;
+use crate::game_options;
+use crate::r_backend;
+use crate::key_bindings;
+use crate::skins;
+use crate::events::GameEvent;
+
+use winit::event::*;
+
+const PLAYER_ONE_ORIGIN: [f32; 2] = [-4.6, 0.0];
+const PLAYER_TWO_ORIGIN: [f32; 2] = [3.8, 0.0];
+//Against the CPU its board is drawn smaller off to the side
+const CPU_ORIGIN: [f32; 2] = [5.0, 2.0];
+const CPU_SCALE: f32 = game_options::SCALE * 0.6;
+//Incoming garbage for each player is shown as a column of tiles between the boards
+const LOCAL_METERS: [f32; 2] = [-1.9, -0.9];
+const CPU_METERS: [f32; 2] = [-1.5, 1.5];
+
+//Both topping out on the same tick is a draw
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Winner(usize),
+    Draw,
+}
+
+#[derive(Clone)]
+pub struct Versus {
+    pub players: [game::Game; 2],
+    pub outcome: Option<Outcome>,
+    pub last_sent: [u32; 2],
+    pub announce_winner: bool,
+    meters: [f32; 2],
+    //Both players' events once garbage and top outs are dealt with, tagged with who they came from
+    events: Vec<(usize, GameEvent)>,
+}
+
+impl Versus {
+    pub fn new() -> Versus {
+        let player_one = key_bindings::KeyBindings::load(key_bindings::BINDINGS_PATH, key_bindings::KeyBindings::default_bindings());
+        let player_two = key_bindings::KeyBindings::load(key_bindings::BINDINGS_PATH_P2, key_bindings::KeyBindings::player_two_bindings());
+        Versus::new_seeded([player_one, player_two], rand::random::<u64>())
+    }
+
+    //Both players get the same seed so they see the same piece sequence
+    pub fn new_seeded(bindings: [key_bindings::KeyBindings; 2], seed: u64) -> Versus {
+        let [player_one, player_two] = bindings;
+        let player_one = game::Game::new_player(player_one, PLAYER_ONE_ORIGIN, seed);
+        let player_two = game::Game::new_player(player_two, PLAYER_TWO_ORIGIN, seed);
+        let players = [player_one, player_two];
+        let outcome = None;
+        let last_sent = [0, 0];
+        let announce_winner = true;
+        let meters = LOCAL_METERS;
+        let events = Vec::new();
+        Versus { players, outcome, last_sent, announce_winner, meters, events }
+    }
+
+    pub fn against_cpu(bindings: key_bindings::KeyBindings, difficulty: bot::Difficulty) -> Versus {
+        let mut versus = Versus::new_seeded([bindings, key_bindings::KeyBindings::player_two_bindings()], rand::random::<u64>());
+        versus.players[1].bot = Some(difficulty.bot());
+        versus.players[1].origin = CPU_ORIGIN;
+        versus.players[1].scale = CPU_SCALE;
+        versus.meters = CPU_METERS;
+        versus
+    }
+
+    pub fn input(&mut self, event: &WindowEvent) {
+
+        //Bots ignore the keyboard so a CPU can't be pushed around with the player two keys
+        for player in self.players.iter_mut() {
+            if player.bot.is_none() {
+                player.input(event);
+            }
+        }
+
+        if self.outcome.is_some() {
+            if let WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(VirtualKeyCode::Return), .. }, .. } = event {
+                self.rematch();
+            }
+        }
+    }
+
+    pub fn process_inputs(&mut self, w_di: [u32; 2]) {
+
+        if self.outcome.is_some() {
+            return;
+        }
+
+        for player in self.players.iter_mut() {
+            player.process_inputs(w_di);
+        }
+    }
+
+    pub fn update(&mut self) {
+
+        if self.outcome.is_some() {
+            return;
+        }
+
+        for player in self.players.iter_mut() {
+            player.update();
+        }
+
+        let mut sent = [0, 0];
+        let mut topped_out = [false, false];
+        for i in 0..2 {
+            for event in self.players[i].take_events() {
+                match event {
+                    GameEvent::GarbageSent { lines } => sent[i] += lines,
+                    GameEvent::TopOut => topped_out[i] = true,
+                    _ => (),
+                }
+                if self.events.len() >= game::MAX_EVENTS {
+                    self.events.remove(0);
+                }
+                self.events.push((i, event));
+            }
+        }
+        self.players[1].receive_garbage(sent[0]);
+        self.players[0].receive_garbage(sent[1]);
+        self.last_sent = sent;
+
+        self.outcome = match topped_out {
+            [true, true] => Some(Outcome::Draw),
+            [true, false] => Some(Outcome::Winner(1)),
+            [false, true] => Some(Outcome::Winner(0)),
+            [false, false] => return,
+        };
+        if self.announce_winner {
+            println!("{}", self.outcome_text());
+            for (p, player) in self.players.iter().enumerate() {
+                println!("Player {} {}", p + 1, player.stats.summary());
+                println!("Player {} {}", p + 1, player.finesse.summary());
+            }
+        }
+    }
+
+    pub fn outcome_text(&self) -> String {
+        match self.outcome {
+            Some(Outcome::Winner(w)) => format!("Player {} wins", w + 1),
+            Some(Outcome::Draw) => "Draw, both players topped out".to_string(),
+            None => String::new(),
+        }
+    }
+
+    pub fn take_events(&mut self) -> Vec<(usize, GameEvent)> {
+        std::mem::take(&mut self.events)
+    }
+
+    fn rematch(&mut self) {
+        let bindings = [self.players[0].bindings.clone(), self.players[1].bindings.clone()];
+        let mut rematch = Versus::new_seeded(bindings, rand::random::<u64>());
+        for i in 0..2 {
+            rematch.players[i].bot = self.players[i].bot.take();
+            rematch.players[i].origin = self.players[i].origin;
+            rematch.players[i].scale = self.players[i].scale;
+        }
+        rematch.meters = self.meters;
+        *self = rematch;
+    }
+
+    pub fn load_state(&mut self, saved: &Versus) {
+        for i in 0..2 {
+            self.players[i].load_state(&saved.players[i]);
+        }
+        self.outcome = saved.outcome;
+        self.last_sent = saved.last_sent;
+    }
+
+    pub fn render(&mut self, r: &mut r_backend::Renderer, camera: &mut camera::Camera) {
+
+        for player in self.players.iter_mut() {
+            player.render(r, camera);
+        }
+
+        let size = game_options::SCALE * 0.5;
+        for i in 0..2 {
+            for line in 0..self.players[i].pending_garbage().min(40) {
+                r.render_sprite_array([self.meters[i], 0.4 + line as f32 * size], [size, size], -1.0, skins::SPRITE_ARRAY.to_string(), 6);
+            }
+        }
+    }
+}
+
+impl Default for Versus {
+    fn default() -> Versus {
+        Versus::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn versus(seed: u64) -> Versus {
+        let mut v = Versus::new_seeded([key_bindings::KeyBindings::default_bindings(), key_bindings::KeyBindings::player_two_bindings()], seed);
+        v.announce_winner = false;
+        v
+    }
+
+    //More garbage than the board has room for, in as soon as the player's piece locks
+    fn bury(player: &mut game::Game) {
+        player.receive_garbage(21);
+        player.apply_action(key_bindings::Action::HardDrop);
+    }
+
+    fn play_out(v: &mut Versus) {
+        for _ in 0..30 {
+            v.update();
+        }
+    }
+
+    #[test]
+    fn same_seed_same_pieces() {
+        let v = versus(9);
+        assert_eq!(v.players[0].piece().kind, v.players[1].piece().kind);
+        assert_eq!(v.players[0].next_pieces(), v.players[1].next_pieces());
+    }
+
+    #[test]
+    fn topping_out_loses() {
+        let mut v = versus(5);
+        bury(&mut v.players[1]);
+        play_out(&mut v);
+        assert_eq!(v.outcome, Some(Outcome::Winner(0)));
+        assert_eq!(v.outcome_text(), "Player 1 wins");
+        assert!(v.take_events().contains(&(1, GameEvent::TopOut)));
+    }
+
+    #[test]
+    fn both_topping_out_together_is_a_draw() {
+        let mut v = versus(5);
+        bury(&mut v.players[0]);
+        bury(&mut v.players[1]);
+        play_out(&mut v);
+        assert_eq!(v.outcome, Some(Outcome::Draw));
+    }
+
+    #[test]
+    fn nothing_moves_once_decided() {
+        let mut v = versus(5);
+        bury(&mut v.players[1]);
+        play_out(&mut v);
+        let ticks = v.players[0].ticks();
+        play_out(&mut v);
+        assert_eq!(v.players[0].ticks(), ticks);
+    }
+}